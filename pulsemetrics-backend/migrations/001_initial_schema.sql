@@ -5,8 +5,9 @@ CREATE EXTENSION IF NOT EXISTS timescaledb;
 CREATE EXTENSION IF NOT EXISTS "uuid-ossp";
 
 -- Create events table
+-- Hypertable unique constraints must include the partitioning column
 CREATE TABLE IF NOT EXISTS events (
-    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    id UUID NOT NULL DEFAULT uuid_generate_v4(),
     time TIMESTAMPTZ NOT NULL,
     project_id VARCHAR(100) NOT NULL,
     event_type VARCHAR(50) NOT NULL,
@@ -14,7 +15,8 @@ CREATE TABLE IF NOT EXISTS events (
     user_id VARCHAR(100),
     session_id UUID,
     value DOUBLE PRECISION,
-    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id, time)
 );
 
 -- Convert to hypertable (TimescaleDB)
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS projects (
+    id VARCHAR(100) PRIMARY KEY,
+    name VARCHAR(200) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id VARCHAR(100) NOT NULL REFERENCES projects (id) ON DELETE CASCADE,
+    name VARCHAR(200) NOT NULL,
+    -- 'bearer' keys store only the SHA-256 hash of the token,
+    -- 'hmac' keys store the shared secret used to verify request signatures
+    auth_scheme VARCHAR(20) NOT NULL DEFAULT 'bearer'
+        CHECK (auth_scheme IN ('bearer', 'hmac')),
+    token_hash CHAR(64) UNIQUE,
+    signing_secret TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ,
+    CHECK (
+        (auth_scheme = 'bearer' AND token_hash IS NOT NULL)
+        OR (auth_scheme = 'hmac' AND signing_secret IS NOT NULL)
+    )
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_project
+    ON api_keys (project_id);
+
+COMMENT ON TABLE projects IS 'Projects owning events and API keys';
+
+COMMENT ON TABLE api_keys IS 'API keys authorized to ingest events for a project';
@@ -0,0 +1,4 @@
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS allowed_origins TEXT[] NOT NULL DEFAULT '{}';
+
+COMMENT ON COLUMN projects.allowed_origins IS 'Browser origins allowed to call the API with this project''s keys';
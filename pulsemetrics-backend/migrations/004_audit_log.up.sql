@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Principal subject (key id, JWT subject or 'config') and how it authenticated
+    actor VARCHAR(200) NOT NULL,
+    actor_method VARCHAR(20) NOT NULL,
+    action VARCHAR(100) NOT NULL,
+    resource_type VARCHAR(50) NOT NULL,
+    resource_id VARCHAR(200) NOT NULL,
+    project_id VARCHAR(100),
+    request_id VARCHAR(100),
+    before JSONB,
+    after JSONB
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_occurred
+    ON audit_log (occurred_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_resource
+    ON audit_log (resource_type, resource_id, id DESC);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_project
+    ON audit_log (project_id, id DESC)
+    WHERE project_id IS NOT NULL;
+
+COMMENT ON TABLE audit_log IS 'Record of administrative changes';
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS organizations (
+    id VARCHAR(100) PRIMARY KEY,
+    name VARCHAR(200) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS organization_members (
+    organization_id VARCHAR(100) NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    -- Identity provider subject (JWT `sub` claim)
+    subject VARCHAR(200) NOT NULL,
+    role VARCHAR(20) NOT NULL
+        CHECK (role IN ('owner', 'admin', 'analyst', 'viewer')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (organization_id, subject)
+);
+
+CREATE INDEX IF NOT EXISTS idx_organization_members_subject
+    ON organization_members (subject);
+
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS organization_id VARCHAR(100) REFERENCES organizations (id);
+
+CREATE INDEX IF NOT EXISTS idx_projects_organization
+    ON projects (organization_id);
+
+COMMENT ON TABLE organizations IS 'Organizations owning projects';
+
+COMMENT ON TABLE organization_members IS 'Members of an organization and their roles';
@@ -0,0 +1,4 @@
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS retention_days INTEGER CHECK (retention_days > 0);
+
+COMMENT ON COLUMN projects.retention_days IS 'Days events are kept before the retention job deletes them, NULL keeps them forever';
@@ -0,0 +1,5 @@
+SELECT remove_compression_policy('events', if_exists => TRUE);
+
+SELECT decompress_chunk(chunk, if_compressed => TRUE) FROM show_chunks('events') AS chunk;
+
+ALTER TABLE events SET (timescaledb.compress = FALSE);
@@ -0,0 +1,8 @@
+-- Compress events per project and type, newest first within a segment;
+-- the compression policy itself is applied at startup from
+-- compression.compress_after_days
+ALTER TABLE events SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'project_id, event_type',
+    timescaledb.compress_orderby = 'time DESC'
+);
@@ -0,0 +1,27 @@
+-- Data subject requests (deleting or exporting a user's events), run in the background
+CREATE TABLE IF NOT EXISTS data_jobs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    kind VARCHAR(50) NOT NULL,
+    project_id VARCHAR(100) NOT NULL REFERENCES projects (id) ON DELETE CASCADE,
+    user_id VARCHAR(100) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'running', 'completed', 'failed')),
+    -- Principal that requested the job, recorded in the audit log on completion
+    requested_by VARCHAR(200) NOT NULL,
+    requested_method VARCHAR(20) NOT NULL,
+    request_id VARCHAR(100),
+    events_affected BIGINT,
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_data_jobs_project
+    ON data_jobs (project_id, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS idx_data_jobs_unfinished
+    ON data_jobs (created_at)
+    WHERE status IN ('pending', 'running');
+
+COMMENT ON TABLE data_jobs IS 'Background deletions and exports of a user''s events';
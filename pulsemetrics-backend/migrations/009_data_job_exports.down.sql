@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idx_data_jobs_exports;
+
+ALTER TABLE data_jobs
+    DROP COLUMN IF EXISTS output_path,
+    DROP COLUMN IF EXISTS format;
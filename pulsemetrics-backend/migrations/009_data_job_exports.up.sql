@@ -0,0 +1,8 @@
+ALTER TABLE data_jobs
+    ADD COLUMN IF NOT EXISTS format VARCHAR(10),
+    -- Export file, cleared once it expires and is deleted
+    ADD COLUMN IF NOT EXISTS output_path TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_data_jobs_exports
+    ON data_jobs (completed_at)
+    WHERE output_path IS NOT NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS scrub_rules JSONB NOT NULL DEFAULT '[]';
+
+COMMENT ON COLUMN projects.scrub_rules IS 'PII scrubbing rules applied to events at ingestion, in order';
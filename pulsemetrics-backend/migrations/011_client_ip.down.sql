@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS ip_salts;
+ALTER TABLE projects DROP COLUMN IF EXISTS ip_mode;
@@ -0,0 +1,12 @@
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS ip_mode VARCHAR(10) NOT NULL DEFAULT 'drop'
+        CHECK (ip_mode IN ('drop', 'full', 'truncate', 'hash'));
+
+COMMENT ON COLUMN projects.ip_mode IS 'How the client IP of ingested events is stored: drop, full, truncate or hash';
+
+CREATE TABLE IF NOT EXISTS ip_salts (
+    period_start TIMESTAMPTZ PRIMARY KEY,
+    salt BYTEA NOT NULL
+);
+
+COMMENT ON TABLE ip_salts IS 'Salt hashing client IPs in the current rotation period, shared between instances; earlier salts are deleted';
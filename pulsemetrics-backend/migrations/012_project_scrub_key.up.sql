@@ -0,0 +1,5 @@
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS scrub_key BYTEA NOT NULL
+        DEFAULT uuid_send(gen_random_uuid()) || uuid_send(gen_random_uuid());
+
+COMMENT ON COLUMN projects.scrub_key IS 'Secret keying the hashes of scrubbed values, never returned by the API';
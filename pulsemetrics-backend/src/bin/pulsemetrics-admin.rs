@@ -0,0 +1,623 @@
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use pulsemetrics_backend::{
+    config::{Config, DatabaseConfig},
+    db::{api_keys, audit, create_pool, events, migrations, projects},
+    metrics::Metrics,
+    middleware::jwt::JwtVerifier,
+    models::{ApiKeySummary, AuthScheme, EventRange, IpMode, NewAuditEntry, Principal},
+    retention::{self, RetentionReport},
+    storage::{Backend, ClickHouseStore, EventStore, PostgresStore, SqliteStore},
+};
+use sqlx::PgPool;
+use std::{path::PathBuf, sync::Arc};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Operate a PulseMetrics deployment
+///
+/// Reads the same configuration as the server.
+#[derive(Debug, Parser)]
+#[command(name = "pulsemetrics-admin", version)]
+struct Cli {
+    /// TOML or YAML config file, defaults to `CONFIG_FILE`
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Name recorded in the audit log for changes made by this command
+    #[arg(long, global = true, env = "USER", default_value = "cli")]
+    operator: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Apply, inspect or revert database migrations
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+
+    /// Manage projects
+    #[command(subcommand)]
+    Project(ProjectCommand),
+
+    /// Manage project API keys
+    #[command(subcommand)]
+    Key(KeyCommand),
+
+    /// Show event counts per project
+    Usage {
+        /// Only show this project
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Only count events at or after this time (RFC 3339)
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+    },
+
+    /// Delete events by project and/or time range
+    Purge(PurgeArgs),
+
+    /// Delete events older than their project's retention_days
+    Retention {
+        /// Actually delete, otherwise only report how many events expired
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Check the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum MigrateCommand {
+    /// Apply all pending migrations
+    Run,
+    /// List migrations and whether they are applied
+    Status,
+    /// Revert migrations newer than a version
+    Rollback {
+        /// Version to roll back to, 0 reverts everything
+        #[arg(long)]
+        to: i64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ProjectCommand {
+    /// Create a project
+    Create {
+        id: String,
+
+        #[arg(long)]
+        name: String,
+
+        /// Organization owning the project
+        #[arg(long)]
+        organization: Option<String>,
+
+        /// Browser origin allowed to call the API, may be repeated
+        #[arg(long = "allowed-origin")]
+        allowed_origins: Vec<String>,
+
+        /// Days events are kept, forever when omitted
+        #[arg(long, value_parser = clap::value_parser!(i32).range(1..=36500))]
+        retention_days: Option<i32>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum KeyCommand {
+    /// Create an API key for a project
+    Create {
+        project: String,
+
+        #[arg(long)]
+        name: String,
+
+        /// `bearer` or `hmac`
+        #[arg(long, default_value = "bearer")]
+        scheme: AuthScheme,
+    },
+    /// Replace a key with a new one of the same name and scheme, revoking the old one
+    Rotate { id: Uuid },
+    /// Revoke an API key
+    Revoke { id: Uuid },
+}
+
+#[derive(Debug, Args)]
+struct PurgeArgs {
+    #[arg(long)]
+    project: Option<String>,
+
+    /// Delete events at or after this time (RFC 3339)
+    #[arg(long)]
+    from: Option<DateTime<Utc>>,
+
+    /// Delete events before this time (RFC 3339)
+    #[arg(long)]
+    to: Option<DateTime<Utc>>,
+
+    /// Actually delete, otherwise only report how many events match
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Load and validate the configuration, reporting every problem
+    Validate,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "warn,pulsemetrics_backend=info".into()),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let config = Config::load(cli.config.as_deref()).context("Failed to load configuration")?;
+    let principal = Principal::cli(cli.operator);
+
+    match cli.command {
+        Command::Config(ConfigCommand::Validate) => validate_config(&config),
+        Command::Migrate(command) => migrate(&connect(&config).await?, command).await,
+        Command::Project(command) => project(&connect(&config).await?, &principal, command).await,
+        Command::Key(command) => key(&connect(&config).await?, &principal, command).await,
+        Command::Usage { project, since } => {
+            usage(&connect(&config).await?, project.as_deref(), since).await
+        }
+        Command::Purge(args) => {
+            let range = purge_range(&args)?;
+            purge(&connect(&config).await?, &principal, &range, args.yes).await
+        }
+        Command::Retention { yes } => {
+            retention(&connect(&config).await?, &config, &principal, yes).await
+        }
+    }
+}
+
+async fn connect(config: &Config) -> anyhow::Result<PgPool> {
+    connect_database(&config.database).await
+}
+
+async fn connect_database(database: &DatabaseConfig) -> anyhow::Result<PgPool> {
+    create_pool(database)
+        .await
+        .context("Failed to create database pool")
+}
+
+fn validate_config(config: &Config) -> anyhow::Result<()> {
+    // `Config::load` has already validated the settings themselves
+    JwtVerifier::from_config(&config.jwt).context("Invalid JWT configuration")?;
+
+    println!("Configuration is valid");
+    println!("  environment: {:?}", config.app.environment);
+    println!(
+        "  listen:      {}:{}",
+        config.server.host, config.server.port
+    );
+    println!(
+        "  jwt:         {}",
+        if config.jwt.hs256_secret.is_some() || config.jwt.jwks_path.is_some() {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+
+    Ok(())
+}
+
+async fn migrate(pool: &PgPool, command: MigrateCommand) -> anyhow::Result<()> {
+    match command {
+        MigrateCommand::Run => {
+            let applied = migrations::run_migrations(pool).await?;
+            if applied.is_empty() {
+                println!("Database is up to date");
+            }
+            for version in applied {
+                println!("Applied {}", version);
+            }
+        }
+        MigrateCommand::Status => {
+            for m in migrations::status(pool).await? {
+                let state = match (m.applied_at, m.checksum_matches) {
+                    (Some(_), false) => "modified".to_string(),
+                    (Some(at), true) => format!("applied {}", at.to_rfc3339()),
+                    (None, _) => "pending".to_string(),
+                };
+                println!(
+                    "{:>4}  {:<30} {:<12} {}",
+                    m.version,
+                    m.description,
+                    if m.reversible { "reversible" } else { "" },
+                    state
+                );
+            }
+        }
+        MigrateCommand::Rollback { to } => {
+            let reverted = migrations::rollback(pool, to).await?;
+            if reverted.is_empty() {
+                println!("Nothing to roll back");
+            }
+            for version in reverted {
+                println!("Reverted {}", version);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn project(
+    pool: &PgPool,
+    principal: &Principal,
+    command: ProjectCommand,
+) -> anyhow::Result<()> {
+    match command {
+        ProjectCommand::Create {
+            id,
+            name,
+            organization,
+            allowed_origins,
+            retention_days,
+        } => {
+            let mut tx = pool.begin().await?;
+
+            let project = projects::create(
+                &mut *tx,
+                &id,
+                &name,
+                organization.as_deref(),
+                &allowed_origins,
+                retention_days,
+                &[],
+                IpMode::Drop,
+            )
+            .await
+            .with_context(|| format!("Failed to create project {}", id))?;
+
+            let entry = NewAuditEntry::new(principal, None, "project.create", "project", &id)
+                .project(&id)
+                .after(&project);
+            audit::record(&mut *tx, &entry).await?;
+
+            tx.commit().await?;
+
+            println!("Created project {}", project.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn key(pool: &PgPool, principal: &Principal, command: KeyCommand) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+
+    match command {
+        KeyCommand::Create {
+            project,
+            name,
+            scheme,
+        } => {
+            if projects::find_by_id(&mut *tx, &project).await?.is_none() {
+                bail!("Project {} not found", project);
+            }
+
+            let (key, secret) = api_keys::create(&mut *tx, &project, &name, scheme).await?;
+            audit_key_created(&mut tx, principal, &ApiKeySummary::from(&key)).await?;
+            tx.commit().await?;
+
+            print_created_key(&key.id, &secret);
+        }
+        KeyCommand::Rotate { id } => {
+            let old = api_keys::revoke(&mut *tx, id)
+                .await?
+                .with_context(|| format!("API key {} not found or already revoked", id))?;
+            let scheme = old
+                .scheme()
+                .with_context(|| format!("API key {} has an unknown scheme", id))?;
+
+            let (key, secret) =
+                api_keys::create(&mut *tx, &old.project_id, &old.name, scheme).await?;
+            audit_key_revoked(&mut tx, principal, &ApiKeySummary::from(&old)).await?;
+            audit_key_created(&mut tx, principal, &ApiKeySummary::from(&key)).await?;
+            tx.commit().await?;
+
+            println!("Revoked API key {}", old.id);
+            print_created_key(&key.id, &secret);
+        }
+        KeyCommand::Revoke { id } => {
+            let key = api_keys::revoke(&mut *tx, id)
+                .await?
+                .with_context(|| format!("API key {} not found or already revoked", id))?;
+            audit_key_revoked(&mut tx, principal, &ApiKeySummary::from(&key)).await?;
+            tx.commit().await?;
+
+            println!("Revoked API key {}", key.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn audit_key_created(
+    tx: &mut sqlx::PgConnection,
+    principal: &Principal,
+    key: &ApiKeySummary,
+) -> anyhow::Result<()> {
+    let entry = NewAuditEntry::new(
+        principal,
+        None,
+        "api_key.create",
+        "api_key",
+        &key.id.to_string(),
+    )
+    .project(&key.project_id)
+    .after(key);
+    audit::record(tx, &entry).await?;
+
+    Ok(())
+}
+
+async fn audit_key_revoked(
+    tx: &mut sqlx::PgConnection,
+    principal: &Principal,
+    key: &ApiKeySummary,
+) -> anyhow::Result<()> {
+    let before = ApiKeySummary {
+        revoked_at: None,
+        ..key.clone()
+    };
+    let entry = NewAuditEntry::new(
+        principal,
+        None,
+        "api_key.revoke",
+        "api_key",
+        &key.id.to_string(),
+    )
+    .project(&key.project_id)
+    .before(&before)
+    .after(key);
+    audit::record(tx, &entry).await?;
+
+    Ok(())
+}
+
+fn print_created_key(id: &Uuid, secret: &str) {
+    println!("Created API key {}", id);
+    println!("Secret (shown only once): {}", secret);
+}
+
+async fn usage(
+    pool: &PgPool,
+    project: Option<&str>,
+    since: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    let rows = events::usage(pool, project, since).await?;
+    if rows.is_empty() {
+        println!("No projects or events found");
+        return Ok(());
+    }
+
+    println!(
+        "{:<30} {:>12} {:>6}  {:<25} {:<25}",
+        "PROJECT", "EVENTS", "KEYS", "FIRST EVENT", "LAST EVENT"
+    );
+    for row in rows {
+        println!(
+            "{:<30} {:>12} {:>6}  {:<25} {:<25}",
+            row.project_id,
+            row.events,
+            row.active_keys,
+            row.first_event.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            row.last_event.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Validate purge arguments before touching the database
+fn purge_range(args: &PurgeArgs) -> anyhow::Result<EventRange> {
+    let range = EventRange {
+        project_id: args.project.clone(),
+        user_id: None,
+        from: args.from,
+        to: args.to,
+    };
+    if range.is_unbounded() {
+        bail!("Refusing to purge every event: pass --project, --from and/or --to");
+    }
+    if let (Some(from), Some(to)) = (range.from, range.to) {
+        if from >= to {
+            bail!("--from must be before --to");
+        }
+    }
+
+    Ok(range)
+}
+
+async fn purge(
+    pool: &PgPool,
+    principal: &Principal,
+    range: &EventRange,
+    confirmed: bool,
+) -> anyhow::Result<()> {
+    if !confirmed {
+        let matching = events::count(pool, range).await?;
+        println!("{} events match, pass --yes to delete them", matching);
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    let deleted = events::purge(&mut *tx, range).await?;
+
+    let resource_id = range.project_id.as_deref().unwrap_or("*");
+    let mut entry = NewAuditEntry::new(principal, None, "events.purge", "events", resource_id)
+        .after(&serde_json::json!({
+            "from": range.from,
+            "to": range.to,
+            "deleted": deleted,
+        }));
+    if let Some(project_id) = &range.project_id {
+        entry = entry.project(project_id);
+    }
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+
+    println!("Deleted {} events", deleted);
+
+    Ok(())
+}
+
+/// The event store the server writes to
+async fn event_store(pool: &PgPool, config: &Config) -> anyhow::Result<Box<dyn EventStore>> {
+    let url = config.database.events_url();
+    let backend = Backend::from_url(url).map_err(anyhow::Error::msg)?;
+    let metrics = Arc::new(Metrics::new());
+
+    Ok(match backend {
+        Backend::Postgres if url == config.database.url => {
+            Box::new(PostgresStore::new(pool.clone(), metrics))
+        }
+        Backend::Postgres => {
+            let database = DatabaseConfig {
+                url: url.to_string(),
+                ..config.database.clone()
+            };
+            Box::new(PostgresStore::new(
+                connect_database(&database).await?,
+                metrics,
+            ))
+        }
+        Backend::ClickHouse => Box::new(ClickHouseStore::from_url(url)?),
+        Backend::Sqlite => Box::new(SqliteStore::connect(url).await?),
+        Backend::Memory => bail!("Events kept in memory can only be managed by the server"),
+    })
+}
+
+async fn retention(
+    pool: &PgPool,
+    config: &Config,
+    principal: &Principal,
+    confirmed: bool,
+) -> anyhow::Result<()> {
+    let store = event_store(pool, config).await?;
+    let projects = projects::with_retention(pool).await?;
+    if projects.is_empty() {
+        println!("No project has a retention period");
+        return Ok(());
+    }
+
+    if !confirmed {
+        let report = retention::report(store.as_ref(), &projects, Utc::now()).await?;
+        print_retention(&report);
+        println!(
+            "{} events expired, pass --yes to delete them",
+            report.total()
+        );
+        return Ok(());
+    }
+
+    let report = retention::enforce(
+        store.as_ref(),
+        &projects,
+        config.retention.batch_size,
+        Utc::now(),
+        &Metrics::new(),
+        &CancellationToken::new(),
+    )
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    for project in report.projects.iter().filter(|p| p.expired_events > 0) {
+        let entry = NewAuditEntry::new(
+            principal,
+            None,
+            "events.retention",
+            "events",
+            &project.project_id,
+        )
+        .project(&project.project_id)
+        .after(project);
+        audit::record(&mut *tx, &entry).await?;
+    }
+    tx.commit().await?;
+
+    print_retention(&report);
+    println!("Deleted {} events", report.total());
+
+    Ok(())
+}
+
+fn print_retention(report: &RetentionReport) {
+    println!(
+        "{:<30} {:>6} {:<25} {:>12}",
+        "PROJECT", "DAYS", "CUTOFF", "EVENTS"
+    );
+    for project in &report.projects {
+        println!(
+            "{:<30} {:>6} {:<25} {:>12}",
+            project.project_id,
+            project.retention_days,
+            project.cutoff.to_rfc3339(),
+            project.expired_events,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn purge_args(project: Option<&str>, from: Option<&str>, to: Option<&str>) -> PurgeArgs {
+        PurgeArgs {
+            project: project.map(str::to_string),
+            from: from.map(|t| t.parse().unwrap()),
+            to: to.map(|t| t.parse().unwrap()),
+            yes: false,
+        }
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_purge_range_validation() {
+        assert!(purge_range(&purge_args(None, None, None)).is_err());
+        assert!(purge_range(&purge_args(
+            Some("web"),
+            Some("2024-06-01T00:00:00Z"),
+            Some("2024-05-01T00:00:00Z")
+        ))
+        .is_err());
+        assert!(purge_range(&purge_args(
+            None,
+            Some("2024-05-01T00:00:00Z"),
+            Some("2024-05-01T00:00:00Z")
+        ))
+        .is_err());
+
+        let range = purge_range(&purge_args(None, None, Some("2024-05-01T00:00:00Z"))).unwrap();
+        assert_eq!(range.project_id, None);
+        assert!(range.to.is_some());
+        let range = purge_range(&purge_args(Some("web"), None, None)).unwrap();
+        assert_eq!(range.project_id.as_deref(), Some("web"));
+        assert_eq!(range.user_id, None);
+    }
+}
@@ -0,0 +1,402 @@
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    db::{ip_salts, projects},
+    models::{Event, IpMode, Project},
+    AppState,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+/// Salt and the start of the period it is used in
+type PeriodSalt = (DateTime<Utc>, Arc<Vec<u8>>);
+
+/// Event property holding the captured address
+pub const IP_PROPERTY: &str = "client_ip";
+const FORWARDED_FOR: &str = "x-forwarded-for";
+/// Time before modes changed through another instance apply here
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Address of the client behind any trusted proxies
+///
+/// `X-Forwarded-For` is only believed when the peer is a trusted proxy.
+/// Each proxy appends the address it received the request from, so the
+/// header is read right to left up to the first address that is not a
+/// trusted proxy.
+pub fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    let mut client = peer?.to_canonical();
+    if !is_trusted(&client) {
+        return Some(client);
+    }
+
+    let hops: Vec<&str> = headers
+        .get_all(FORWARDED_FOR)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .collect();
+    for hop in hops.iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !is_trusted(&client) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+/// Address as a project stores it, `None` when it stores none
+///
+/// `salt` is only used to hash.
+pub fn anonymize(ip: IpAddr, mode: IpMode, salt: &[u8]) -> Option<String> {
+    match mode {
+        IpMode::Drop => None,
+        IpMode::Full => Some(ip.to_string()),
+        IpMode::Truncate => Some(truncate(ip).to_string()),
+        IpMode::Hash => {
+            let mut mac =
+                HmacSha256::new_from_slice(salt).expect("HMAC accepts keys of any length");
+            mac.update(ip.to_string().as_bytes());
+            Some(hex::encode(&mac.finalize().into_bytes()[..16]))
+        }
+    }
+}
+
+fn truncate(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Ipv4Addr::new(a, b, c, 0).into()
+        }
+        IpAddr::V6(ip) => {
+            // Keep the /48 routing prefix
+            let mut segments = ip.segments();
+            segments[3..].fill(0);
+            Ipv6Addr::from(segments).into()
+        }
+    }
+}
+
+/// IP modes of the projects capturing client IPs
+///
+/// Kept in memory like the scrubbing rules, so ingestion never waits on
+/// the database; [`start`] reloads them for changes made through other
+/// instances.
+#[derive(Default)]
+pub struct IpModes {
+    modes: Mutex<HashMap<String, IpMode>>,
+}
+
+impl IpModes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, project_id: &str) -> IpMode {
+        let modes = self.modes.lock().expect("IP modes poisoned");
+        modes.get(project_id).copied().unwrap_or_default()
+    }
+
+    pub fn set(&self, project_id: &str, mode: IpMode) {
+        let mut modes = self.modes.lock().expect("IP modes poisoned");
+        match mode {
+            IpMode::Drop => modes.remove(project_id),
+            mode => modes.insert(project_id.to_string(), mode),
+        };
+    }
+
+    /// Keep only the given projects, with their current modes
+    fn replace(&self, projects: &[Project]) {
+        *self.modes.lock().expect("IP modes poisoned") = projects
+            .iter()
+            .map(|p| (p.id.clone(), p.ip_mode()))
+            .collect();
+    }
+}
+
+/// Salt hashing client IPs, replaced every `client_ip.salt_rotation_hours`
+///
+/// Salts are random and forgotten once replaced, so hashes cannot be
+/// traced back to addresses or linked across periods. Instances sharing
+/// Postgres agree on the salt through it.
+#[derive(Default)]
+pub struct IpSalts {
+    pool: Option<PgPool>,
+    current: Mutex<Option<PeriodSalt>>,
+}
+
+impl IpSalts {
+    /// Salts kept by this process only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Salts shared through the `ip_salts` table
+    pub fn shared(pool: PgPool) -> Self {
+        Self {
+            pool: Some(pool),
+            ..Self::default()
+        }
+    }
+
+    pub async fn current(
+        &self,
+        rotation_hours: u64,
+        now: DateTime<Utc>,
+    ) -> Result<Arc<Vec<u8>>, sqlx::Error> {
+        let start = period_start(now, rotation_hours);
+        if let Some((period, salt)) = &*self.current.lock().expect("IP salt poisoned") {
+            if *period == start {
+                return Ok(salt.clone());
+            }
+        }
+
+        let candidate = [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat();
+        let salt = match &self.pool {
+            Some(pool) => ip_salts::current(pool, start, &candidate).await?,
+            None => candidate,
+        };
+
+        let mut current = self.current.lock().expect("IP salt poisoned");
+        match &*current {
+            // Another batch got here first
+            Some((period, salt)) if *period == start => Ok(salt.clone()),
+            _ => {
+                let salt = Arc::new(salt);
+                *current = Some((start, salt.clone()));
+                Ok(salt)
+            }
+        }
+    }
+}
+
+fn period_start(now: DateTime<Utc>, rotation_hours: u64) -> DateTime<Utc> {
+    let period = rotation_hours.max(1) as i64 * 3600;
+    let timestamp = now.timestamp();
+    DateTime::from_timestamp(timestamp - timestamp.rem_euclid(period), 0)
+        .expect("period start precedes now")
+}
+
+/// Load the IP mode of every project capturing client IPs, none without Postgres
+pub async fn refresh(state: &AppState) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    let projects = projects::with_ip_capture(db).await?;
+    state.ip_modes.replace(&projects);
+    Ok(())
+}
+
+/// Spawn the task reloading IP modes every `REFRESH_INTERVAL`
+pub fn start(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = shutdown.cancelled() => break,
+            }
+
+            if let Err(e) = refresh(&state).await {
+                tracing::warn!("Failed to reload IP modes: {}", e);
+            }
+        }
+    })
+}
+
+/// Store the client IP in the properties of events whose project captures it
+///
+/// Replaces any `client_ip` property sent by the client. Events whose
+/// properties are not an object are left alone.
+pub async fn capture_batch(
+    state: &AppState,
+    events: &mut [Event],
+    project_ids: &[String],
+    ip: Option<IpAddr>,
+) {
+    let Some(ip) = ip else {
+        return;
+    };
+
+    for project_id in project_ids {
+        let mode = state.ip_modes.get(project_id);
+        let salt = match mode {
+            IpMode::Drop => continue,
+            IpMode::Hash => {
+                let rotation_hours = state.config().client_ip.salt_rotation_hours;
+                match state.ip_salts.current(rotation_hours, Utc::now()).await {
+                    Ok(salt) => salt,
+                    Err(e) => {
+                        tracing::warn!(%project_id, "No IP salt, storing no address: {}", e);
+                        continue;
+                    }
+                }
+            }
+            IpMode::Full | IpMode::Truncate => Arc::default(),
+        };
+        let Some(address) = anonymize(ip, mode, &salt) else {
+            continue;
+        };
+
+        for event in events.iter_mut().filter(|e| e.project_id == *project_id) {
+            if let Value::Object(properties) = event
+                .properties
+                .get_or_insert_with(|| Value::Object(Map::new()))
+            {
+                properties.insert(IP_PROPERTY.to_string(), Value::String(address.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::http::HeaderValue;
+    use chrono::TimeZone;
+    use serde_json::json;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_resolve_through_trusted_proxies() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+
+        // (peer, X-Forwarded-For, client)
+        let cases = [
+            ("203.0.113.9", None, "203.0.113.9"),
+            // Only trusted proxies may name the client
+            ("203.0.113.9", Some("198.51.100.1"), "203.0.113.9"),
+            ("10.0.0.2", None, "10.0.0.2"),
+            ("10.0.0.2", Some("198.51.100.1"), "198.51.100.1"),
+            // A spoofed leftmost entry is ignored
+            (
+                "10.0.0.2",
+                Some("1.2.3.4, 198.51.100.1, 10.0.0.7"),
+                "198.51.100.1",
+            ),
+            ("10.0.0.2", Some("10.0.0.8, 10.0.0.7"), "10.0.0.8"),
+            ("10.0.0.2", Some("198.51.100.1, unknown"), "10.0.0.2"),
+            ("::ffff:10.0.0.2", Some("2001:db8::1"), "2001:db8::1"),
+        ];
+
+        for (peer, header, client) in cases {
+            let headers = header.map(forwarded).unwrap_or_default();
+            assert_eq!(
+                resolve(Some(ip(peer)), &headers, &trusted),
+                Some(ip(client)),
+                "{} {:?}",
+                peer,
+                header
+            );
+        }
+        assert_eq!(resolve(None, &forwarded("198.51.100.1"), &trusted), None);
+    }
+
+    #[test]
+    fn test_anonymize_modes() {
+        let v4 = ip("198.51.100.77");
+        let v6 = ip("2001:db8:85a3:8d3:1319:8a2e:370:7348");
+
+        assert_eq!(anonymize(v4, IpMode::Drop, b"salt"), None);
+        assert_eq!(
+            anonymize(v4, IpMode::Full, b"salt").unwrap(),
+            "198.51.100.77"
+        );
+        assert_eq!(
+            anonymize(v4, IpMode::Truncate, b"salt").unwrap(),
+            "198.51.100.0"
+        );
+        assert_eq!(
+            anonymize(v6, IpMode::Truncate, b"salt").unwrap(),
+            "2001:db8:85a3::"
+        );
+
+        let hashed = anonymize(v4, IpMode::Hash, b"salt").unwrap();
+        assert_eq!(hashed.len(), 32);
+        assert_eq!(anonymize(v4, IpMode::Hash, b"salt").unwrap(), hashed);
+        assert_ne!(anonymize(v4, IpMode::Hash, b"other").unwrap(), hashed);
+    }
+
+    #[tokio::test]
+    async fn test_salt_rotates_by_period() {
+        let salts = IpSalts::new();
+        let morning = Utc.with_ymd_and_hms(2024, 5, 1, 8, 0, 0).unwrap();
+
+        let first = salts.current(24, morning).await.unwrap();
+        let evening = morning + chrono::Duration::hours(15);
+        assert_eq!(salts.current(24, evening).await.unwrap(), first);
+        let next_day = morning + chrono::Duration::hours(16);
+        assert_ne!(salts.current(24, next_day).await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn test_capture_batch_per_project_mode() {
+        let config = Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "postgres://db".to_string())
+        })
+        .unwrap();
+        let pool = PgPoolOptions::new()
+            .connect_lazy(&config.database.url)
+            .unwrap();
+        let state = AppState::new(Some(pool), config);
+        state.ip_modes.set("web", IpMode::Truncate);
+        state.ip_modes.set("app", IpMode::Hash);
+
+        let mut events = vec![
+            Event::fixture("web"),
+            Event::fixture("web").properties(json!({"client_ip": "1.2.3.4", "plan": "pro"})),
+            Event::fixture("web").properties(json!("not an object")),
+            Event::fixture("app"),
+            Event::fixture("docs"),
+        ];
+        let project_ids = ["app", "docs", "web"].map(str::to_string);
+        capture_batch(&state, &mut events, &project_ids, Some(ip("198.51.100.77"))).await;
+
+        assert_eq!(
+            events[0].properties,
+            Some(json!({"client_ip": "198.51.100.0"}))
+        );
+        assert_eq!(
+            events[1].properties,
+            Some(json!({"client_ip": "198.51.100.0", "plan": "pro"}))
+        );
+        assert_eq!(events[2].properties, Some(json!("not an object")));
+        assert_eq!(
+            events[3].properties.as_ref().unwrap()[IP_PROPERTY]
+                .as_str()
+                .unwrap()
+                .len(),
+            32
+        );
+        assert_eq!(events[4].properties, None);
+    }
+}
@@ -1,17 +1,50 @@
+pub mod reload;
+mod security;
+mod sources;
+mod validation;
+
+use ipnet::IpNet;
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use crate::storage::Backend;
+use sources::Layers;
+pub use sources::DEFAULT_API_KEY;
+pub use validation::ValidationError;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
     pub app: AppConfig,
+    pub jwt: JwtConfig,
+    pub cors: CorsConfig,
+    pub log: LogConfig,
+    pub rate_limit: RateLimitConfig,
+    pub otel: OtelConfig,
+    pub retention: RetentionConfig,
+    pub compression: CompressionConfig,
+    pub export: ExportConfig,
+    pub client_ip: ClientIpConfig,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+
+    /// PEM certificate chain, TLS is enabled when this and the key are set
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+
+    /// Reverse proxies (IPs or CIDR ranges) allowed to terminate TLS in front of the server,
+    /// whose `X-Forwarded-For` is believed when capturing client IPs
+    pub trusted_proxies: Vec<String>,
 }
 
 impl ServerConfig {
@@ -20,27 +53,192 @@ impl ServerConfig {
         addr.parse()
             .map_err(|e| anyhow::anyhow!("Invalid socket address: {}", e))
     }
+
+    /// Certificate and key paths when TLS is enabled
+    pub fn tls(&self) -> Option<(&str, &str)> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            _ => None,
+        }
+    }
+
+    /// Parsed `trusted_proxies`, a bare address is a single-host network
+    pub fn trusted_proxy_networks(&self) -> Result<Vec<IpNet>, String> {
+        self.trusted_proxies
+            .iter()
+            .map(|p| {
+                p.parse::<IpNet>()
+                    .or_else(|_| p.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| p.clone())
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DatabaseConfig {
+    /// Projects, keys, organizations and the audit log, and events unless `events_url` is set
     pub url: String,
+    /// Separate event storage, such as `clickhouse://host:8123/db`
+    pub events_url: Option<String>,
+    /// Postgres standbys that serve event queries
+    pub read_urls: Vec<String>,
+    /// Replay lag above which a replica stops receiving queries
+    pub replica_max_lag_seconds: u64,
     pub max_connections: u32,
     pub min_connections: u32,
     pub connect_timeout_seconds: u64,
     pub idle_timeout_seconds: u64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl DatabaseConfig {
+    /// URL of the event storage
+    pub fn events_url(&self) -> &str {
+        self.events_url.as_deref().unwrap_or(&self.url)
+    }
+
+    /// Event storage backend named by the URL scheme
+    pub fn backend(&self) -> Result<Backend, String> {
+        Backend::from_url(self.events_url())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AppConfig {
     pub environment: Environment,
     pub api_key: String,
     pub max_batch_size: usize,
     pub buffer_flush_interval_ms: u64,
+    pub hmac_max_skew_seconds: u64,
+}
+
+/// JWT verification settings, disabled unless a secret or JWKS file is set
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    pub hs256_secret: Option<String>,
+    pub jwks_path: Option<String>,
+    pub audience: String,
+    pub issuer: Option<String>,
+    pub leeway_seconds: u64,
+}
+
+/// Global CORS settings, used for projects that define no origins of their own
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Allowed browser origins, `*` allows any origin
+    pub allowed_origins: Vec<String>,
+}
+
+/// Per-project ingestion rate limit, a token bucket refilled every second
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained events per second per project, 0 disables the limit
+    pub events_per_second: u32,
+    /// Events a project may send at once before being limited
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.events_per_second > 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// `tracing` filter directives, `RUST_LOG` syntax
+    pub filter: String,
+    pub format: LogFormat,
+    /// Directory for rotated log files, stdout when unset
+    pub directory: Option<String>,
+    /// File name prefix of log files inside `directory`
+    pub file_prefix: String,
+    pub rotation: LogRotation,
+}
+
+/// OpenTelemetry trace export, disabled unless an endpoint is set
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtelConfig {
+    /// Base URL of an OTLP/HTTP collector, e.g. `http://localhost:4318`
+    pub endpoint: Option<String>,
+    /// `service.name` resource attribute of exported spans
+    pub service_name: String,
+}
+
+/// Background deletion of events past their project's `retention_days`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Seconds between retention runs, 0 disables the job
+    pub interval_seconds: u64,
+    /// Events deleted per statement, bounding how long each holds its locks
+    pub batch_size: u64,
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.interval_seconds > 0
+    }
+}
+
+/// Native compression of the TimescaleDB `events` hypertable
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Age in days at which chunks are compressed, 0 removes the policy
+    pub compress_after_days: u32,
+}
+
+/// Files produced by user data exports
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Directory export files are written to
+    pub directory: String,
+    /// Hours an export stays downloadable before its file is deleted
+    pub expire_after_hours: u64,
+}
+
+/// Client IPs captured with events
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientIpConfig {
+    /// Hours before the salt of hashed IPs is replaced and forgotten
+    pub salt_rotation_hours: u64,
+}
+
+/// How log lines are rendered
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum LogFormat {
+    /// Single line with span context, the `tracing-subscriber` default
+    Full,
+    Compact,
+    /// Multi-line, for local development
+    Pretty,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
+/// How often log files are rolled over
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
+#[serde(try_from = "String")]
 pub enum Environment {
     Development,
     Production,
@@ -48,52 +246,37 @@ pub enum Environment {
 }
 
 impl Config {
-    pub fn from_env() -> anyhow::Result<Self> {
+    /// Load and validate the configuration
+    ///
+    /// Layers, from lowest to highest precedence: built-in defaults, the
+    /// TOML or YAML file at `path` (or `CONFIG_FILE` when `None`), then
+    /// environment variables.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
         dotenvy::dotenv().ok();
 
-        let config = Config {
-            server: ServerConfig {
-                host: std::env::var("SERVER_HOST")
-                    .unwrap_or_else(|_| "0.0.0.0".to_string()),
-                port: std::env::var("SERVER_PORT")
-                    .unwrap_or_else(|_| "8000".to_string())
-                    .parse()
-                    .map_err(|e| anyhow::anyhow!("Invalid SERVER_PORT: {}", e))?,
-            },
-            database: DatabaseConfig {
-                url: std::env::var("DATABASE_URL")
-                    .map_err(|_| anyhow::anyhow!("DATABASE_URL must be set"))?,
-                max_connections: std::env::var("DB_MAX_CONNECTIONS")
-                    .unwrap_or_else(|_| "20".to_string())
-                    .parse()?,
-                min_connections: std::env::var("DB_MIN_CONNECTIONS")
-                    .unwrap_or_else(|_| "5".to_string())
-                    .parse()?,
-                connect_timeout_seconds: std::env::var("DB_CONNECT_TIMEOUT")
-                    .unwrap_or_else(|_| "10".to_string())
-                    .parse()?,
-                idle_timeout_seconds: std::env::var("DB_IDLE_TIMEOUT")
-                    .unwrap_or_else(|_| "600".to_string())
-                    .parse()?,
-            },
-            app: AppConfig {
-                environment: std::env::var("ENVIRONMENT")
-                    .unwrap_or_else(|_| "development".to_string())
-                    .parse()
-                    .map_err(|_| anyhow::anyhow!("Invalid ENVIRONMENT"))?,
-                api_key: std::env::var("API_KEY")
-                    .unwrap_or_else(|_| "dev-api-key-change-in-production".to_string()),
-                max_batch_size: std::env::var("MAX_BATCH_SIZE")
-                    .unwrap_or_else(|_| "1000".to_string())
-                    .parse()?,
-                buffer_flush_interval_ms: std::env::var("BUFFER_FLUSH_INTERVAL_MS")
-                    .unwrap_or_else(|_| "100".to_string())
-                    .parse()?,
-            },
-        };
+        let config = Self::load_from(path, |var| std::env::var(var).ok())?;
+        config.validate()?;
 
         Ok(config)
     }
+
+    /// Build the configuration from the given environment without validating it
+    pub fn load_from(
+        path: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> anyhow::Result<Self> {
+        let mut layers = Layers::defaults();
+
+        let file = path
+            .map(Path::to_path_buf)
+            .or_else(|| env("CONFIG_FILE").map(PathBuf::from));
+        if let Some(file) = file {
+            layers.merge_file(&file)?;
+        }
+
+        layers.merge_env(env)?;
+        layers.build()
+    }
 }
 
 impl std::str::FromStr for Environment {
@@ -107,4 +290,258 @@ impl std::str::FromStr for Environment {
             _ => Err(format!("Unknown environment: {}", s)),
         }
     }
-}
\ No newline at end of file
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "full" => Ok(LogFormat::Full),
+            "compact" => Ok(LogFormat::Compact),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::str::FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "minutely" => Ok(LogRotation::Minutely),
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            "never" => Ok(LogRotation::Never),
+            _ => Err(format!("Unknown log rotation: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for LogRotation {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    fn write_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}", uuid::Uuid::new_v4(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_layers_apply_in_order() {
+        let path = write_file(
+            "config.toml",
+            "[server]\nport = 9000\nhost = \"127.0.0.1\"\n\n[database]\nurl = \"postgres://file\"\n",
+        );
+
+        let config = Config::load_from(Some(&path), env(&[("SERVER_PORT", "9100")])).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(config.server.port, 9100);
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.database.url, "postgres://file");
+        assert_eq!(config.database.max_connections, 20);
+    }
+
+    #[test]
+    fn test_yaml_file_from_env() {
+        let path = write_file("config.yaml", "app:\n  environment: Production\n");
+
+        let config =
+            Config::load_from(None, env(&[("CONFIG_FILE", path.to_str().unwrap())])).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(config.app.environment, Environment::Production);
+    }
+
+    #[test]
+    fn test_errors_name_the_key() {
+        let err = Config::load_from(None, env(&[("DB_MAX_CONNECTIONS", "lots")])).unwrap_err();
+        assert!(err.to_string().contains("DB_MAX_CONNECTIONS"), "{}", err);
+
+        let err = Config::load_from(None, env(&[("SERVER_PORT", "70000")])).unwrap_err();
+        assert!(err.to_string().contains("server.port"), "{}", err);
+        assert!(err.to_string().contains("SERVER_PORT"), "{}", err);
+
+        let path = write_file("typo.toml", "[database]\nmax_conections = 5\n");
+        let err = Config::load_from(Some(&path), env(&[])).unwrap_err();
+        std::fs::remove_file(&path).ok();
+        assert!(err.to_string().contains("max_conections"), "{}", err);
+    }
+
+    #[test]
+    fn test_log_output_from_env() {
+        let config = Config::load_from(
+            None,
+            env(&[("LOG_FORMAT", "JSON"), ("LOG_DIRECTORY", "/var/log/pm")]),
+        )
+        .unwrap();
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(config.log.directory.as_deref(), Some("/var/log/pm"));
+        assert_eq!(config.log.rotation, LogRotation::Daily);
+
+        let err = Config::load_from(None, env(&[("LOG_FORMAT", "xml")])).unwrap_err();
+        assert!(err.to_string().contains("LOG_FORMAT"), "{}", err);
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("ENVIRONMENT", "production"),
+                ("DB_MIN_CONNECTIONS", "50"),
+                ("DB_MAX_CONNECTIONS", "10"),
+            ]),
+        )
+        .unwrap();
+
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.problems.len(), 6, "{}", err);
+        assert!(err.to_string().contains("database.url"));
+        assert!(err.to_string().contains("database.min_connections (50)"));
+        assert!(err.to_string().contains("production: app.api_key"));
+        assert!(err.to_string().contains("production: cors.allowed_origins"));
+        assert!(err
+            .to_string()
+            .contains("production: either server.tls_cert_path"));
+        assert!(err.to_string().contains("production: log.filter"));
+    }
+
+    #[test]
+    fn test_events_url_selects_event_backend() {
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("DATABASE_URL", "postgres://db"),
+                ("EVENTS_DATABASE_URL", "clickhouse://ch:8123/pm"),
+            ]),
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.database.backend(), Ok(Backend::ClickHouse));
+
+        let config =
+            Config::load_from(None, env(&[("DATABASE_URL", "clickhouse://ch:8123/pm")])).unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("database.events_url"), "{}", err);
+    }
+
+    #[test]
+    fn test_read_urls_from_env() {
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("DATABASE_URL", "postgres://primary/pm"),
+                (
+                    "DATABASE_READ_URL",
+                    "postgres://replica-1/pm, postgres://replica-2/pm",
+                ),
+            ]),
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.database.read_urls.len(), 2);
+        assert_eq!(config.database.replica_max_lag_seconds, 30);
+
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("DATABASE_URL", "sqlite://data/pm.db"),
+                ("DATABASE_READ_URL", "sqlite://data/copy.db"),
+            ]),
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.problems.len(), 2, "{}", err);
+    }
+
+    #[test]
+    fn test_retention_batch_size_required_when_enabled() {
+        let config = Config::load_from(None, env(&[("DATABASE_URL", "postgres://db")])).unwrap();
+        assert!(config.retention.is_enabled());
+        assert_eq!(config.retention.batch_size, 10000);
+
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("DATABASE_URL", "postgres://db"),
+                ("RETENTION_BATCH_SIZE", "0"),
+            ]),
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("retention.batch_size"), "{}", err);
+
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("DATABASE_URL", "postgres://db"),
+                ("RETENTION_INTERVAL_SECONDS", "0"),
+                ("RETENTION_BATCH_SIZE", "0"),
+            ]),
+        )
+        .unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_hardened_production_config_is_accepted() {
+        let config = Config::load_from(
+            None,
+            env(&[
+                ("ENVIRONMENT", "production"),
+                ("DATABASE_URL", "postgres://db"),
+                ("API_KEY", "0123456789abcdef0123456789abcdef"),
+                ("CORS_ALLOWED_ORIGINS", "https://app.example.com"),
+                ("TRUSTED_PROXIES", "10.0.0.0/8, 192.168.1.1"),
+                ("RUST_LOG", "info,sqlx=warn"),
+            ]),
+        )
+        .unwrap();
+
+        config.validate().unwrap();
+        assert!(
+            Config::load_from(None, env(&[("DATABASE_URL", "postgres://db")]))
+                .unwrap()
+                .validate()
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use serde::Serialize;
+
+use super::Config;
+
+/// Result of merging a freshly loaded configuration into the running one
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadOutcome {
+    /// Reloadable keys whose value changed
+    pub changed: Vec<String>,
+    /// Sections that changed but only take effect after a restart
+    pub requires_restart: Vec<String>,
+}
+
+/// Apply the reloadable settings of `candidate` to `current`
+///
+/// Only batch limits, the flush interval, the replica lag limit, rate
+/// limits, the log filter, CORS origins, retention settings and the client
+/// IP salt rotation are taken from `candidate`; everything else keeps its running value and is
+/// reported in `requires_restart` if it differs.
+pub fn apply(current: &Config, candidate: &Config) -> (Config, ReloadOutcome) {
+    let mut next = current.clone();
+    let mut outcome = ReloadOutcome::default();
+
+    macro_rules! reload {
+        ($($field:ident).+) => {
+            if next.$($field).+ != candidate.$($field).+ {
+                next.$($field).+ = candidate.$($field).+.clone();
+                outcome.changed.push(stringify!($($field).+).replace(' ', ""));
+            }
+        };
+    }
+
+    reload!(app.max_batch_size);
+    reload!(app.buffer_flush_interval_ms);
+    reload!(database.replica_max_lag_seconds);
+    reload!(rate_limit.events_per_second);
+    reload!(rate_limit.burst);
+    reload!(log.filter);
+    reload!(cors.allowed_origins);
+    reload!(retention.interval_seconds);
+    reload!(retention.batch_size);
+    reload!(client_ip.salt_rotation_hours);
+
+    let sections = [
+        ("server", next.server != candidate.server),
+        ("database", next.database != candidate.database),
+        ("app", next.app != candidate.app),
+        ("jwt", next.jwt != candidate.jwt),
+        ("log", next.log != candidate.log),
+        ("otel", next.otel != candidate.otel),
+        ("compression", next.compression != candidate.compression),
+        ("export", next.export != candidate.export),
+    ];
+    outcome.requires_restart = sections
+        .into_iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+    (next, outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "postgres://db".to_string())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_only_reloadable_settings_are_applied() {
+        let current = config();
+        let mut candidate = config();
+        candidate.app.max_batch_size = 500;
+        candidate.log.filter = "warn".to_string();
+        candidate.cors.allowed_origins = vec!["https://app.example.com".to_string()];
+        candidate.server.port = 9999;
+        candidate.app.api_key = "rotated".to_string();
+        candidate.compression.compress_after_days = 30;
+
+        let (next, outcome) = apply(&current, &candidate);
+
+        assert_eq!(next.app.max_batch_size, 500);
+        assert_eq!(next.log.filter, "warn");
+        assert_eq!(next.cors.allowed_origins, candidate.cors.allowed_origins);
+        assert_eq!(next.server.port, current.server.port);
+        assert_eq!(next.app.api_key, current.app.api_key);
+        assert_eq!(
+            outcome.changed,
+            ["app.max_batch_size", "log.filter", "cors.allowed_origins"]
+        );
+        assert_eq!(outcome.requires_restart, ["server", "app", "compression"]);
+    }
+
+    #[test]
+    fn test_unchanged_config_is_a_no_op() {
+        let (next, outcome) = apply(&config(), &config());
+
+        assert_eq!(next, config());
+        assert!(outcome.changed.is_empty());
+        assert!(outcome.requires_restart.is_empty());
+    }
+}
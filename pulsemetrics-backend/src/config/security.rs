@@ -0,0 +1,94 @@
+use tracing_subscriber::{filter::LevelFilter, EnvFilter};
+
+use super::{Config, DEFAULT_API_KEY};
+use crate::storage::Backend;
+
+/// Minimum length of secrets accepted in production
+const MIN_SECRET_LEN: usize = 32;
+
+impl Config {
+    /// Security checklist enforced when running in production
+    ///
+    /// Returns one message per violation. Development and test
+    /// environments skip the checklist to keep the convenient defaults.
+    pub fn security_violations(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if self.app.api_key == DEFAULT_API_KEY {
+            violations.push("app.api_key must be changed from the default".to_string());
+        } else if self.app.api_key.len() < MIN_SECRET_LEN {
+            violations.push(format!(
+                "app.api_key must be at least {} characters",
+                MIN_SECRET_LEN
+            ));
+        }
+
+        if let Some(secret) = &self.jwt.hs256_secret {
+            if secret.len() < MIN_SECRET_LEN {
+                violations.push(format!(
+                    "jwt.hs256_secret must be at least {} characters",
+                    MIN_SECRET_LEN
+                ));
+            }
+        }
+
+        if self.database.backend() == Ok(Backend::Memory) {
+            violations.push(
+                "event storage must not be memory://, events would be lost on restart".to_string(),
+            );
+        }
+
+        if self.cors.allowed_origins.iter().any(|o| o == "*") {
+            violations
+                .push("cors.allowed_origins must list explicit origins instead of *".to_string());
+        }
+
+        if self.server.tls().is_none() && self.server.trusted_proxies.is_empty() {
+            violations.push(
+                "either server.tls_cert_path and server.tls_key_path or server.trusted_proxies \
+                 must be set so traffic is encrypted"
+                    .to_string(),
+            );
+        }
+
+        if enables_verbose_logging(&self.log.filter) {
+            violations.push(format!(
+                "log.filter ({}) must not enable debug or trace logging",
+                self.log.filter
+            ));
+        }
+
+        violations
+    }
+}
+
+/// Whether any directive of a `tracing` filter enables debug or trace
+///
+/// A target without a level enables everything, and levels may be given as
+/// numbers, so the filter is parsed rather than matched. Invalid filters
+/// are reported by validation.
+fn enables_verbose_logging(filter: &str) -> bool {
+    EnvFilter::try_new(filter)
+        .ok()
+        .and_then(|filter| filter.max_level_hint())
+        .is_some_and(|level| level >= LevelFilter::DEBUG)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbose_log_filters() {
+        assert!(enables_verbose_logging("debug"));
+        assert!(enables_verbose_logging("info,pulsemetrics_backend=debug"));
+        assert!(enables_verbose_logging("warn,sqlx=TRACE"));
+        assert!(enables_verbose_logging("info,pulsemetrics_backend"));
+        assert!(enables_verbose_logging("sqlx=5"));
+        assert!(enables_verbose_logging("warn,tower_http=4"));
+        assert!(!enables_verbose_logging("sqlx=3"));
+        assert!(!enables_verbose_logging("off"));
+        assert!(!enables_verbose_logging("info"));
+        assert!(!enables_verbose_logging("info,sqlx=warn"));
+    }
+}
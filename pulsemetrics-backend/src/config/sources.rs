@@ -0,0 +1,301 @@
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, path::Path};
+
+/// Global API key used when none is configured
+pub const DEFAULT_API_KEY: &str = "dev-api-key-change-in-production";
+
+/// How an environment variable's value is converted
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Str,
+    Int,
+    /// Comma separated, empty items dropped
+    List,
+}
+
+/// Environment variables and the config key each one overrides
+const ENV_OVERRIDES: &[(&str, &str, Kind)] = &[
+    ("SERVER_HOST", "server.host", Kind::Str),
+    ("SERVER_PORT", "server.port", Kind::Int),
+    ("TLS_CERT_PATH", "server.tls_cert_path", Kind::Str),
+    ("TLS_KEY_PATH", "server.tls_key_path", Kind::Str),
+    ("TRUSTED_PROXIES", "server.trusted_proxies", Kind::List),
+    ("DATABASE_URL", "database.url", Kind::Str),
+    ("EVENTS_DATABASE_URL", "database.events_url", Kind::Str),
+    ("DATABASE_READ_URL", "database.read_urls", Kind::List),
+    (
+        "DB_REPLICA_MAX_LAG_SECONDS",
+        "database.replica_max_lag_seconds",
+        Kind::Int,
+    ),
+    ("DB_MAX_CONNECTIONS", "database.max_connections", Kind::Int),
+    ("DB_MIN_CONNECTIONS", "database.min_connections", Kind::Int),
+    (
+        "DB_CONNECT_TIMEOUT",
+        "database.connect_timeout_seconds",
+        Kind::Int,
+    ),
+    (
+        "DB_IDLE_TIMEOUT",
+        "database.idle_timeout_seconds",
+        Kind::Int,
+    ),
+    ("ENVIRONMENT", "app.environment", Kind::Str),
+    ("API_KEY", "app.api_key", Kind::Str),
+    ("MAX_BATCH_SIZE", "app.max_batch_size", Kind::Int),
+    (
+        "BUFFER_FLUSH_INTERVAL_MS",
+        "app.buffer_flush_interval_ms",
+        Kind::Int,
+    ),
+    (
+        "HMAC_MAX_SKEW_SECONDS",
+        "app.hmac_max_skew_seconds",
+        Kind::Int,
+    ),
+    ("JWT_HS256_SECRET", "jwt.hs256_secret", Kind::Str),
+    ("JWT_JWKS_PATH", "jwt.jwks_path", Kind::Str),
+    ("JWT_AUDIENCE", "jwt.audience", Kind::Str),
+    ("JWT_ISSUER", "jwt.issuer", Kind::Str),
+    ("JWT_LEEWAY_SECONDS", "jwt.leeway_seconds", Kind::Int),
+    ("CORS_ALLOWED_ORIGINS", "cors.allowed_origins", Kind::List),
+    ("RUST_LOG", "log.filter", Kind::Str),
+    ("LOG_FORMAT", "log.format", Kind::Str),
+    ("LOG_DIRECTORY", "log.directory", Kind::Str),
+    ("LOG_FILE_PREFIX", "log.file_prefix", Kind::Str),
+    ("LOG_ROTATION", "log.rotation", Kind::Str),
+    (
+        "RATE_LIMIT_EVENTS_PER_SECOND",
+        "rate_limit.events_per_second",
+        Kind::Int,
+    ),
+    ("RATE_LIMIT_BURST", "rate_limit.burst", Kind::Int),
+    ("OTEL_EXPORTER_OTLP_ENDPOINT", "otel.endpoint", Kind::Str),
+    ("OTEL_SERVICE_NAME", "otel.service_name", Kind::Str),
+    (
+        "RETENTION_INTERVAL_SECONDS",
+        "retention.interval_seconds",
+        Kind::Int,
+    ),
+    ("RETENTION_BATCH_SIZE", "retention.batch_size", Kind::Int),
+    (
+        "COMPRESS_AFTER_DAYS",
+        "compression.compress_after_days",
+        Kind::Int,
+    ),
+    ("EXPORT_DIRECTORY", "export.directory", Kind::Str),
+    (
+        "EXPORT_EXPIRE_AFTER_HOURS",
+        "export.expire_after_hours",
+        Kind::Int,
+    ),
+    (
+        "CLIENT_IP_SALT_ROTATION_HOURS",
+        "client_ip.salt_rotation_hours",
+        Kind::Int,
+    ),
+];
+
+/// Configuration tree built from successive layers
+///
+/// Remembers where each key was last set so errors can point at the
+/// file or environment variable to fix.
+#[derive(Debug)]
+pub struct Layers {
+    tree: Value,
+    sources: HashMap<String, String>,
+}
+
+impl Layers {
+    /// Start from the built-in defaults
+    pub fn defaults() -> Self {
+        Self {
+            tree: json!({
+                "server": {
+                    "host": "0.0.0.0",
+                    "port": 8000,
+                    "tls_cert_path": null,
+                    "tls_key_path": null,
+                    "trusted_proxies": [],
+                },
+                "database": {
+                    "url": "",
+                    "events_url": null,
+                    "read_urls": [],
+                    "replica_max_lag_seconds": 30,
+                    "max_connections": 20,
+                    "min_connections": 5,
+                    "connect_timeout_seconds": 10,
+                    "idle_timeout_seconds": 600,
+                },
+                "app": {
+                    "environment": "development",
+                    "api_key": DEFAULT_API_KEY,
+                    "max_batch_size": 1000,
+                    "buffer_flush_interval_ms": 100,
+                    "hmac_max_skew_seconds": 300,
+                },
+                "jwt": {
+                    "hs256_secret": null,
+                    "jwks_path": null,
+                    "audience": "pulsemetrics",
+                    "issuer": null,
+                    "leeway_seconds": 30,
+                },
+                "cors": {
+                    "allowed_origins": ["*"],
+                },
+                "log": {
+                    "filter": "info,pulsemetrics_backend=debug",
+                    "format": "full",
+                    "directory": null,
+                    "file_prefix": "pulsemetrics.log",
+                    "rotation": "daily",
+                },
+                "rate_limit": {
+                    "events_per_second": 0,
+                    "burst": 10000,
+                },
+                "otel": {
+                    "endpoint": null,
+                    "service_name": "pulsemetrics-backend",
+                },
+                "retention": {
+                    "interval_seconds": 3600,
+                    "batch_size": 10000,
+                },
+                "compression": {
+                    "compress_after_days": 7,
+                },
+                "export": {
+                    "directory": "data/exports",
+                    "expire_after_hours": 72,
+                },
+                "client_ip": {
+                    "salt_rotation_hours": 24,
+                },
+            }),
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Merge a TOML or YAML file, chosen by its extension
+    pub fn merge_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        let value: Value = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text)
+                .with_context(|| format!("Invalid TOML in {}", path.display()))?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text)
+                .with_context(|| format!("Invalid YAML in {}", path.display()))?,
+            _ => anyhow::bail!(
+                "Config file {} must end in .toml, .yaml or .yml",
+                path.display()
+            ),
+        };
+
+        let origin = format!("config file {}", path.display());
+        self.merge_value(value, &origin)
+    }
+
+    /// Merge a parsed document over the current tree
+    pub fn merge_value(&mut self, value: Value, origin: &str) -> anyhow::Result<()> {
+        if !value.is_object() {
+            anyhow::bail!("Expected a table of settings in {}", origin);
+        }
+
+        let mut paths = Vec::new();
+        merge(&mut self.tree, value, String::new(), &mut paths);
+        for path in paths {
+            self.sources.insert(path, origin.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Apply environment variable overrides
+    pub fn merge_env(&mut self, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        for (var, path, kind) in ENV_OVERRIDES {
+            let Some(raw) = env(var) else { continue };
+
+            let value = match kind {
+                Kind::Str => Value::String(raw),
+                Kind::Int => raw
+                    .trim()
+                    .parse::<u64>()
+                    .map(Value::from)
+                    .map_err(|e| anyhow::anyhow!("Invalid {} ({}): {}", var, path, e))?,
+                Kind::List => Value::Array(
+                    raw.split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(|s| Value::String(s.to_string()))
+                        .collect(),
+                ),
+            };
+
+            self.set(path, value);
+            self.sources
+                .insert(path.to_string(), format!("environment variable {}", var));
+        }
+
+        Ok(())
+    }
+
+    /// Deserialize the merged tree, naming the key on error
+    pub fn build<T: serde::de::DeserializeOwned>(self) -> anyhow::Result<T> {
+        serde_path_to_error::deserialize(self.tree).map_err(|e| {
+            let path = e.path().to_string();
+            let source = self
+                .sources
+                .get(&path)
+                .map(|s| format!(" (set by {})", s))
+                .unwrap_or_default();
+            anyhow::anyhow!("Invalid config key {}{}: {}", path, source, e.inner())
+        })
+    }
+
+    fn set(&mut self, path: &str, value: Value) {
+        let mut node = &mut self.tree;
+        let mut parts = path.split('.').peekable();
+        while let Some(part) = parts.next() {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            let map = node.as_object_mut().expect("just made an object");
+            if parts.peek().is_none() {
+                map.insert(part.to_string(), value);
+                return;
+            }
+            node = map.entry(part).or_insert_with(|| Value::Object(Map::new()));
+        }
+    }
+}
+
+/// Recursively merge `overlay` into `base`, collecting the dotted paths set
+fn merge(base: &mut Value, overlay: Value, prefix: String, paths: &mut Vec<String>) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value, path, paths),
+                    None => {
+                        paths.push(path);
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => {
+            paths.push(prefix);
+            *base = overlay;
+        }
+    }
+}
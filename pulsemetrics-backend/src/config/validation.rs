@@ -0,0 +1,171 @@
+use super::{Config, Environment};
+use crate::storage::Backend;
+
+/// Every problem found while validating a configuration
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid configuration:\n{}", format_problems(.problems))]
+pub struct ValidationError {
+    pub problems: Vec<String>,
+}
+
+fn format_problems(problems: &[String]) -> String {
+    problems
+        .iter()
+        .map(|p| format!("  - {}", p))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Config {
+    /// Check settings that are well-formed but inconsistent or unsafe
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut problems = Vec::new();
+
+        if self.server.socket_addr().is_err() {
+            problems.push(format!(
+                "server.host ({}) and server.port ({}) do not form a valid socket address",
+                self.server.host, self.server.port
+            ));
+        }
+
+        if self.server.tls_cert_path.is_some() != self.server.tls_key_path.is_some() {
+            problems.push(
+                "server.tls_cert_path and server.tls_key_path must be set together".to_string(),
+            );
+        }
+        if let Err(proxy) = self.server.trusted_proxy_networks() {
+            problems.push(format!(
+                "server.trusted_proxies entry {} is not an IP address or CIDR range",
+                proxy
+            ));
+        }
+
+        if self.database.url.is_empty() {
+            problems.push("database.url must be set (DATABASE_URL)".to_string());
+        } else {
+            match Backend::from_url(&self.database.url) {
+                Ok(Backend::Postgres) | Ok(Backend::Sqlite) | Ok(Backend::Memory) => {}
+                Ok(_) => problems.push(
+                    "database.url must be a postgres://, sqlite:// or memory:// URL, \
+                     point database.events_url at other event storage"
+                        .to_string(),
+                ),
+                Err(e) => problems.push(format!("database.url has an {}", e)),
+            }
+        }
+        if let Some(url) = &self.database.events_url {
+            if let Err(e) = Backend::from_url(url) {
+                problems.push(format!("database.events_url has an {}", e));
+            }
+        }
+        if !self.database.read_urls.is_empty() && self.database.backend() != Ok(Backend::Postgres) {
+            problems.push("database.read_urls require events to be stored in Postgres".to_string());
+        }
+        if let Some(url) = self
+            .database
+            .read_urls
+            .iter()
+            .find(|url| Backend::from_url(url) != Ok(Backend::Postgres))
+        {
+            problems.push(format!(
+                "database.read_urls entry {} is not a postgres:// URL",
+                url
+            ));
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            problems.push(format!(
+                "database.min_connections ({}) must not exceed database.max_connections ({})",
+                self.database.min_connections, self.database.max_connections
+            ));
+        }
+
+        if self.app.api_key.is_empty() {
+            problems.push("app.api_key must not be empty".to_string());
+        }
+        if self.app.max_batch_size == 0 {
+            problems.push("app.max_batch_size must be at least 1".to_string());
+        }
+        if self.app.buffer_flush_interval_ms == 0 {
+            problems.push("app.buffer_flush_interval_ms must be at least 1".to_string());
+        }
+        if self.app.hmac_max_skew_seconds == 0 {
+            problems.push("app.hmac_max_skew_seconds must be at least 1".to_string());
+        }
+
+        if self.jwt.hs256_secret.as_deref() == Some("") {
+            problems.push("jwt.hs256_secret must not be empty when set".to_string());
+        }
+        if self.jwt.audience.is_empty() {
+            problems.push("jwt.audience must not be empty".to_string());
+        }
+
+        if self.rate_limit.is_enabled()
+            && (self.rate_limit.burst as usize) < self.app.max_batch_size
+        {
+            problems.push(format!(
+                "rate_limit.burst ({}) must be at least app.max_batch_size ({}) so a full batch can be accepted",
+                self.rate_limit.burst, self.app.max_batch_size
+            ));
+        }
+
+        if self.retention.is_enabled() && self.retention.batch_size == 0 {
+            problems.push("retention.batch_size must be at least 1".to_string());
+        }
+
+        if self.export.directory.is_empty() {
+            problems.push("export.directory must not be empty".to_string());
+        }
+        if !(1..=8760).contains(&self.export.expire_after_hours) {
+            problems
+                .push("export.expire_after_hours must be between 1 and 8760 (a year)".to_string());
+        }
+        if !(1..=8760).contains(&self.client_ip.salt_rotation_hours) {
+            problems.push(
+                "client_ip.salt_rotation_hours must be between 1 and 8760 (a year)".to_string(),
+            );
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.filter) {
+            problems.push(format!(
+                "log.filter ({}) is invalid: {}",
+                self.log.filter, e
+            ));
+        }
+
+        if self.log.directory.as_deref() == Some("") {
+            problems.push("log.directory must not be empty when set".to_string());
+        }
+        if self.log.file_prefix.is_empty() {
+            problems.push("log.file_prefix must not be empty".to_string());
+        }
+
+        if let Some(endpoint) = &self.otel.endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                problems.push(format!(
+                    "otel.endpoint ({}) must be an http:// or https:// URL",
+                    endpoint
+                ));
+            }
+        }
+        if self.otel.service_name.is_empty() {
+            problems.push("otel.service_name must not be empty".to_string());
+        }
+
+        if self.app.environment == Environment::Production {
+            problems.extend(
+                self.security_violations()
+                    .into_iter()
+                    .map(|v| format!("production: {}", v)),
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::models::{ApiKey, AuthScheme};
+
+const API_KEY_COLUMNS: &str =
+    "id, project_id, name, auth_scheme, token_hash, signing_secret, created_at, revoked_at";
+
+/// Hash a bearer token for storage and lookup
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Generate a new random secret for a key
+pub fn generate_secret() -> String {
+    format!("pm_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Find a non-revoked bearer key by the hash of its token
+pub async fn find_active_by_token_hash(
+    pool: &PgPool,
+    token_hash: &str,
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE token_hash = $1 AND revoked_at IS NULL",
+        API_KEY_COLUMNS
+    ))
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Find a non-revoked key by id
+pub async fn find_active_by_id(pool: &PgPool, id: Uuid) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE id = $1 AND revoked_at IS NULL",
+        API_KEY_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Find a key by id, including revoked keys
+pub async fn find_by_id<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE id = $1",
+        API_KEY_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Create a key for a project
+///
+/// Returns the stored key and its secret. The secret is only ever
+/// returned here: bearer keys persist just its hash.
+pub async fn create<'e>(
+    executor: impl PgExecutor<'e>,
+    project_id: &str,
+    name: &str,
+    scheme: AuthScheme,
+) -> Result<(ApiKey, String), sqlx::Error> {
+    let secret = generate_secret();
+    let (token_hash, signing_secret) = match scheme {
+        AuthScheme::Bearer => (Some(hash_token(&secret)), None),
+        AuthScheme::Hmac => (None, Some(secret.clone())),
+    };
+
+    let key = sqlx::query_as::<_, ApiKey>(&format!(
+        "INSERT INTO api_keys (project_id, name, auth_scheme, token_hash, signing_secret) \
+         VALUES ($1, $2, $3, $4, $5) RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(project_id)
+    .bind(name)
+    .bind(scheme.as_str())
+    .bind(token_hash)
+    .bind(signing_secret)
+    .fetch_one(executor)
+    .await?;
+
+    Ok((key, secret))
+}
+
+/// Revoke a key, returning it if it was active
+pub async fn revoke<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(&format!(
+        "UPDATE api_keys SET revoked_at = NOW() \
+         WHERE id = $1 AND revoked_at IS NULL RETURNING {}",
+        API_KEY_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
@@ -0,0 +1,158 @@
+use sqlx::{PgExecutor, QueryBuilder};
+
+use crate::models::{AuditEntry, AuditPage, AuditQuery, NewAuditEntry};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Record an audit entry
+///
+/// Pass the transaction performing the change so the entry is only
+/// stored if the change itself commits.
+pub async fn record<'e>(
+    executor: impl PgExecutor<'e>,
+    entry: &NewAuditEntry,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO audit_log \
+         (actor, actor_method, action, resource_type, resource_id, project_id, request_id, before, after) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(&entry.actor)
+    .bind(&entry.actor_method)
+    .bind(&entry.action)
+    .bind(&entry.resource_type)
+    .bind(&entry.resource_id)
+    .bind(&entry.project_id)
+    .bind(&entry.request_id)
+    .bind(&entry.before)
+    .bind(&entry.after)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// List audit entries matching the query, newest first
+pub async fn list<'e>(
+    executor: impl PgExecutor<'e>,
+    query: &AuditQuery,
+) -> Result<AuditPage, sqlx::Error> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let mut builder = QueryBuilder::new(
+        "SELECT id, occurred_at, actor, actor_method, action, resource_type, resource_id, \
+         project_id, request_id, before, after FROM audit_log WHERE TRUE",
+    );
+
+    if let Some(actor) = &query.actor {
+        builder.push(" AND actor = ").push_bind(actor);
+    }
+    if let Some(action) = &query.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    if let Some(resource_type) = &query.resource_type {
+        builder
+            .push(" AND resource_type = ")
+            .push_bind(resource_type);
+    }
+    if let Some(resource_id) = &query.resource_id {
+        builder.push(" AND resource_id = ").push_bind(resource_id);
+    }
+    if let Some(project_id) = &query.project_id {
+        builder.push(" AND project_id = ").push_bind(project_id);
+    }
+    if let Some(before) = query.before {
+        builder.push(" AND id < ").push_bind(before);
+    }
+
+    // Fetch one extra row to know whether another page follows
+    builder
+        .push(" ORDER BY id DESC LIMIT ")
+        .push_bind(limit + 1);
+
+    let entries: Vec<AuditEntry> = builder.build_query_as().fetch_all(executor).await?;
+
+    Ok(page(entries, limit))
+}
+
+/// Page of the first `limit` entries, with a cursor if more were fetched
+fn page(mut entries: Vec<AuditEntry>, limit: i64) -> AuditPage {
+    let next_cursor = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|e| e.id)
+    } else {
+        None
+    };
+
+    AuditPage {
+        entries,
+        next_cursor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, http::Uri};
+
+    fn entries(ids: impl IntoIterator<Item = i64>) -> Vec<AuditEntry> {
+        ids.into_iter()
+            .map(|id| AuditEntry {
+                id,
+                occurred_at: chrono::Utc::now(),
+                actor: "alice".to_string(),
+                actor_method: "jwt".to_string(),
+                action: "project.update".to_string(),
+                resource_type: "project".to_string(),
+                resource_id: "web".to_string(),
+                project_id: Some("web".to_string()),
+                request_id: None,
+                before: None,
+                after: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_next_cursor() {
+        // Exactly a page: nothing follows
+        let page_of_three = page(entries([9, 8, 7]), 3);
+        assert_eq!(page_of_three.entries.len(), 3);
+        assert_eq!(page_of_three.next_cursor, None);
+
+        // The extra row is dropped and the cursor points at the last kept one
+        let first = page(entries([9, 8, 7, 6]), 3);
+        assert_eq!(
+            first.entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+            [9, 8, 7]
+        );
+        assert_eq!(first.next_cursor, Some(7));
+
+        assert_eq!(page(Vec::new(), 3).next_cursor, None);
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let first = page(entries([9, 8, 7, 6]), 3);
+        let body = serde_json::to_value(&first).unwrap();
+        assert_eq!(body["next_cursor"], 7);
+
+        // Clients pass the cursor back as `before`
+        let uri: Uri = format!("/api/admin/audit?before={}&limit=3", body["next_cursor"])
+            .parse()
+            .unwrap();
+        let Query(query) = Query::<AuditQuery>::try_from_uri(&uri).unwrap();
+        assert_eq!(query.before, Some(7));
+        assert_eq!(query.limit, Some(3));
+
+        let last = serde_json::to_value(page(entries([6]), 3)).unwrap();
+        assert!(last["next_cursor"].is_null());
+
+        let uri: Uri = "/api/admin/audit?before=abc".parse().unwrap();
+        assert!(Query::<AuditQuery>::try_from_uri(&uri).is_err());
+    }
+}
@@ -0,0 +1,695 @@
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::Config,
+    metrics::Metrics,
+    models::Event,
+    storage::{self, EventStore},
+};
+
+/// Batches the buffer can hold before ingestion is refused
+const QUEUE_CAPACITY: usize = 1024;
+/// Attempts at writing a chunk before its events are dropped
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait before retrying a failed write, doubled after each further failure
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// Failed events kept for retry; the oldest are dropped beyond this
+const MAX_RETRY_EVENTS: usize = 100_000;
+
+/// Returned when the buffer cannot accept more events
+#[derive(Debug, thiserror::Error)]
+#[error("Ingestion buffer is full")]
+pub struct BufferFull;
+
+/// Queue between the ingestion handler and the database
+///
+/// Events are written by a background flusher once `max_batch_size`
+/// events are pending or `buffer_flush_interval_ms` has passed since the
+/// oldest pending event arrived. Both settings are re-read on every
+/// cycle so a config reload applies immediately.
+///
+/// Ingestion answers before events are written, so a failed write is
+/// retried with backoff rather than reported to the client. Events still
+/// failing after `MAX_ATTEMPTS` are dropped and counted in
+/// `pulsemetrics_events_dropped_total`, as are events the database refuses
+/// outright, which are not retried.
+#[derive(Clone)]
+pub struct EventBuffer {
+    tx: mpsc::Sender<Vec<Event>>,
+    discards: mpsc::UnboundedSender<Discard>,
+    rx: Arc<Mutex<Option<Receivers>>>,
+    depth: Arc<AtomicUsize>,
+    last_flush: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl EventBuffer {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let (discards, discard_rx) = mpsc::unbounded_channel();
+        Self {
+            tx,
+            discards,
+            rx: Arc::new(Mutex::new(Some((rx, discard_rx)))),
+            depth: Arc::new(AtomicUsize::new(0)),
+            last_flush: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Queue events for insertion without waiting for the database
+    pub fn push(&self, events: Vec<Event>) -> Result<(), BufferFull> {
+        let count = events.len();
+        self.tx.try_send(events).map_err(|_| BufferFull)?;
+        self.depth.fetch_add(count, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Events accepted but not yet written
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Whether the queue has no room for another batch
+    pub fn is_full(&self) -> bool {
+        self.tx.capacity() == 0
+    }
+
+    /// When events were last written successfully
+    pub fn last_flush(&self) -> Option<DateTime<Utc>> {
+        *self.last_flush.lock().expect("buffer lock poisoned")
+    }
+
+    /// Drop the buffered events of a user, returning how many were dropped
+    ///
+    /// Events queued before the call are dropped too, so nothing of the
+    /// user is written once this returns.
+    pub async fn discard_user(&self, project_id: &str, user_id: &str) -> usize {
+        // Nothing is written before the flusher starts
+        if self.rx.lock().expect("buffer lock poisoned").is_some() {
+            return 0;
+        }
+
+        let (done, discarded) = oneshot::channel();
+        let discard = Discard {
+            project_id: project_id.to_string(),
+            user_id: user_id.to_string(),
+            done,
+        };
+        if self.discards.send(discard).is_err() {
+            return 0;
+        }
+        discarded.await.unwrap_or(0)
+    }
+
+    /// Spawn the flusher, which drains the buffer once `shutdown` is cancelled
+    ///
+    /// # Panics
+    ///
+    /// If called more than once.
+    pub fn start(
+        &self,
+        store: Arc<dyn EventStore>,
+        config: Arc<ArcSwap<Config>>,
+        metrics: Arc<Metrics>,
+        shutdown: CancellationToken,
+    ) -> JoinHandle<()> {
+        let (rx, discards) = self
+            .rx
+            .lock()
+            .expect("buffer lock poisoned")
+            .take()
+            .expect("event buffer flusher already started");
+
+        let flusher = Flusher {
+            store,
+            config,
+            metrics,
+            depth: self.depth.clone(),
+            last_flush: self.last_flush.clone(),
+            retries: VecDeque::new(),
+            retry_events: 0,
+        };
+        tokio::spawn(flusher.run(rx, discards, shutdown))
+    }
+}
+
+impl Default for EventBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Receivers = (mpsc::Receiver<Vec<Event>>, mpsc::UnboundedReceiver<Discard>);
+
+/// Request to drop the buffered events of a user
+struct Discard {
+    project_id: String,
+    user_id: String,
+    done: oneshot::Sender<usize>,
+}
+
+struct Flusher {
+    store: Arc<dyn EventStore>,
+    config: Arc<ArcSwap<Config>>,
+    metrics: Arc<Metrics>,
+    depth: Arc<AtomicUsize>,
+    last_flush: Arc<Mutex<Option<DateTime<Utc>>>>,
+    /// Chunks whose write failed, oldest first
+    retries: VecDeque<Retry>,
+    /// Events held in `retries`
+    retry_events: usize,
+}
+
+/// A chunk waiting to be written again
+struct Retry {
+    events: Vec<Event>,
+    attempts: u32,
+    at: Instant,
+}
+
+impl Flusher {
+    async fn run(
+        mut self,
+        mut rx: mpsc::Receiver<Vec<Event>>,
+        mut discards: mpsc::UnboundedReceiver<Discard>,
+        shutdown: CancellationToken,
+    ) {
+        let mut pending: Vec<Event> = Vec::new();
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            let (max_batch_size, interval) = {
+                let config = self.config.load();
+                (
+                    config.app.max_batch_size,
+                    Duration::from_millis(config.app.buffer_flush_interval_ms),
+                )
+            };
+            let next_retry = self.retries.iter().map(|r| r.at).min();
+
+            tokio::select! {
+                received = rx.recv() => match received {
+                    Some(events) => {
+                        deadline.get_or_insert_with(|| Instant::now() + interval);
+                        pending.extend(events);
+                        if pending.len() < max_batch_size {
+                            continue;
+                        }
+                    }
+                    None => break,
+                },
+                Some(discard) = discards.recv() => {
+                    // Include batches queued before the request
+                    while let Ok(events) = rx.try_recv() {
+                        deadline.get_or_insert_with(|| Instant::now() + interval);
+                        pending.extend(events);
+                    }
+                    let count = self.discard(&mut pending, &discard.project_id, &discard.user_id);
+                    let _ = discard.done.send(count);
+                    if pending.is_empty() {
+                        deadline = None;
+                    }
+                    if pending.len() < max_batch_size {
+                        continue;
+                    }
+                }
+                _ = sleep_until(deadline) => {}
+                _ = sleep_until(next_retry) => {
+                    self.retry_due(Instant::now()).await;
+                    continue;
+                }
+                _ = shutdown.cancelled() => break,
+            }
+
+            self.flush(&mut pending, max_batch_size).await;
+            deadline = None;
+        }
+
+        // Write whatever was accepted before shutting down
+        rx.close();
+        while let Ok(events) = rx.try_recv() {
+            pending.extend(events);
+        }
+        let max_batch_size = self.config.load().app.max_batch_size;
+        self.flush(&mut pending, max_batch_size).await;
+
+        // Failed chunks get one last attempt, without waiting out their backoff
+        while let Some(retry) = self.retries.pop_front() {
+            self.retry_events -= retry.events.len();
+            match self.store.insert(&retry.events).await {
+                Ok(()) => self.written(retry.events.len()),
+                Err(e) => {
+                    tracing::error!(
+                        "Dropping {} buffered events at shutdown: {:?}",
+                        retry.events.len(),
+                        e
+                    );
+                    self.dropped(retry.events.len(), "shutdown");
+                }
+            }
+        }
+
+        tracing::info!("Event buffer drained");
+    }
+
+    #[tracing::instrument(name = "buffer_flush", skip_all, fields(events = pending.len()))]
+    async fn flush(&mut self, pending: &mut Vec<Event>, max_batch_size: usize) {
+        let max_batch_size = max_batch_size.max(1);
+        while !pending.is_empty() {
+            let rest = pending.split_off(max_batch_size.min(pending.len()));
+            let chunk = std::mem::replace(pending, rest);
+            self.write(chunk, 0).await;
+        }
+    }
+
+    /// Remove the events of a user from `pending` and the retries
+    fn discard(&mut self, pending: &mut Vec<Event>, project_id: &str, user_id: &str) -> usize {
+        let of_user =
+            |e: &Event| e.project_id == project_id && e.user_id.as_deref() == Some(user_id);
+
+        let before = pending.len();
+        pending.retain(|e| !of_user(e));
+        let mut count = before - pending.len();
+
+        for retry in &mut self.retries {
+            let before = retry.events.len();
+            retry.events.retain(|e| !of_user(e));
+            let removed = before - retry.events.len();
+            self.retry_events -= removed;
+            count += removed;
+        }
+        self.retries.retain(|r| !r.events.is_empty());
+
+        self.depth.fetch_sub(count, Ordering::Relaxed);
+        count
+    }
+
+    /// Write the failed chunks whose backoff has passed
+    async fn retry_due(&mut self, now: Instant) {
+        let (due, waiting) = std::mem::take(&mut self.retries)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|r| r.at <= now);
+        self.retries = waiting;
+        for retry in due {
+            self.retry_events -= retry.events.len();
+            self.write(retry.events, retry.attempts).await;
+        }
+    }
+
+    /// Write a chunk, keeping it for a later attempt if that fails
+    ///
+    /// A chunk the database refuses outright is halved until the refused
+    /// events are isolated, so they do not take the rest down with them.
+    async fn write(&mut self, events: Vec<Event>, attempts: u32) {
+        let mut chunks = vec![events];
+        while let Some(mut events) = chunks.pop() {
+            let count = events.len();
+            let Err(e) = self.store.insert(&events).await else {
+                self.written(count);
+                tracing::debug!("Flushed {} events", count);
+                continue;
+            };
+
+            if !storage::is_permanent_error(&e) {
+                self.retry(events, attempts, e);
+            } else if count > 1 {
+                let rest = events.split_off(count / 2);
+                chunks.push(rest);
+                chunks.push(events);
+            } else {
+                tracing::error!("Dropping a buffered event the database refused: {:?}", e);
+                self.dropped(count, "rejected");
+            }
+        }
+    }
+
+    /// Keep a chunk whose write failed for a later attempt
+    fn retry(&mut self, events: Vec<Event>, attempts: u32, e: anyhow::Error) {
+        let count = events.len();
+        let attempts = attempts + 1;
+        if attempts >= MAX_ATTEMPTS {
+            tracing::error!(
+                "Dropping {} buffered events after {} failed writes: {:?}",
+                count,
+                attempts,
+                e
+            );
+            self.dropped(count, "retries_exhausted");
+            return;
+        }
+
+        let backoff = RETRY_BACKOFF * 2u32.pow(attempts - 1);
+        tracing::warn!(
+            "Failed to write {} buffered events, retrying in {:?}: {:?}",
+            count,
+            backoff,
+            e
+        );
+        self.retry_events += count;
+        self.retries.push_back(Retry {
+            events,
+            attempts,
+            at: Instant::now() + backoff,
+        });
+
+        // Bound the memory held by a database that stays down
+        while self.retry_events > MAX_RETRY_EVENTS {
+            let Some(oldest) = self.retries.pop_front() else {
+                break;
+            };
+            self.retry_events -= oldest.events.len();
+            tracing::error!(
+                "Dropping {} buffered events, too many are waiting to be retried",
+                oldest.events.len()
+            );
+            self.dropped(oldest.events.len(), "retry_queue_full");
+        }
+    }
+
+    fn written(&self, count: usize) {
+        *self.last_flush.lock().expect("buffer lock poisoned") = Some(Utc::now());
+        self.depth.fetch_sub(count, Ordering::Relaxed);
+    }
+
+    fn dropped(&self, count: usize, reason: &str) {
+        self.metrics
+            .events_dropped
+            .with_label_values(&[reason])
+            .inc_by(count as u64);
+        self.depth.fetch_sub(count, Ordering::Relaxed);
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{AggregateBucket, AggregateQuery, EventQuery, EventRange},
+        storage::{MemoryStore, SqliteStore},
+    };
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicU32;
+    use uuid::Uuid;
+
+    /// Fails the first `failures` inserts, then stores in memory
+    struct FailingStore {
+        failures: AtomicU32,
+        inner: MemoryStore,
+    }
+
+    impl FailingStore {
+        fn new(failures: u32) -> Self {
+            Self {
+                failures: AtomicU32::new(failures),
+                inner: MemoryStore::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl EventStore for FailingStore {
+        async fn insert(&self, events: &[Event]) -> anyhow::Result<()> {
+            let failures = self.failures.load(Ordering::Relaxed);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::Relaxed);
+                anyhow::bail!("database unavailable");
+            }
+            self.inner.insert(events).await
+        }
+
+        async fn query(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+            self.inner.query(query).await
+        }
+
+        async fn aggregate(&self, query: &AggregateQuery) -> anyhow::Result<Vec<AggregateBucket>> {
+            self.inner.aggregate(query).await
+        }
+
+        async fn delete(&self, range: &EventRange) -> anyhow::Result<u64> {
+            self.inner.delete(range).await
+        }
+
+        async fn count(&self, range: &EventRange) -> anyhow::Result<u64> {
+            self.inner.count(range).await
+        }
+
+        async fn scan(
+            &self,
+            range: &EventRange,
+            after: Option<(DateTime<Utc>, Uuid)>,
+            limit: u32,
+        ) -> anyhow::Result<Vec<Event>> {
+            self.inner.scan(range, after, limit).await
+        }
+
+        async fn health(&self) -> anyhow::Result<()> {
+            self.inner.health().await
+        }
+    }
+
+    /// Refuses chunks holding an `invalid` event, as a constraint would
+    struct RejectingStore {
+        checks: sqlx::SqlitePool,
+        inner: MemoryStore,
+    }
+
+    impl RejectingStore {
+        async fn new() -> Self {
+            let checks = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            sqlx::query("CREATE TABLE checks (event_type TEXT NOT NULL)")
+                .execute(&checks)
+                .await
+                .unwrap();
+            Self {
+                checks,
+                inner: MemoryStore::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl EventStore for RejectingStore {
+        async fn insert(&self, events: &[Event]) -> anyhow::Result<()> {
+            if events.iter().any(|e| e.event_type == "invalid") {
+                sqlx::query("INSERT INTO checks VALUES (NULL)")
+                    .execute(&self.checks)
+                    .await?;
+            }
+            self.inner.insert(events).await
+        }
+
+        async fn query(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+            self.inner.query(query).await
+        }
+
+        async fn aggregate(&self, query: &AggregateQuery) -> anyhow::Result<Vec<AggregateBucket>> {
+            self.inner.aggregate(query).await
+        }
+
+        async fn delete(&self, range: &EventRange) -> anyhow::Result<u64> {
+            self.inner.delete(range).await
+        }
+
+        async fn count(&self, range: &EventRange) -> anyhow::Result<u64> {
+            self.inner.count(range).await
+        }
+
+        async fn scan(
+            &self,
+            range: &EventRange,
+            after: Option<(DateTime<Utc>, Uuid)>,
+            limit: u32,
+        ) -> anyhow::Result<Vec<Event>> {
+            self.inner.scan(range, after, limit).await
+        }
+
+        async fn health(&self) -> anyhow::Result<()> {
+            self.inner.health().await
+        }
+    }
+
+    fn events(count: usize) -> Vec<Event> {
+        (0..count).map(|_| Event::fixture("web")).collect()
+    }
+
+    fn start(
+        buffer: &EventBuffer,
+        store: Arc<dyn EventStore>,
+    ) -> (Arc<Metrics>, CancellationToken) {
+        let config = Config::load_from(None, |_| None).unwrap();
+        let metrics = Arc::new(Metrics::new());
+        let shutdown = CancellationToken::new();
+        buffer.start(
+            store,
+            Arc::new(ArcSwap::from_pointee(config)),
+            metrics.clone(),
+            shutdown.clone(),
+        );
+        (metrics, shutdown)
+    }
+
+    fn dropped(metrics: &Metrics, reason: &str) -> u64 {
+        metrics.events_dropped.with_label_values(&[reason]).get()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_writes_are_retried() {
+        let buffer = EventBuffer::new();
+        let store = Arc::new(FailingStore::new(2));
+        let (metrics, _shutdown) = start(&buffer, store.clone());
+
+        buffer.push(events(3)).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(store.inner.len(), 0);
+        assert_eq!(buffer.depth(), 3);
+
+        // Two failures back off 500ms, then 1s
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(store.inner.len(), 3);
+        assert_eq!(buffer.depth(), 0);
+        assert!(buffer.last_flush().is_some());
+        assert_eq!(dropped(&metrics, "retries_exhausted"), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_are_dropped_after_repeated_failures() {
+        let buffer = EventBuffer::new();
+        let store = Arc::new(FailingStore::new(u32::MAX));
+        let (metrics, _shutdown) = start(&buffer, store.clone());
+
+        buffer.push(events(3)).unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        assert_eq!(store.inner.len(), 0);
+        assert_eq!(buffer.depth(), 0);
+        assert_eq!(dropped(&metrics, "retries_exhausted"), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_retries_failed_writes_once() {
+        let buffer = EventBuffer::new();
+        let store = Arc::new(FailingStore::new(1));
+        let (metrics, shutdown) = start(&buffer, store.clone());
+
+        buffer.push(events(2)).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(store.inner.len(), 0);
+
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(store.inner.len(), 2);
+        assert_eq!(buffer.depth(), 0);
+        assert_eq!(dropped(&metrics, "shutdown"), 0);
+    }
+
+    fn events_of(project_id: &str, user_id: &str, count: usize) -> Vec<Event> {
+        (0..count)
+            .map(|_| Event::fixture(project_id).user_id(user_id))
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_discarded_users_are_never_written() {
+        let buffer = EventBuffer::new();
+        let store = Arc::new(FailingStore::new(1));
+        let (_metrics, _shutdown) = start(&buffer, store.clone());
+
+        // A failed chunk waiting to be retried...
+        buffer.push(events_of("web", "jane", 2)).unwrap();
+        buffer.push(events_of("web", "joe", 1)).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(store.inner.len(), 0);
+
+        // ...and batches not flushed yet
+        buffer.push(events_of("web", "jane", 1)).unwrap();
+        buffer.push(events_of("shop", "jane", 1)).unwrap();
+
+        assert_eq!(buffer.discard_user("web", "jane").await, 3);
+        assert_eq!(buffer.depth(), 2);
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(store.inner.len(), 2);
+        let jane = EventRange {
+            user_id: Some("jane".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(store.count(&jane).await.unwrap(), 1);
+        assert_eq!(buffer.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_discard_before_start() {
+        let buffer = EventBuffer::new();
+        buffer.push(events_of("web", "jane", 1)).unwrap();
+        assert_eq!(buffer.discard_user("web", "jane").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_refused_events_spare_their_chunk() {
+        let buffer = EventBuffer::new();
+        let store = Arc::new(RejectingStore::new().await);
+        let (metrics, _shutdown) = start(&buffer, store.clone());
+
+        let mut batch = events(6);
+        batch[4].event_type = "invalid".to_string();
+        buffer.push(batch).unwrap();
+
+        // Written well before a retry would be
+        tokio::time::sleep(RETRY_BACKOFF / 2).await;
+        assert_eq!(store.inner.len(), 5);
+        assert_eq!(buffer.depth(), 0);
+        assert_eq!(dropped(&metrics, "rejected"), 1);
+        assert_eq!(dropped(&metrics, "retries_exhausted"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_resent_events_are_written_once() {
+        let directory = std::env::temp_dir().join(format!("pulsemetrics-{}", Uuid::new_v4()));
+        let url = format!("sqlite://{}/events.db", directory.display());
+        let store = Arc::new(SqliteStore::connect(&url).await.unwrap());
+
+        // A client retrying after a timeout resends an event already stored
+        let resent = events(1);
+        store.insert(&resent).await.unwrap();
+
+        let buffer = EventBuffer::new();
+        let (metrics, shutdown) = start(&buffer, store.clone());
+        buffer.push(events(2)).unwrap();
+        buffer.push(resent).unwrap();
+        buffer.push(events(2)).unwrap();
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stored = store.count(&EventRange::default()).await.unwrap();
+        std::fs::remove_dir_all(&directory).ok();
+        assert_eq!(stored, 5);
+        assert_eq!(buffer.depth(), 0);
+        assert_eq!(dropped(&metrics, "rejected"), 0);
+        assert_eq!(dropped(&metrics, "shutdown"), 0);
+    }
+}
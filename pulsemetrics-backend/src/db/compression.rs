@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+
+use crate::{config::CompressionConfig, db::events, models::EventRange};
+
+/// Storage used by a chunk of the `events` hypertable
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ChunkSize {
+    /// `schema.name` of the chunk table
+    pub chunk: String,
+    pub range_start: DateTime<Utc>,
+    pub range_end: DateTime<Utc>,
+    pub compressed: bool,
+    /// Size before compression, or the current size of an uncompressed chunk
+    pub uncompressed_bytes: i64,
+    /// Size after compression, `None` for an uncompressed chunk
+    pub compressed_bytes: Option<i64>,
+}
+
+/// Make the compression policy of `events` match `config`
+///
+/// The policy is only replaced when its interval changed, so restarts do
+/// not reschedule the background job.
+pub async fn apply_policy(pool: &PgPool, config: &CompressionConfig) -> Result<(), sqlx::Error> {
+    let days = i32::try_from(config.compress_after_days).unwrap_or(i32::MAX);
+    let mut tx = pool.begin().await?;
+
+    // NULL without a policy, otherwise whether it already has this interval
+    let current: Option<bool> = sqlx::query_scalar(
+        "SELECT (config->>'compress_after')::interval = make_interval(days => $1) \
+         FROM timescaledb_information.jobs \
+         WHERE proc_name = 'policy_compression' AND hypertable_name = 'events'",
+    )
+    .bind(days)
+    .fetch_optional(&mut *tx)
+    .await?
+    .flatten();
+
+    match (current, days) {
+        (None, 0) | (Some(true), _) => return Ok(()),
+        (Some(_), 0) => {
+            sqlx::query("SELECT remove_compression_policy('events')")
+                .execute(&mut *tx)
+                .await?;
+            tracing::info!("Removed the events compression policy");
+        }
+        _ => {
+            sqlx::query("SELECT remove_compression_policy('events', if_exists => TRUE)")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("SELECT add_compression_policy('events', make_interval(days => $1))")
+                .bind(days)
+                .execute(&mut *tx)
+                .await?;
+            tracing::info!("Compressing event chunks older than {} days", days);
+        }
+    }
+
+    tx.commit().await
+}
+
+/// Compression state and size of every chunk, oldest first
+pub async fn chunk_sizes(pool: &PgPool) -> Result<Vec<ChunkSize>, sqlx::Error> {
+    sqlx::query_as::<_, ChunkSize>(
+        "SELECT c.chunk_schema || '.' || c.chunk_name AS chunk, \
+                c.range_start, c.range_end, c.is_compressed AS compressed, \
+                COALESCE(s.before_compression_total_bytes, \
+                         pg_total_relation_size(format('%I.%I', c.chunk_schema, c.chunk_name)::regclass) \
+                ) AS uncompressed_bytes, \
+                s.after_compression_total_bytes AS compressed_bytes \
+         FROM timescaledb_information.chunks c \
+         LEFT JOIN chunk_compression_stats('events') s \
+           ON s.chunk_schema = c.chunk_schema AND s.chunk_name = c.chunk_name \
+         WHERE c.hypertable_name = 'events' \
+         ORDER BY c.range_start",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Whether a write failed because it targeted a compressed chunk
+///
+/// TimescaleDB before 2.11 cannot insert into or delete from compressed
+/// chunks; later versions handle such writes and never return this error.
+pub fn is_compressed_chunk_error(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(db) => {
+            db.code().as_deref() == Some("0A000") && db.message().contains("compressed")
+        }
+        _ => false,
+    }
+}
+
+/// Decompress the chunks holding any of `times`, returning how many were
+///
+/// Only those chunks, so one event with a stray timestamp does not
+/// decompress every chunk between it and the rest of its batch. The
+/// compression policy compresses them again once they are old enough.
+pub async fn decompress_at(
+    conn: &mut PgConnection,
+    times: &[DateTime<Utc>],
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(decompress_chunk(format('%I.%I', c.chunk_schema, c.chunk_name)::regclass, \
+                                      if_compressed => TRUE)) \
+         FROM timescaledb_information.chunks c \
+         WHERE c.hypertable_name = 'events' AND c.is_compressed \
+           AND EXISTS (SELECT 1 FROM unnest($1::timestamptz[]) AS t(time) \
+                       WHERE t.time >= c.range_start AND t.time < c.range_end)",
+    )
+    .bind(times)
+    .fetch_one(conn)
+    .await
+}
+
+/// Decompress the chunks holding events of `range`, returning how many were
+pub async fn decompress_matching(
+    conn: &mut PgConnection,
+    range: &EventRange,
+) -> Result<i64, sqlx::Error> {
+    matching_chunks(range)
+        .build_query_scalar()
+        .fetch_one(conn)
+        .await
+}
+
+fn matching_chunks(range: &EventRange) -> QueryBuilder<'static, Postgres> {
+    let mut builder = QueryBuilder::new(
+        "SELECT COUNT(decompress_chunk(format('%I.%I', c.chunk_schema, c.chunk_name)::regclass, \
+                                      if_compressed => TRUE)) \
+         FROM timescaledb_information.chunks c \
+         WHERE c.hypertable_name = 'events' AND c.is_compressed \
+           AND EXISTS (SELECT 1 FROM events",
+    );
+    events::push_range(range, &mut builder);
+    builder.push(" AND time >= c.range_start AND time < c.range_end)");
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_chunks_are_limited_to_the_range() {
+        let range = EventRange {
+            project_id: Some("web".to_string()),
+            user_id: Some("alice".to_string()),
+            ..Default::default()
+        };
+
+        let sql = matching_chunks(&range).into_sql();
+
+        assert!(sql.ends_with(
+            "AND EXISTS (SELECT 1 FROM events WHERE TRUE AND project_id = $1 AND user_id = $2 \
+             AND time >= c.range_start AND time < c.range_end)"
+        ));
+    }
+}
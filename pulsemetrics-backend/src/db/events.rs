@@ -0,0 +1,252 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::models::{AggregateBucket, AggregateQuery, Event, EventQuery, EventRange, ProjectUsage};
+
+/// Rows per INSERT, keeping under Postgres' limit of 65535 bind parameters
+pub const MAX_ROWS_PER_INSERT: usize = 65535 / 8;
+
+pub(crate) fn push_range(range: &EventRange, builder: &mut QueryBuilder<'_, Postgres>) {
+    builder.push(" WHERE TRUE");
+    if let Some(project_id) = &range.project_id {
+        builder
+            .push(" AND project_id = ")
+            .push_bind(project_id.clone());
+    }
+    if let Some(user_id) = &range.user_id {
+        builder.push(" AND user_id = ").push_bind(user_id.clone());
+    }
+    if let Some(from) = range.from {
+        builder.push(" AND time >= ").push_bind(from);
+    }
+    if let Some(to) = range.to {
+        builder.push(" AND time < ").push_bind(to);
+    }
+}
+
+/// Insert events in a single statement
+///
+/// Callers must split batches larger than [`MAX_ROWS_PER_INSERT`].
+#[tracing::instrument(
+    name = "insert_events",
+    skip_all,
+    fields(db.system = "postgresql", db.operation = "INSERT", rows = events.len())
+)]
+pub async fn insert<'e>(
+    executor: impl PgExecutor<'e>,
+    events: &[Event],
+) -> Result<(), sqlx::Error> {
+    let mut query_builder = QueryBuilder::new(
+        "INSERT INTO events (id, time, project_id, event_type, properties, user_id, session_id, value) ",
+    );
+
+    query_builder.push_values(events, |mut b, event| {
+        b.push_bind(event.id)
+            .push_bind(event.time)
+            .push_bind(&event.project_id)
+            .push_bind(&event.event_type)
+            .push_bind(&event.properties)
+            .push_bind(&event.user_id)
+            .push_bind(event.session_id)
+            .push_bind(event.value);
+    });
+    // A client resending an event must not fail the batch it was buffered with
+    query_builder.push(" ON CONFLICT (id, time) DO NOTHING");
+
+    query_builder.build().execute(executor).await?;
+
+    Ok(())
+}
+
+/// A project's events matching `query`, newest first
+pub async fn list<'e>(
+    executor: impl PgExecutor<'e>,
+    query: &EventQuery,
+) -> Result<Vec<Event>, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "SELECT id, time, project_id, event_type, properties, user_id, session_id, value \
+         FROM events WHERE project_id = ",
+    );
+    builder.push_bind(&query.project_id);
+    if let Some(event_type) = &query.event_type {
+        builder.push(" AND event_type = ").push_bind(event_type);
+    }
+    if let Some(user_id) = &query.user_id {
+        builder.push(" AND user_id = ").push_bind(user_id);
+    }
+    if let Some(from) = query.from {
+        builder.push(" AND time >= ").push_bind(from);
+    }
+    if let Some(to) = query.to {
+        builder.push(" AND time < ").push_bind(to);
+    }
+    builder
+        .push(" ORDER BY time DESC LIMIT ")
+        .push_bind(i64::from(query.limit));
+
+    builder.build_query_as().fetch_all(executor).await
+}
+
+/// Per-bucket statistics of a project's events, oldest bucket first
+pub async fn aggregate<'e>(
+    executor: impl PgExecutor<'e>,
+    query: &AggregateQuery,
+) -> Result<Vec<AggregateBucket>, sqlx::Error> {
+    let bucket_seconds = query.interval.duration().num_seconds();
+
+    sqlx::query_as::<_, AggregateBucket>(
+        "SELECT time_bucket(make_interval(secs => $1), time) AS bucket, \
+                COUNT(*) AS count, COUNT(DISTINCT user_id) AS unique_users, \
+                SUM(value) AS sum, AVG(value) AS avg \
+         FROM events \
+         WHERE project_id = $2 AND ($3::text IS NULL OR event_type = $3) \
+           AND time >= $4 AND time < $5 \
+         GROUP BY bucket \
+         ORDER BY bucket",
+    )
+    .bind(bucket_seconds as f64)
+    .bind(&query.project_id)
+    .bind(&query.event_type)
+    .bind(query.from)
+    .bind(query.to)
+    .fetch_all(executor)
+    .await
+}
+
+/// Event counts per project, including projects without events
+///
+/// Events whose project was never registered are reported too.
+pub async fn usage<'e>(
+    executor: impl PgExecutor<'e>,
+    project_id: Option<&str>,
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<ProjectUsage>, sqlx::Error> {
+    sqlx::query_as::<_, ProjectUsage>(
+        "WITH counts AS ( \
+             SELECT project_id, COUNT(*) AS events, MIN(time) AS first_event, MAX(time) AS last_event \
+             FROM events \
+             WHERE ($1::text IS NULL OR project_id = $1) AND ($2::timestamptz IS NULL OR time >= $2) \
+             GROUP BY project_id \
+         ), keys AS ( \
+             SELECT project_id, COUNT(*) AS active_keys FROM api_keys \
+             WHERE revoked_at IS NULL GROUP BY project_id \
+         ) \
+         SELECT COALESCE(p.id, c.project_id) AS project_id, \
+                COALESCE(c.events, 0) AS events, c.first_event, c.last_event, \
+                COALESCE(k.active_keys, 0) AS active_keys \
+         FROM (SELECT id FROM projects WHERE $1::text IS NULL OR id = $1) p \
+         FULL OUTER JOIN counts c ON c.project_id = p.id \
+         LEFT JOIN keys k ON k.project_id = COALESCE(p.id, c.project_id) \
+         ORDER BY 1",
+    )
+    .bind(project_id)
+    .bind(since)
+    .fetch_all(executor)
+    .await
+}
+
+/// Number of events in a range
+pub async fn count<'e>(
+    executor: impl PgExecutor<'e>,
+    range: &EventRange,
+) -> Result<i64, sqlx::Error> {
+    let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM events");
+    push_range(range, &mut builder);
+
+    builder.build_query_scalar().fetch_one(executor).await
+}
+
+/// Delete the events in a range, returning how many were deleted
+pub async fn purge<'e>(
+    executor: impl PgExecutor<'e>,
+    range: &EventRange,
+) -> Result<u64, sqlx::Error> {
+    let mut builder = QueryBuilder::new("DELETE FROM events");
+    push_range(range, &mut builder);
+
+    let result = builder.build().execute(executor).await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Up to `limit` events of a range ordered by `(time, id)`, after `after`
+pub async fn scan<'e>(
+    executor: impl PgExecutor<'e>,
+    range: &EventRange,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    limit: u32,
+) -> Result<Vec<Event>, sqlx::Error> {
+    let mut builder = QueryBuilder::new(
+        "SELECT id, time, project_id, event_type, properties, user_id, session_id, value \
+         FROM events",
+    );
+    push_range(range, &mut builder);
+    if let Some((time, id)) = after {
+        builder
+            .push(" AND (time, id) > (")
+            .push_bind(time)
+            .push(", ")
+            .push_bind(id)
+            .push(")");
+    }
+    builder
+        .push(" ORDER BY time, id LIMIT ")
+        .push_bind(i64::from(limit));
+
+    builder.build_query_as().fetch_all(executor).await
+}
+
+/// Delete at most `limit` events of a range, returning how many were deleted
+pub async fn purge_batch<'e>(
+    executor: impl PgExecutor<'e>,
+    range: &EventRange,
+    limit: u64,
+) -> Result<u64, sqlx::Error> {
+    let mut builder =
+        QueryBuilder::new("DELETE FROM events WHERE (id, time) IN (SELECT id, time FROM events");
+    push_range(range, &mut builder);
+    builder
+        .push(" LIMIT ")
+        .push_bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .push(")");
+
+    let result = builder.build().execute(executor).await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_filters() {
+        let from = "2024-05-01T00:00:00Z".parse().unwrap();
+        let to = "2024-06-01T00:00:00Z".parse().unwrap();
+
+        // Every combination of filters, each bound in the order it is pushed
+        for mask in 0..16 {
+            let range = EventRange {
+                project_id: (mask & 1 != 0).then(|| "web".to_string()),
+                user_id: (mask & 2 != 0).then(|| "alice".to_string()),
+                from: (mask & 4 != 0).then_some(from),
+                to: (mask & 8 != 0).then_some(to),
+            };
+            let conditions = ["project_id = ", "user_id = ", "time >= ", "time < "];
+            let mut expected = "SELECT COUNT(*) FROM events WHERE TRUE".to_string();
+            let mut param = 0;
+            for (bit, condition) in conditions.iter().enumerate() {
+                if mask & (1 << bit) != 0 {
+                    param += 1;
+                    expected.push_str(&format!(" AND {}${}", condition, param));
+                }
+            }
+
+            let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM events");
+            push_range(&range, &mut builder);
+
+            assert_eq!(builder.into_sql(), expected, "filters {:04b}", mask);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// Salt of the period starting at `period_start`, storing `candidate` when it has none yet
+///
+/// Every instance thus agrees on the salt; those of earlier periods are deleted.
+pub async fn current(
+    pool: &PgPool,
+    period_start: DateTime<Utc>,
+    candidate: &[u8],
+) -> Result<Vec<u8>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "INSERT INTO ip_salts (period_start, salt) VALUES ($1, $2) \
+         ON CONFLICT (period_start) DO NOTHING",
+    )
+    .bind(period_start)
+    .bind(candidate)
+    .execute(&mut *tx)
+    .await?;
+
+    let salt = sqlx::query_scalar("SELECT salt FROM ip_salts WHERE period_start = $1")
+        .bind(period_start)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM ip_salts WHERE period_start < $1")
+        .bind(period_start)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(salt)
+}
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::models::{DataJob, ExportFormat, JobKind, JobStatus, Principal};
+
+const JOB_COLUMNS: &str = "id, kind, project_id, user_id, status, requested_by, requested_method, \
+     request_id, format, output_path, events_affected, error, created_at, started_at, completed_at";
+
+/// Queue a job for a user's events
+pub async fn create<'e>(
+    executor: impl PgExecutor<'e>,
+    kind: JobKind,
+    project_id: &str,
+    user_id: &str,
+    format: Option<ExportFormat>,
+    principal: &Principal,
+    request_id: Option<&str>,
+) -> Result<DataJob, sqlx::Error> {
+    sqlx::query_as::<_, DataJob>(&format!(
+        "INSERT INTO data_jobs \
+         (kind, project_id, user_id, format, requested_by, requested_method, request_id) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+        JOB_COLUMNS
+    ))
+    .bind(kind.as_str())
+    .bind(project_id)
+    .bind(user_id)
+    .bind(format.map(|f| f.as_str()))
+    .bind(&principal.subject)
+    .bind(principal.method.as_str())
+    .bind(request_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Find a job of a project
+pub async fn find<'e>(
+    executor: impl PgExecutor<'e>,
+    project_id: &str,
+    id: Uuid,
+) -> Result<Option<DataJob>, sqlx::Error> {
+    sqlx::query_as::<_, DataJob>(&format!(
+        "SELECT {} FROM data_jobs WHERE id = $1 AND project_id = $2",
+        JOB_COLUMNS
+    ))
+    .bind(id)
+    .bind(project_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Jobs not yet completed or failed, oldest first
+pub async fn unfinished<'e>(executor: impl PgExecutor<'e>) -> Result<Vec<DataJob>, sqlx::Error> {
+    sqlx::query_as::<_, DataJob>(&format!(
+        "SELECT {} FROM data_jobs WHERE status IN ('pending', 'running') ORDER BY created_at",
+        JOB_COLUMNS
+    ))
+    .fetch_all(executor)
+    .await
+}
+
+/// Mark a job as running
+pub async fn start<'e>(executor: impl PgExecutor<'e>, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE data_jobs SET status = $2, started_at = NOW() WHERE id = $1")
+        .bind(id)
+        .bind(JobStatus::Running.as_str())
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Mark a job as completed after affecting `events` events
+pub async fn complete<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+    events: i64,
+    output_path: Option<&str>,
+) -> Result<DataJob, sqlx::Error> {
+    sqlx::query_as::<_, DataJob>(&format!(
+        "UPDATE data_jobs SET status = $2, events_affected = $3, output_path = $4, error = NULL, \
+         completed_at = NOW() WHERE id = $1 RETURNING {}",
+        JOB_COLUMNS
+    ))
+    .bind(id)
+    .bind(JobStatus::Completed.as_str())
+    .bind(events)
+    .bind(output_path)
+    .fetch_one(executor)
+    .await
+}
+
+/// Exports whose file was completed before `before`
+pub async fn expired_exports<'e>(
+    executor: impl PgExecutor<'e>,
+    before: DateTime<Utc>,
+) -> Result<Vec<DataJob>, sqlx::Error> {
+    sqlx::query_as::<_, DataJob>(&format!(
+        "SELECT {} FROM data_jobs WHERE output_path IS NOT NULL AND completed_at < $1",
+        JOB_COLUMNS
+    ))
+    .bind(before)
+    .fetch_all(executor)
+    .await
+}
+
+/// Forget the file of an export after deleting it
+pub async fn clear_output<'e>(executor: impl PgExecutor<'e>, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE data_jobs SET output_path = NULL WHERE id = $1")
+        .bind(id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Mark a job as failed
+pub async fn fail<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE data_jobs SET status = $2, error = $3, completed_at = NOW() WHERE id = $1")
+        .bind(id)
+        .bind(JobStatus::Failed.as_str())
+        .bind(error)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
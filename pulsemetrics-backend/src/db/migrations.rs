@@ -0,0 +1,379 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::{Migration, MigrationType, Migrator},
+    PgConnection, PgPool,
+};
+use std::{collections::HashMap, time::Instant};
+
+/// Migrations embedded from the `migrations/` directory at compile time
+///
+/// Files are named `<version>_<description>.sql`, or `.up.sql` plus an
+/// optional `.down.sql` for migrations that can be rolled back. A script
+/// starting with `-- no-transaction` runs outside a transaction.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Arbitrary key for the advisory lock serializing concurrent runners
+const MIGRATION_LOCK_ID: i64 = 0x7075_6c73_656d_6967;
+
+/// Row of the `schema_migrations` tracking table
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: String,
+    pub checksum: String,
+    pub applied_at: DateTime<Utc>,
+    pub execution_ms: i64,
+}
+
+/// State of a known migration
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied_at: Option<DateTime<Utc>>,
+    pub reversible: bool,
+    /// `false` when the applied script differs from the embedded one
+    pub checksum_matches: bool,
+}
+
+/// Apply all pending migrations, returning the versions applied
+///
+/// Refuses to run if an applied migration's checksum no longer matches
+/// the embedded script, or if the database has migrations this build
+/// does not know about.
+pub async fn run_migrations(pool: &PgPool) -> anyhow::Result<Vec<i64>> {
+    tracing::info!("Running database migrations");
+
+    let mut conn = pool.acquire().await?;
+    lock(&mut conn).await?;
+    let result = apply_pending(&mut conn).await;
+    unlock(&mut conn).await?;
+
+    let applied = result?;
+    tracing::info!(
+        "Database migrations completed successfully ({} applied)",
+        applied.len()
+    );
+
+    Ok(applied)
+}
+
+/// Revert applied migrations newer than `target`, newest first
+///
+/// Every migration to revert must have a down script, otherwise nothing is reverted.
+pub async fn rollback(pool: &PgPool, target: i64) -> anyhow::Result<Vec<i64>> {
+    let mut conn = pool.acquire().await?;
+    lock(&mut conn).await?;
+    let result = revert_to(&mut conn, target).await;
+    unlock(&mut conn).await?;
+
+    result
+}
+
+/// Status of every embedded migration
+pub async fn status(pool: &PgPool) -> anyhow::Result<Vec<MigrationStatus>> {
+    let mut conn = pool.acquire().await?;
+    ensure_table(&mut conn).await?;
+    let applied = applied_by_version(&mut conn).await?;
+
+    Ok(up_migrations()
+        .map(|m| {
+            let record = applied.get(&m.version);
+            MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                applied_at: record.map(|r| r.applied_at),
+                reversible: down_migration(m.version).is_some(),
+                checksum_matches: record.is_none_or(|r| r.checksum == checksum(m)),
+            }
+        })
+        .collect())
+}
+
+/// Latest applied migration version
+pub async fn current_version(pool: &PgPool) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+}
+
+/// Newest migration version embedded in this build
+pub fn latest_version() -> Option<i64> {
+    up_migrations().map(|m| m.version).max()
+}
+
+async fn apply_pending(conn: &mut PgConnection) -> anyhow::Result<Vec<i64>> {
+    ensure_table(conn).await?;
+    let applied = applied_by_version(conn).await?;
+
+    // Refuse to start on drift rather than run against an unknown schema
+    let problems = drift(applied.values());
+    if !problems.is_empty() {
+        anyhow::bail!("Migration checksum drift: {}", problems.join("; "));
+    }
+
+    let mut versions = Vec::new();
+    for migration in up_migrations().filter(|m| !applied.contains_key(&m.version)) {
+        tracing::info!(
+            "Applying migration {} ({})",
+            migration.version,
+            migration.description
+        );
+        apply(conn, migration)
+            .await
+            .with_context(|| format!("Migration {} failed", migration.version))?;
+        versions.push(migration.version);
+    }
+
+    Ok(versions)
+}
+
+/// Applied migrations modified since, or unknown to this build, sorted
+fn drift<'a>(applied: impl Iterator<Item = &'a AppliedMigration>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for record in applied {
+        match up_migrations().find(|m| m.version == record.version) {
+            Some(m) if record.checksum != checksum(m) => problems.push(format!(
+                "migration {} ({}) was modified after being applied",
+                record.version, record.description
+            )),
+            Some(_) => {}
+            None => problems.push(format!(
+                "migration {} ({}) is applied but missing from this build",
+                record.version, record.description
+            )),
+        }
+    }
+    problems.sort();
+    problems
+}
+
+async fn apply(conn: &mut PgConnection, migration: &Migration) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    if migration.no_tx {
+        sqlx::raw_sql(&migration.sql).execute(&mut *conn).await?;
+        record(conn, migration, start).await?;
+    } else {
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        sqlx::raw_sql(&migration.sql).execute(&mut *tx).await?;
+        record(&mut tx, migration, start).await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+async fn record(
+    conn: &mut PgConnection,
+    migration: &Migration,
+    start: Instant,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO schema_migrations (version, description, checksum, execution_ms) \
+         VALUES ($1, $2, $3, $4)",
+    )
+    .bind(migration.version)
+    .bind(migration.description.as_ref())
+    .bind(checksum(migration))
+    .bind(start.elapsed().as_millis() as i64)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn revert_to(conn: &mut PgConnection, target: i64) -> anyhow::Result<Vec<i64>> {
+    ensure_table(conn).await?;
+
+    let applied: Vec<i64> = applied_by_version(conn).await?.into_keys().collect();
+    let to_revert = rollback_plan(&applied, target)?;
+
+    for version in &to_revert {
+        let down = down_migration(*version).expect("checked above");
+        tracing::info!("Reverting migration {} ({})", version, down.description);
+
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        sqlx::raw_sql(&down.sql)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("Reverting migration {} failed", version))?;
+        sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+            .bind(version)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(to_revert)
+}
+
+/// Applied versions newer than `target`, newest first, if all have a down script
+fn rollback_plan(applied: &[i64], target: i64) -> anyhow::Result<Vec<i64>> {
+    let mut to_revert: Vec<i64> = applied.iter().copied().filter(|v| *v > target).collect();
+    to_revert.sort_unstable_by(|a, b| b.cmp(a));
+
+    let missing: Vec<String> = to_revert
+        .iter()
+        .filter(|v| down_migration(**v).is_none())
+        .map(|v| v.to_string())
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Cannot roll back to {}: no down migration for version(s) {}",
+            target,
+            missing.join(", ")
+        );
+    }
+
+    Ok(to_revert)
+}
+
+async fn ensure_table(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            description TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            execution_ms BIGINT NOT NULL
+        )",
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn applied_by_version(
+    conn: &mut PgConnection,
+) -> Result<HashMap<i64, AppliedMigration>, sqlx::Error> {
+    let rows: Vec<AppliedMigration> = sqlx::query_as(
+        "SELECT version, description, checksum, applied_at, execution_ms \
+         FROM schema_migrations ORDER BY version",
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows.into_iter().map(|r| (r.version, r)).collect())
+}
+
+async fn lock(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn unlock(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_ID)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+fn up_migrations() -> impl Iterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|m| m.migration_type != MigrationType::ReversibleDown)
+}
+
+fn down_migration(version: i64) -> Option<&'static Migration> {
+    MIGRATOR
+        .iter()
+        .find(|m| m.version == version && m.migration_type == MigrationType::ReversibleDown)
+}
+
+fn checksum(migration: &Migration) -> String {
+    hex::encode(&migration.checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_migrations_are_ordered_and_unique() {
+        let versions: Vec<i64> = up_migrations().map(|m| m.version).collect();
+
+        let mut sorted = versions.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+
+        assert!(!versions.is_empty());
+        assert_eq!(versions, sorted);
+    }
+
+    #[test]
+    fn test_down_migrations_have_matching_up() {
+        for down in MIGRATOR
+            .iter()
+            .filter(|m| m.migration_type == MigrationType::ReversibleDown)
+        {
+            assert!(
+                up_migrations().any(|m| m.version == down.version),
+                "down migration {} has no up migration",
+                down.version
+            );
+        }
+    }
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            description: migration.description.to_string(),
+            checksum: checksum(migration),
+            applied_at: Utc::now(),
+            execution_ms: 1,
+        }
+    }
+
+    fn up(version: i64) -> &'static Migration {
+        up_migrations().find(|m| m.version == version).unwrap()
+    }
+
+    #[test]
+    fn test_drift_detection() {
+        let records: Vec<AppliedMigration> = up_migrations().map(applied).collect();
+        assert!(drift(records.iter()).is_empty());
+
+        let mut modified = applied(up(2));
+        modified.checksum = "0".repeat(96);
+        let mut unknown = applied(up(3));
+        unknown.version = 9999;
+        let problems = drift([applied(up(1)), modified, unknown].iter());
+
+        assert_eq!(
+            problems,
+            [
+                "migration 2 (api keys) was modified after being applied",
+                "migration 9999 (project cors) is applied but missing from this build",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_baseline_is_unchanged() {
+        // Deployments record this checksum; schema changes go in new migrations
+        assert_eq!(
+            checksum(up(1)),
+            "78f1c876c0db57aefb2fb0ecaeb8ccee888406e2962587fcd75c1b1fad5f7070e11d39c0c80ad35c81f7067e2cfb7223"
+        );
+    }
+
+    #[test]
+    fn test_rollback_plan() {
+        assert_eq!(rollback_plan(&[2, 4, 3], 1).unwrap(), [4, 3, 2]);
+        assert!(rollback_plan(&[2, 3], 3).unwrap().is_empty());
+
+        // Nothing is reverted when any migration lacks a down script
+        let err = rollback_plan(&[1, 2, 3], 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot roll back to 0: no down migration for version(s) 1"
+        );
+    }
+}
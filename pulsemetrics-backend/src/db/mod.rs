@@ -1,3 +1,15 @@
+pub mod api_keys;
+pub mod audit;
+pub mod buffer;
+pub mod compression;
+pub mod events;
+pub mod ip_salts;
+pub mod jobs;
+pub mod migrations;
+pub mod organizations;
 pub mod pool;
+pub mod projects;
+pub mod replicas;
 
-pub use pool::{create_pool, health_check, run_migrations};
\ No newline at end of file
+pub use migrations::run_migrations;
+pub use pool::{create_pool, health_check, timescaledb_version};
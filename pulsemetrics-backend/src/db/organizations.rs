@@ -0,0 +1,135 @@
+use sqlx::PgExecutor;
+
+use crate::models::{Member, Organization, Role};
+
+const MEMBER_COLUMNS: &str = "organization_id, subject, role, created_at";
+
+/// Create an organization
+pub async fn create<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+    name: &str,
+) -> Result<Organization, sqlx::Error> {
+    sqlx::query_as::<_, Organization>(
+        "INSERT INTO organizations (id, name) VALUES ($1, $2) RETURNING id, name, created_at",
+    )
+    .bind(id)
+    .bind(name)
+    .fetch_one(executor)
+    .await
+}
+
+/// Find an organization by id
+pub async fn find_by_id<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+) -> Result<Option<Organization>, sqlx::Error> {
+    sqlx::query_as::<_, Organization>(
+        "SELECT id, name, created_at FROM organizations WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Find an organization by id, locking it until the transaction ends
+///
+/// Serializes membership changes, so two of them cannot each remove one of
+/// the last two owners.
+pub async fn lock<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+) -> Result<Option<Organization>, sqlx::Error> {
+    sqlx::query_as::<_, Organization>(
+        "SELECT id, name, created_at FROM organizations WHERE id = $1 FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Number of owners of an organization
+pub async fn count_owners<'e>(
+    executor: impl PgExecutor<'e>,
+    organization_id: &str,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM organization_members WHERE organization_id = $1 AND role = $2",
+    )
+    .bind(organization_id)
+    .bind(Role::Owner.as_str())
+    .fetch_one(executor)
+    .await
+}
+
+/// Find a member of an organization
+pub async fn find_member<'e>(
+    executor: impl PgExecutor<'e>,
+    organization_id: &str,
+    subject: &str,
+) -> Result<Option<Member>, sqlx::Error> {
+    sqlx::query_as::<_, Member>(&format!(
+        "SELECT {} FROM organization_members WHERE organization_id = $1 AND subject = $2",
+        MEMBER_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(subject)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Add a member or change the role of an existing one
+pub async fn upsert_member<'e>(
+    executor: impl PgExecutor<'e>,
+    organization_id: &str,
+    subject: &str,
+    role: Role,
+) -> Result<Member, sqlx::Error> {
+    sqlx::query_as::<_, Member>(&format!(
+        "INSERT INTO organization_members (organization_id, subject, role) VALUES ($1, $2, $3) \
+         ON CONFLICT (organization_id, subject) DO UPDATE SET role = EXCLUDED.role \
+         RETURNING {}",
+        MEMBER_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(subject)
+    .bind(role.as_str())
+    .fetch_one(executor)
+    .await
+}
+
+/// Remove a member, returning the removed membership
+pub async fn remove_member<'e>(
+    executor: impl PgExecutor<'e>,
+    organization_id: &str,
+    subject: &str,
+) -> Result<Option<Member>, sqlx::Error> {
+    sqlx::query_as::<_, Member>(&format!(
+        "DELETE FROM organization_members WHERE organization_id = $1 AND subject = $2 \
+         RETURNING {}",
+        MEMBER_COLUMNS
+    ))
+    .bind(organization_id)
+    .bind(subject)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Role of a subject in the organization owning a project
+pub async fn role_for_project<'e>(
+    executor: impl PgExecutor<'e>,
+    project_id: &str,
+    subject: &str,
+) -> Result<Option<Role>, sqlx::Error> {
+    let role: Option<String> = sqlx::query_scalar(
+        "SELECT m.role FROM projects p \
+         JOIN organization_members m ON m.organization_id = p.organization_id \
+         WHERE p.id = $1 AND m.subject = $2",
+    )
+    .bind(project_id)
+    .bind(subject)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(role.and_then(|r| r.parse().ok()))
+}
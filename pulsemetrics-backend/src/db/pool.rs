@@ -10,7 +10,22 @@ pub async fn create_pool(config: &DatabaseConfig) -> anyhow::Result<PgPool> {
         config.min_connections
     );
 
-    let pool = PgPoolOptions::new()
+    let pool = pool_options(config).connect(&config.url).await?;
+
+    tracing::info!("Database connection pool initialized successfully");
+
+    Ok(pool)
+}
+
+/// Pool for `url` with the settings of `config`, connecting on first use
+///
+/// Used for read replicas, which may be unreachable at startup.
+pub fn create_lazy_pool(config: &DatabaseConfig, url: &str) -> Result<PgPool, sqlx::Error> {
+    pool_options(config).connect_lazy(url)
+}
+
+fn pool_options(config: &DatabaseConfig) -> PgPoolOptions {
+    PgPoolOptions::new()
         .max_connections(config.max_connections)
         .min_connections(config.min_connections)
         .acquire_timeout(Duration::from_secs(config.connect_timeout_seconds))
@@ -29,48 +44,6 @@ pub async fn create_pool(config: &DatabaseConfig) -> anyhow::Result<PgPool> {
                 Ok(())
             })
         })
-        .connect(&config.url)
-        .await?;
-
-    tracing::info!("Database connection pool initialized successfully");
-
-    Ok(pool)
-}
-
-/// Run database migrations at runtime by reading SQL file
-pub async fn run_migrations(pool: &PgPool) -> anyhow::Result<()> {
-    tracing::info!("Running database migrations");
-
-    // Include the SQL file at compile time
-    let migration_sql = include_str!("../../migrations/001_initial_schema.sql");
-    
-    // Execute the entire migration as a single transaction
-    let mut tx = pool.begin().await?;
-    
-    // Split by semicolon and execute each statement
-    let statements: Vec<&str> = migration_sql
-        .split(';')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty() && !s.starts_with("--"))
-        .collect();
-
-    for (idx, statement) in statements.iter().enumerate() {
-        tracing::debug!("Executing migration statement {}/{}", idx + 1, statements.len());
-        
-        sqlx::query(statement)
-            .execute(&mut *tx)
-            .await
-            .map_err(|e| {
-                tracing::error!("Failed to execute statement: {}", statement);
-                anyhow::anyhow!("Migration failed at statement {}: {}", idx + 1, e)
-            })?;
-    }
-
-    tx.commit().await?;
-
-    tracing::info!("Database migrations completed successfully");
-
-    Ok(())
 }
 
 /// Health check for database connection
@@ -80,4 +53,11 @@ pub async fn health_check(pool: &PgPool) -> anyhow::Result<()> {
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Installed TimescaleDB extension version, if any
+pub async fn timescaledb_version(pool: &PgPool) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT extversion FROM pg_extension WHERE extname = 'timescaledb'")
+        .fetch_optional(pool)
+        .await
+}
@@ -0,0 +1,131 @@
+use sqlx::{types::Json, PgExecutor};
+
+use crate::models::{IpMode, Project, ScrubRule};
+
+const PROJECT_COLUMNS: &str = "id, name, organization_id, allowed_origins, retention_days, \
+                               scrub_rules, scrub_key, ip_mode, created_at";
+
+/// Find a project by id
+pub async fn find_by_id<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+) -> Result<Option<Project>, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "SELECT {} FROM projects WHERE id = $1",
+        PROJECT_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Create a project
+#[allow(clippy::too_many_arguments)]
+pub async fn create<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+    name: &str,
+    organization_id: Option<&str>,
+    allowed_origins: &[String],
+    retention_days: Option<i32>,
+    scrub_rules: &[ScrubRule],
+    ip_mode: IpMode,
+) -> Result<Project, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "INSERT INTO projects (id, name, organization_id, allowed_origins, retention_days, \
+                               scrub_rules, ip_mode) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING {}",
+        PROJECT_COLUMNS
+    ))
+    .bind(id)
+    .bind(name)
+    .bind(organization_id)
+    .bind(allowed_origins)
+    .bind(retention_days)
+    .bind(Json(scrub_rules))
+    .bind(ip_mode.as_str())
+    .fetch_one(executor)
+    .await
+}
+
+/// Update a project's name, allowed origins, retention, scrubbing rules and IP mode
+pub async fn update<'e>(
+    executor: impl PgExecutor<'e>,
+    id: &str,
+    name: &str,
+    allowed_origins: &[String],
+    retention_days: Option<i32>,
+    scrub_rules: &[ScrubRule],
+    ip_mode: IpMode,
+) -> Result<Option<Project>, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "UPDATE projects \
+         SET name = $2, allowed_origins = $3, retention_days = $4, scrub_rules = $5, \
+             ip_mode = $6 \
+         WHERE id = $1 RETURNING {}",
+        PROJECT_COLUMNS
+    ))
+    .bind(id)
+    .bind(name)
+    .bind(allowed_origins)
+    .bind(retention_days)
+    .bind(Json(scrub_rules))
+    .bind(ip_mode.as_str())
+    .fetch_optional(executor)
+    .await
+}
+
+/// Projects whose events expire, ordered by id
+pub async fn with_retention<'e>(
+    executor: impl PgExecutor<'e>,
+) -> Result<Vec<Project>, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "SELECT {} FROM projects WHERE retention_days IS NOT NULL ORDER BY id",
+        PROJECT_COLUMNS
+    ))
+    .fetch_all(executor)
+    .await
+}
+
+/// Projects with scrubbing rules, ordered by id
+pub async fn with_scrub_rules<'e>(
+    executor: impl PgExecutor<'e>,
+) -> Result<Vec<Project>, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "SELECT {} FROM projects WHERE scrub_rules <> '[]'::jsonb ORDER BY id",
+        PROJECT_COLUMNS
+    ))
+    .fetch_all(executor)
+    .await
+}
+
+/// Projects capturing client IPs, ordered by id
+pub async fn with_ip_capture<'e>(
+    executor: impl PgExecutor<'e>,
+) -> Result<Vec<Project>, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "SELECT {} FROM projects WHERE ip_mode <> 'drop' ORDER BY id",
+        PROJECT_COLUMNS
+    ))
+    .fetch_all(executor)
+    .await
+}
+
+/// Ids of every project, ordered
+pub async fn ids<'e>(executor: impl PgExecutor<'e>) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM projects ORDER BY id")
+        .fetch_all(executor)
+        .await
+}
+
+/// Projects with their own allowed origins, ordered by id
+pub async fn with_allowed_origins<'e>(
+    executor: impl PgExecutor<'e>,
+) -> Result<Vec<Project>, sqlx::Error> {
+    sqlx::query_as::<_, Project>(&format!(
+        "SELECT {} FROM projects WHERE cardinality(allowed_origins) > 0 ORDER BY id",
+        PROJECT_COLUMNS
+    ))
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,335 @@
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Duration, Utc};
+use std::path::{Path, PathBuf};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    config::ExportConfig,
+    db::{audit, jobs},
+    models::{DataJob, EventRange, ExportFormat, JobKind, NewAuditEntry},
+    storage::EventStore,
+    AppState,
+};
+
+/// Events deleted per statement, keeping locks on the events table short
+const DELETE_BATCH_SIZE: u64 = 10_000;
+/// Events read per page while exporting, bounding memory use
+const EXPORT_PAGE_SIZE: u32 = 1000;
+/// Time between sweeps for expired export files
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Run a data job in the background
+///
+/// The outcome is stored on the job; completion is also recorded in the
+/// audit log under the principal that requested it.
+pub fn spawn(state: AppState, job: DataJob) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(e) = run(&state, &job).await {
+            tracing::error!(job_id = %job.id, "Data job failed: {:#}", e);
+            let Some(db) = &state.db else {
+                return;
+            };
+            if let Err(e) = jobs::fail(db, job.id, &format!("{:#}", e)).await {
+                tracing::error!(job_id = %job.id, "Failed to record job failure: {}", e);
+            }
+        }
+    })
+}
+
+/// Restart jobs interrupted by a shutdown; they are safe to repeat
+pub async fn resume(state: &AppState) -> anyhow::Result<()> {
+    let unfinished = jobs::unfinished(state.db()?).await?;
+    if !unfinished.is_empty() {
+        tracing::info!("Resuming {} unfinished data jobs", unfinished.len());
+    }
+    for job in unfinished {
+        spawn(state.clone(), job);
+    }
+
+    Ok(())
+}
+
+async fn run(state: &AppState, job: &DataJob) -> anyhow::Result<()> {
+    jobs::start(state.db()?, job.id).await?;
+
+    let range = EventRange {
+        project_id: Some(job.project_id.clone()),
+        user_id: Some(job.user_id.clone()),
+        ..Default::default()
+    };
+    let (action, affected, output_path) = match job.kind() {
+        Some(JobKind::UserDeletion) => {
+            // Buffered events would otherwise be written after the deletion
+            let discarded = state
+                .buffer
+                .discard_user(&job.project_id, &job.user_id)
+                .await as u64;
+            let deleted = delete_events(state.events.as_ref(), &range).await?;
+            ("user.delete", discarded + deleted, None)
+        }
+        Some(JobKind::UserExport) => {
+            let format = job.format().unwrap_or_default();
+            let path = export_path(&state.config().export, job.id, format);
+            let exported = export_to_file(state.events.as_ref(), &range, format, &path).await?;
+            ("user.export", exported, Some(path))
+        }
+        None => return Err(anyhow!("Unknown job kind {}", job.kind)),
+    };
+
+    let output_path = output_path.map(|p| p.to_string_lossy().into_owned());
+    let mut tx = state.db()?.begin().await?;
+    let job = jobs::complete(&mut *tx, job.id, affected as i64, output_path.as_deref()).await?;
+    let entry = NewAuditEntry {
+        actor: job.requested_by.clone(),
+        actor_method: job.requested_method.clone(),
+        action: action.to_string(),
+        resource_type: "user".to_string(),
+        resource_id: job.user_id.clone(),
+        project_id: Some(job.project_id.clone()),
+        request_id: job.request_id.clone(),
+        before: None,
+        after: None,
+    }
+    .after(&job);
+    audit::record(&mut *tx, &entry).await?;
+    tx.commit().await?;
+
+    tracing::info!(job_id = %job.id, kind = %job.kind, affected, "Data job completed");
+
+    Ok(())
+}
+
+/// When the file of a completed export is deleted
+pub fn expires_at(job: &DataJob, config: &ExportConfig) -> Option<DateTime<Utc>> {
+    job.completed_at
+        .map(|completed| completed + Duration::hours(config.expire_after_hours as i64))
+}
+
+fn export_path(config: &ExportConfig, id: Uuid, format: ExportFormat) -> PathBuf {
+    Path::new(&config.directory).join(format!("{}.{}", id, format.as_str()))
+}
+
+/// Write an export next to `path`, moving it into place once complete
+async fn export_to_file(
+    store: &dyn EventStore,
+    range: &EventRange,
+    format: ExportFormat,
+    path: &Path,
+) -> anyhow::Result<u64> {
+    if let Some(directory) = path.parent() {
+        tokio::fs::create_dir_all(directory)
+            .await
+            .with_context(|| {
+                format!("Failed to create export directory {}", directory.display())
+            })?;
+    }
+
+    let partial = path.with_extension("partial");
+    let file = tokio::fs::File::create(&partial)
+        .await
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+    let mut writer = BufWriter::new(file);
+    let exported = write_export(store, range, format, &mut writer).await?;
+    writer.flush().await?;
+    writer.get_ref().sync_all().await?;
+    tokio::fs::rename(&partial, path).await?;
+
+    Ok(exported)
+}
+
+/// Stream the events of a range to `writer` a page at a time, returning how many were written
+///
+/// JSON wraps the events in a document naming the project and user;
+/// NDJSON writes one event per line.
+pub async fn write_export<W: AsyncWrite + Unpin>(
+    store: &dyn EventStore,
+    range: &EventRange,
+    format: ExportFormat,
+    writer: &mut W,
+) -> anyhow::Result<u64> {
+    if format == ExportFormat::Json {
+        let header = serde_json::json!({
+            "project_id": range.project_id,
+            "user_id": range.user_id,
+            "exported_at": Utc::now(),
+        })
+        .to_string();
+        // Reopen the header object to append the events to it
+        writer
+            .write_all(header.trim_end_matches('}').as_bytes())
+            .await?;
+        writer.write_all(br#","events":["#).await?;
+    }
+
+    let mut exported = 0;
+    let mut after = None;
+    loop {
+        let page = store.scan(range, after, EXPORT_PAGE_SIZE).await?;
+        for event in &page {
+            if format == ExportFormat::Json && exported > 0 {
+                writer.write_all(b",").await?;
+            }
+            writer.write_all(&serde_json::to_vec(event)?).await?;
+            if format == ExportFormat::Ndjson {
+                writer.write_all(b"\n").await?;
+            }
+            exported += 1;
+        }
+
+        match page.last() {
+            Some(last) if page.len() == EXPORT_PAGE_SIZE as usize => {
+                after = Some((last.time, last.id));
+            }
+            _ => break,
+        }
+    }
+
+    if format == ExportFormat::Json {
+        writer
+            .write_all(format!(r#"],"event_count":{}}}"#, exported).as_bytes())
+            .await?;
+    }
+
+    Ok(exported)
+}
+
+/// Spawn the task deleting export files past `export.expire_after_hours`
+pub fn start_cleanup(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = remove_expired_exports(&state).await {
+                tracing::error!("Failed to remove expired exports: {:#}", e);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(CLEANUP_INTERVAL) => {}
+                _ = shutdown.cancelled() => break,
+            }
+        }
+    })
+}
+
+async fn remove_expired_exports(state: &AppState) -> anyhow::Result<()> {
+    let hours = state.config().export.expire_after_hours as i64;
+    let cutoff = Utc::now() - Duration::hours(hours);
+
+    let db = state.db()?;
+    for job in jobs::expired_exports(db, cutoff).await? {
+        if let Some(path) = &job.output_path {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => tracing::info!(job_id = %job.id, "Deleted expired export"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    tracing::warn!(job_id = %job.id, "Failed to delete export {}: {}", path, e);
+                    continue;
+                }
+            }
+        }
+        jobs::clear_output(db, job.id).await?;
+    }
+
+    Ok(())
+}
+
+/// Delete every event of a range in batches, returning how many were deleted
+async fn delete_events(store: &dyn EventStore, range: &EventRange) -> anyhow::Result<u64> {
+    let mut deleted = 0;
+    loop {
+        let batch = store.delete_batch(range, DELETE_BATCH_SIZE).await?;
+        deleted += batch;
+        if batch < DELETE_BATCH_SIZE {
+            return Ok(deleted);
+        }
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{models::Event, storage::MemoryStore};
+
+    #[tokio::test]
+    async fn test_delete_events_of_one_user() {
+        let store = MemoryStore::new();
+        store
+            .insert(&[
+                Event::fixture("web").user_id("alice"),
+                Event::fixture("web").user_id("alice"),
+                Event::fixture("web").user_id("bob"),
+                Event::fixture("web"),
+                Event::fixture("app").user_id("alice"),
+            ])
+            .await
+            .unwrap();
+
+        let range = EventRange {
+            project_id: Some("web".to_string()),
+            user_id: Some("alice".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(delete_events(&store, &range).await.unwrap(), 2);
+
+        assert_eq!(store.count(&range).await.unwrap(), 0);
+        assert_eq!(store.count(&EventRange::default()).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_every_page() {
+        let store = MemoryStore::new();
+        let events: Vec<Event> = (0..EXPORT_PAGE_SIZE + 5)
+            .map(|_| Event::fixture("web").user_id("alice"))
+            .collect();
+        store.insert(&events).await.unwrap();
+        store
+            .insert(&[Event::fixture("web").user_id("bob")])
+            .await
+            .unwrap();
+        let range = EventRange {
+            project_id: Some("web".to_string()),
+            user_id: Some("alice".to_string()),
+            ..Default::default()
+        };
+
+        let mut json = Vec::new();
+        let exported = write_export(&store, &range, ExportFormat::Json, &mut json)
+            .await
+            .unwrap();
+        assert_eq!(exported, u64::from(EXPORT_PAGE_SIZE) + 5);
+        let document: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(document["user_id"], "alice");
+        assert_eq!(document["event_count"], exported);
+        let ids: std::collections::HashSet<&str> = document["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids.len() as u64, exported);
+
+        let mut ndjson = Vec::new();
+        write_export(&store, &range, ExportFormat::Ndjson, &mut ndjson)
+            .await
+            .unwrap();
+        let lines: Vec<&[u8]> = ndjson
+            .split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .collect();
+        assert_eq!(lines.len() as u64, exported);
+        let first: Event = serde_json::from_slice(lines[0]).unwrap();
+        assert_eq!(first.user_id.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_job_kind_round_trip() {
+        for kind in [JobKind::UserDeletion, JobKind::UserExport] {
+            assert_eq!(kind.as_str().parse::<JobKind>().unwrap(), kind);
+        }
+        assert!("user_purge".parse::<JobKind>().is_err());
+    }
+}
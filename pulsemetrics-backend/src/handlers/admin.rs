@@ -0,0 +1,404 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    config::reload::ReloadOutcome,
+    db::{
+        api_keys, audit,
+        compression::{self, ChunkSize},
+        projects,
+    },
+    middleware::{
+        policy::{self, Action, Resource},
+        request_id::REQUEST_ID_HEADER,
+    },
+    models::{
+        ApiKeySummary, AppError, AppResult, AuthScheme, IpMode, NewAuditEntry, Principal, Project,
+        ScrubRule,
+    },
+    retention::{self, RetentionReport},
+    scrub, AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateProjectRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub id: String,
+
+    #[validate(length(min = 1, max = 200))]
+    pub name: String,
+
+    /// Organization owning the project
+    pub organization_id: Option<String>,
+
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+
+    /// Days events are kept, forever when absent
+    #[validate(range(min = 1, max = 36500))]
+    pub retention_days: Option<i32>,
+
+    /// PII scrubbing applied to events at ingestion
+    #[serde(default)]
+    #[validate(length(max = scrub::MAX_RULES), custom(function = scrub::validate_rules))]
+    pub scrub_rules: Vec<ScrubRule>,
+
+    /// How client IPs are stored with events, not at all by default
+    #[serde(default)]
+    pub ip_mode: IpMode,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateProjectRequest {
+    #[validate(length(min = 1, max = 200))]
+    pub name: Option<String>,
+
+    pub allowed_origins: Option<Vec<String>>,
+
+    /// Days events are kept, `null` keeps them forever
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(range(min = 1, max = 36500))]
+    pub retention_days: Option<Option<i32>>,
+
+    #[validate(length(max = scrub::MAX_RULES), custom(function = scrub::validate_rules))]
+    pub scrub_rules: Option<Vec<ScrubRule>>,
+
+    pub ip_mode: Option<IpMode>,
+}
+
+/// Tell an explicit `null` (`Some(None)`) apart from an absent field (`None`)
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateKeyRequest {
+    #[validate(length(min = 1, max = 200))]
+    pub name: String,
+
+    #[serde(default = "default_auth_scheme")]
+    pub auth_scheme: AuthScheme,
+}
+
+fn default_auth_scheme() -> AuthScheme {
+    AuthScheme::Bearer
+}
+
+/// Response for a newly created key, the only time its secret is shown
+#[derive(Debug, Serialize)]
+pub struct CreatedKeyResponse {
+    pub key: ApiKeySummary,
+    pub secret: String,
+}
+
+/// Create a project
+pub async fn create_project(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Json(req): Json<CreateProjectRequest>,
+) -> AppResult<(StatusCode, Json<Project>)> {
+    // Projects outside an organization can only be created by the config key
+    let resource = match req.organization_id.as_deref() {
+        Some(organization_id) => Resource::Organization(organization_id),
+        None => Resource::Global,
+    };
+    policy::authorize(&state, &principal, Action::ManageProject, resource).await?;
+    req.validate()?;
+
+    let mut tx = state.db()?.begin().await?;
+
+    let project = projects::create(
+        &mut *tx,
+        &req.id,
+        &req.name,
+        req.organization_id.as_deref(),
+        &req.allowed_origins,
+        req.retention_days,
+        &req.scrub_rules,
+        req.ip_mode,
+    )
+    .await
+    .map_err(|e| conflict_on_duplicate(e, format!("Project {} already exists", req.id)))?;
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "project.create",
+        "project",
+        &project.id,
+    )
+    .project(&project.id)
+    .after(&project);
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+    apply_project_settings(&state, &project);
+
+    tracing::info!(project_id = %project.id, "Created project");
+
+    Ok((StatusCode::CREATED, Json(project)))
+}
+
+/// Update a project's name, allowed origins, retention, scrubbing rules or IP mode
+pub async fn update_project(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<UpdateProjectRequest>,
+) -> AppResult<Json<Project>> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageProject,
+        Resource::Project(&project_id),
+    )
+    .await?;
+    req.validate()?;
+
+    let mut tx = state.db()?.begin().await?;
+
+    let before = projects::find_by_id(&mut *tx, &project_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Project {}", project_id)))?;
+
+    let name = req.name.unwrap_or_else(|| before.name.clone());
+    let allowed_origins = req
+        .allowed_origins
+        .unwrap_or_else(|| before.allowed_origins.clone());
+    let retention_days = req.retention_days.unwrap_or(before.retention_days);
+    let scrub_rules = req
+        .scrub_rules
+        .unwrap_or_else(|| before.scrub_rules.clone());
+    let ip_mode = req.ip_mode.unwrap_or_else(|| before.ip_mode());
+
+    let project = projects::update(
+        &mut *tx,
+        &project_id,
+        &name,
+        &allowed_origins,
+        retention_days,
+        &scrub_rules,
+        ip_mode,
+    )
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("Project {}", project_id)))?;
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "project.update",
+        "project",
+        &project.id,
+    )
+    .project(&project.id)
+    .before(&before)
+    .after(&project);
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+    apply_project_settings(&state, &project);
+
+    Ok(Json(project))
+}
+
+/// Create an API key for a project
+pub async fn create_key(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(project_id): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<CreateKeyRequest>,
+) -> AppResult<(StatusCode, Json<CreatedKeyResponse>)> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageKeys,
+        Resource::Project(&project_id),
+    )
+    .await?;
+    req.validate()?;
+
+    let mut tx = state.db()?.begin().await?;
+
+    if projects::find_by_id(&mut *tx, &project_id).await?.is_none() {
+        return Err(AppError::NotFound(format!("Project {}", project_id)));
+    }
+
+    let (key, secret) = api_keys::create(&mut *tx, &project_id, &req.name, req.auth_scheme).await?;
+    let summary = ApiKeySummary::from(&key);
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "api_key.create",
+        "api_key",
+        &key.id.to_string(),
+    )
+    .project(&project_id)
+    .after(&summary);
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(project_id = %project_id, key_id = %key.id, "Created API key");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedKeyResponse {
+            key: summary,
+            secret,
+        }),
+    ))
+}
+
+/// Revoke an API key
+pub async fn revoke_key(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(key_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> AppResult<StatusCode> {
+    let mut tx = state.db()?.begin().await?;
+
+    let not_found = || AppError::NotFound(format!("API key {}", key_id));
+    let before = api_keys::find_by_id(&mut *tx, key_id)
+        .await?
+        .ok_or_else(not_found)?;
+    // Callers who may not manage the key cannot tell it from a missing one
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageKeys,
+        Resource::Project(&before.project_id),
+    )
+    .await
+    .map_err(|e| match e {
+        AppError::Forbidden(_) => not_found(),
+        e => e,
+    })?;
+
+    let key = api_keys::revoke(&mut *tx, key_id)
+        .await?
+        .ok_or_else(|| AppError::Conflict(format!("API key {} is already revoked", key_id)))?;
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "api_key.revoke",
+        "api_key",
+        &key.id.to_string(),
+    )
+    .project(&key.project_id)
+    .before(&ApiKeySummary::from(&before))
+    .after(&ApiKeySummary::from(&key));
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+
+    tracing::info!(key_id = %key.id, "Revoked API key");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reload the runtime configuration, as on SIGHUP
+pub async fn reload_config(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+) -> AppResult<Json<ReloadOutcome>> {
+    policy::authorize(&state, &principal, Action::ReloadConfig, Resource::Global).await?;
+
+    let outcome = state.reload_config().map_err(|e| {
+        tracing::warn!("Configuration reload rejected: {:#}", e);
+        AppError::UnprocessableEntity(format!("Configuration reload rejected: {:#}", e))
+    })?;
+
+    // The audit log lives in Postgres; without it the reload still applies
+    if let Some(db) = &state.db {
+        let entry = NewAuditEntry::new(
+            &principal,
+            request_id(&headers),
+            "config.reload",
+            "config",
+            "runtime",
+        )
+        .after(&outcome);
+        audit::record(db, &entry).await?;
+    }
+
+    Ok(Json(outcome))
+}
+
+/// Events the retention job would delete if it ran now
+pub async fn retention_report(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> AppResult<Json<RetentionReport>> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageRetention,
+        Resource::Global,
+    )
+    .await?;
+
+    let projects = projects::with_retention(state.db()?).await?;
+    let report = retention::report(state.events.as_ref(), &projects, chrono::Utc::now()).await?;
+
+    Ok(Json(report))
+}
+
+/// Compressed and uncompressed size of each chunk of the events hypertable
+pub async fn chunk_sizes(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> AppResult<Json<Vec<ChunkSize>>> {
+    policy::authorize(&state, &principal, Action::ManageStorage, Resource::Global).await?;
+
+    let pool = state.events_db.as_ref().ok_or_else(|| {
+        AppError::NotFound("Chunks exist only when events are stored in TimescaleDB".to_string())
+    })?;
+
+    Ok(Json(compression::chunk_sizes(pool).await?))
+}
+
+/// Apply the project's saved scrubbing rules, IP mode and allowed origins to
+/// its next requests, and count its events under its own metric label
+fn apply_project_settings(state: &AppState, project: &Project) {
+    state.metrics.add_project(&project.id);
+    state.origins.set(&project.id, &project.allowed_origins);
+    if let Err(e) = state
+        .scrubbers
+        .set(&project.id, &project.scrub_key, &project.scrub_rules)
+    {
+        // Rules are validated before they are saved
+        tracing::error!(project_id = %project.id, "Invalid scrubbing rules: {}", e);
+    }
+    state.ip_modes.set(&project.id, project.ip_mode());
+}
+
+pub(crate) fn request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+}
+
+pub(crate) fn conflict_on_duplicate(e: sqlx::Error, message: String) -> AppError {
+    match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => AppError::Conflict(message),
+        _ => AppError::Database(e),
+    }
+}
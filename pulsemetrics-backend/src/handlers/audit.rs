@@ -0,0 +1,34 @@
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
+
+use crate::{
+    db::audit,
+    middleware::policy::{self, Action, Resource},
+    models::{AppResult, AuditPage, AuditQuery, Principal},
+    AppState,
+};
+
+/// List audit log entries, newest first
+///
+/// Page through results by passing the returned `next_cursor` as `before`.
+/// Project-restricted callers only see entries for their own project.
+pub async fn list_audit_log(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Query(mut query): Query<AuditQuery>,
+) -> AppResult<Json<AuditPage>> {
+    if let Some(own) = &principal.project_id {
+        query.project_id = Some(own.clone());
+    }
+    let resource = match query.project_id.as_deref() {
+        Some(project_id) => Resource::Project(project_id),
+        None => Resource::Global,
+    };
+    policy::authorize(&state, &principal, Action::ViewAudit, resource).await?;
+
+    let page = audit::list(state.db()?, &query).await?;
+
+    Ok(Json(page))
+}
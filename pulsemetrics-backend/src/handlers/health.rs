@@ -1,75 +1,373 @@
 use axum::{extract::State, http::StatusCode, Json};
+use chrono::{DateTime, Utc};
+use futures::future::OptionFuture;
 use serde::Serialize;
+use sqlx::PgPool;
 use std::time::Instant;
 
-use crate::{db, models::AppResult, AppState};
+use crate::{
+    db::{self, migrations},
+    models::AppResult,
+    utils::format_duration,
+    AppState,
+};
+
+/// Pending events with no successful write for this long mark the buffer degraded
+const FLUSH_STALL_SECONDS: i64 = 30;
+
+/// Health of a single component, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
 
 #[derive(Serialize)]
 pub struct HealthResponse {
-    pub status: String,
-    pub database: DatabaseHealth,
+    pub status: HealthStatus,
     pub uptime_seconds: u64,
+    pub uptime: String,
+    pub components: Components,
+}
+
+#[derive(Serialize)]
+pub struct Components {
+    /// Postgres holding projects and keys, omitted without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<DatabaseHealth>,
+    pub event_store: EventStoreHealth,
+    /// Omitted unless events are stored in TimescaleDB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timescaledb: Option<TimescaleHealth>,
+    /// Omitted without Postgres
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub migrations: Option<MigrationHealth>,
+    pub ingestion_buffer: BufferHealth,
+    /// Read replicas, omitted when none are configured
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub replicas: Vec<ReplicaHealth>,
+}
+
+impl Components {
+    /// Overall status, the worst of all components
+    fn rollup(&self) -> HealthStatus {
+        [
+            self.database.as_ref().map(|c| c.status),
+            Some(self.event_store.status),
+            self.timescaledb.as_ref().map(|c| c.status),
+            self.migrations.as_ref().map(|c| c.status),
+            Some(self.ingestion_buffer.status),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.replicas.iter().map(|r| r.status))
+        .max()
+        .unwrap_or(HealthStatus::Healthy)
+    }
 }
 
 #[derive(Serialize)]
 pub struct DatabaseHealth {
-    pub status: String,
+    pub status: HealthStatus,
+    pub latency_ms: u64,
+    pub pool: PoolStats,
+}
+
+#[derive(Serialize)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    pub max: u32,
+}
+
+#[derive(Serialize)]
+pub struct EventStoreHealth {
+    pub status: HealthStatus,
     pub latency_ms: u64,
 }
 
+#[derive(Serialize)]
+pub struct TimescaleHealth {
+    pub status: HealthStatus,
+    pub version: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MigrationHealth {
+    pub status: HealthStatus,
+    pub current_version: Option<i64>,
+    pub latest_version: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct BufferHealth {
+    pub status: HealthStatus,
+    pub depth: usize,
+    pub last_flush_at: Option<DateTime<Utc>>,
+}
+
+/// Queries fall back to the primary, so a replica is at worst degraded
+#[derive(Serialize)]
+pub struct ReplicaHealth {
+    pub name: String,
+    pub status: HealthStatus,
+    pub reachable: bool,
+    pub lag_seconds: Option<f64>,
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
 /// Health check endpoint
-/// 
-/// Returns 200 if all systems are operational
-/// Returns 503 if database is unavailable
+///
+/// Returns 200 when healthy or degraded
+/// Returns 503 if any component is unhealthy
 pub async fn health_check(
     State(state): State<AppState>,
 ) -> AppResult<(StatusCode, Json<HealthResponse>)> {
+    let (database, event_store, timescaledb, migrations) = tokio::join!(
+        OptionFuture::from(state.db.as_ref().map(check_database)),
+        check_event_store(&state),
+        OptionFuture::from(state.events_db.as_ref().map(check_timescaledb)),
+        OptionFuture::from(state.db.as_ref().map(check_migrations)),
+    );
+
+    let components = Components {
+        database,
+        event_store,
+        timescaledb,
+        migrations,
+        ingestion_buffer: check_buffer(&state, Utc::now()),
+        replicas: check_replicas(&state),
+    };
+    let status = components.rollup();
+    let uptime_seconds = state.started_at.elapsed().as_secs();
+
+    let response = HealthResponse {
+        status,
+        uptime_seconds,
+        uptime: format_duration(uptime_seconds),
+        components,
+    };
+
+    let code = if status == HealthStatus::Unhealthy {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((code, Json(response)))
+}
+
+async fn check_database(pool: &PgPool) -> DatabaseHealth {
     let start = Instant::now();
+    let result = db::health_check(pool).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
 
-    // Check database connection
-    let db_status = match db::health_check(&state.db).await {
-        Ok(_) => {
-            let latency = start.elapsed().as_millis() as u64;
-            (
-                StatusCode::OK,
-                DatabaseHealth {
-                    status: "healthy".to_string(),
-                    latency_ms: latency,
-                },
-            )
-        }
+    let size = pool.size();
+    let idle = pool.num_idle();
+    let pool = PoolStats {
+        size,
+        idle,
+        in_use: (size as usize).saturating_sub(idle),
+        max: pool.options().get_max_connections(),
+    };
+
+    let status = match result {
         Err(e) => {
             tracing::error!("Database health check failed: {:?}", e);
-            (
-                StatusCode::SERVICE_UNAVAILABLE,
-                DatabaseHealth {
-                    status: "unhealthy".to_string(),
-                    latency_ms: 0,
-                },
-            )
+            HealthStatus::Unhealthy
         }
+        // Every connection busy: requests are queueing for the pool
+        Ok(()) if pool.size >= pool.max && pool.idle == 0 => HealthStatus::Degraded,
+        Ok(()) => HealthStatus::Healthy,
     };
 
-    let response = HealthResponse {
-        status: if db_status.0 == StatusCode::OK {
-            "healthy".to_string()
-        } else {
-            "degraded".to_string()
+    DatabaseHealth {
+        status,
+        latency_ms,
+        pool,
+    }
+}
+
+async fn check_event_store(state: &AppState) -> EventStoreHealth {
+    let start = Instant::now();
+    let result = state.events.health().await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    let status = match result {
+        Ok(()) => HealthStatus::Healthy,
+        Err(e) => {
+            tracing::error!("Event store health check failed: {:?}", e);
+            HealthStatus::Unhealthy
+        }
+    };
+
+    EventStoreHealth { status, latency_ms }
+}
+
+async fn check_timescaledb(pool: &PgPool) -> TimescaleHealth {
+    match db::timescaledb_version(pool).await {
+        Ok(Some(version)) => TimescaleHealth {
+            status: HealthStatus::Healthy,
+            version: Some(version),
+        },
+        Ok(None) => {
+            tracing::error!("TimescaleDB extension is not installed");
+            TimescaleHealth {
+                status: HealthStatus::Unhealthy,
+                version: None,
+            }
+        }
+        Err(e) => {
+            tracing::error!("TimescaleDB health check failed: {:?}", e);
+            TimescaleHealth {
+                status: HealthStatus::Unhealthy,
+                version: None,
+            }
+        }
+    }
+}
+
+async fn check_migrations(pool: &PgPool) -> MigrationHealth {
+    let latest_version = migrations::latest_version();
+
+    match migrations::current_version(pool).await {
+        Ok(current_version) => MigrationHealth {
+            status: if current_version == latest_version {
+                HealthStatus::Healthy
+            } else {
+                HealthStatus::Degraded
+            },
+            current_version,
+            latest_version,
         },
-        database: db_status.1,
-        uptime_seconds: 0, // TODO: Track actual uptime
+        Err(e) => {
+            tracing::error!("Migration health check failed: {:?}", e);
+            MigrationHealth {
+                status: HealthStatus::Unhealthy,
+                current_version: None,
+                latest_version,
+            }
+        }
+    }
+}
+
+fn check_buffer(state: &AppState, now: DateTime<Utc>) -> BufferHealth {
+    let depth = state.buffer.depth();
+    let last_flush_at = state.buffer.last_flush();
+
+    // Before the first flush, measure from process start
+    let since = last_flush_at.unwrap_or_else(|| {
+        now - chrono::Duration::from_std(state.started_at.elapsed()).unwrap_or_default()
+    });
+    let stalled = depth > 0 && (now - since).num_seconds() > FLUSH_STALL_SECONDS;
+    let status = if state.buffer.is_full() {
+        HealthStatus::Unhealthy
+    } else if stalled {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Healthy
     };
 
-    Ok((db_status.0, Json(response)))
+    BufferHealth {
+        status,
+        depth,
+        last_flush_at,
+    }
+}
+
+/// State of each replica as of the monitor's last check
+fn check_replicas(state: &AppState) -> Vec<ReplicaHealth> {
+    state
+        .replicas
+        .iter()
+        .map(|replica| {
+            let status = replica.status();
+            ReplicaHealth {
+                name: replica.name.clone(),
+                status: if status.usable {
+                    HealthStatus::Healthy
+                } else {
+                    HealthStatus::Degraded
+                },
+                reachable: status.reachable,
+                lag_seconds: status.lag_seconds,
+                checked_at: status.checked_at,
+            }
+        })
+        .collect()
 }
 
 /// Readiness check (for Kubernetes)
 pub async fn readiness(State(state): State<AppState>) -> AppResult<StatusCode> {
-    db::health_check(&state.db).await?;
+    if let Some(db) = &state.db {
+        db::health_check(db).await?;
+    }
+    state.events.health().await?;
     Ok(StatusCode::OK)
 }
 
 /// Liveness check (for Kubernetes)
 pub async fn liveness() -> StatusCode {
     StatusCode::OK
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(database: HealthStatus, buffer: HealthStatus) -> Components {
+        Components {
+            database: Some(DatabaseHealth {
+                status: database,
+                latency_ms: 1,
+                pool: PoolStats {
+                    size: 1,
+                    idle: 1,
+                    in_use: 0,
+                    max: 10,
+                },
+            }),
+            event_store: EventStoreHealth {
+                status: HealthStatus::Healthy,
+                latency_ms: 1,
+            },
+            timescaledb: Some(TimescaleHealth {
+                status: HealthStatus::Healthy,
+                version: Some("2.14.2".to_string()),
+            }),
+            migrations: Some(MigrationHealth {
+                status: HealthStatus::Healthy,
+                current_version: Some(5),
+                latest_version: Some(5),
+            }),
+            ingestion_buffer: BufferHealth {
+                status: buffer,
+                depth: 0,
+                last_flush_at: None,
+            },
+            replicas: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_rollup_reports_worst_component() {
+        use HealthStatus::*;
+
+        assert_eq!(components(Healthy, Healthy).rollup(), Healthy);
+        assert_eq!(components(Healthy, Degraded).rollup(), Degraded);
+        assert_eq!(components(Unhealthy, Degraded).rollup(), Unhealthy);
+
+        let mut lagging = components(Healthy, Healthy);
+        lagging.replicas.push(ReplicaHealth {
+            name: "replica-1:5432".to_string(),
+            status: Degraded,
+            reachable: true,
+            lag_seconds: Some(120.0),
+            checked_at: None,
+        });
+        assert_eq!(lagging.rollup(), Degraded);
+    }
+}
@@ -1,66 +1,114 @@
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use std::{net::SocketAddr, time::Instant};
 use validator::Validate;
 
 use crate::{
-    models::{AppError, AppResult, EventBatch, IngestionResponse},
-    AppState,
+    client_ip,
+    middleware::policy::{self, Action, Resource},
+    models::{AppError, AppResult, EventBatch, IngestionResponse, Principal},
+    scrub, AppState,
 };
 
 /// Ingest a batch of events
-/// 
-/// Accepts up to 1000 events per request
-/// Returns 202 Accepted immediately (async processing)
+///
+/// Accepts up to `app.max_batch_size` events per request
+/// Returns 202 Accepted once the events are buffered (async processing)
 pub async fn ingest_events(
     State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
     Json(batch): Json<EventBatch>,
 ) -> AppResult<(StatusCode, Json<IngestionResponse>)> {
     // Validate batch
-    batch.validate()?;
+    tracing::info_span!("validate_batch", events = batch.len()).in_scope(|| batch.validate())?;
+
+    // Every project in the batch must accept events from the caller
+    let mut project_ids: Vec<String> = batch.events.iter().map(|e| e.project_id.clone()).collect();
+    project_ids.sort_unstable();
+    project_ids.dedup();
+    for project_id in &project_ids {
+        policy::authorize(
+            &state,
+            &principal,
+            Action::IngestEvents,
+            Resource::Project(project_id),
+        )
+        .await?;
+    }
+
+    let config = state.config();
+    let per_project: Vec<(&str, usize)> = project_ids
+        .iter()
+        .map(|project_id| {
+            let count = batch
+                .events
+                .iter()
+                .filter(|e| e.project_id == *project_id)
+                .count();
+            (project_id.as_str(), count)
+        })
+        .collect();
+    let reject = |reason: &str| {
+        for (project_id, count) in &per_project {
+            state.metrics.reject_events(project_id, reason, *count);
+        }
+    };
+
+    state.metrics.batch_size.observe(batch.len() as f64);
 
     // Check batch size
-    if batch.len() > state.config.app.max_batch_size {
+    if batch.len() > config.app.max_batch_size {
+        reject("batch_too_large");
         return Err(AppError::BadRequest(format!(
             "Batch size {} exceeds maximum of {}",
             batch.len(),
-            state.config.app.max_batch_size
+            config.app.max_batch_size
         )));
     }
 
-    tracing::debug!("Received batch of {} events", batch.len());
-
-    // Insert events into database
-    insert_events(&state, &batch).await?;
+    // Charge each project for its share of the batch, or none if one is limited
+    let costs: Vec<(&str, u32)> = per_project
+        .iter()
+        .map(|&(project_id, count)| (project_id, count as u32))
+        .collect();
+    let charged = state
+        .rate_limiter
+        .check_all(&costs, &config.rate_limit, Instant::now());
+    if let Err(project_id) = charged {
+        tracing::warn!(project_id = %project_id, "Ingestion rate limit exceeded");
+        reject("rate_limited");
+        return Err(AppError::RateLimited);
+    }
 
-    tracing::info!("Successfully ingested {} events", batch.len());
+    tracing::debug!("Received batch of {} events", batch.len());
 
-    Ok((
-        StatusCode::ACCEPTED,
-        Json(IngestionResponse::new(batch.len())),
-    ))
-}
+    // Personal data never reaches the buffer, let alone storage
+    let mut events = batch.events;
+    scrub::scrub_batch(&state, &mut events, &project_ids);
 
-/// Insert events into the database
-async fn insert_events(state: &AppState, batch: &EventBatch) -> AppResult<()> {
-    // Build bulk insert query
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO events (id, time, project_id, event_type, properties, user_id, session_id, value) "
-    );
+    // Record where the events came from, as each project allows
+    let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr.ip());
+    let trusted = config.server.trusted_proxy_networks().unwrap_or_default();
+    let ip = client_ip::resolve(peer, &headers, &trusted);
+    client_ip::capture_batch(&state, &mut events, &project_ids, ip).await;
 
-    query_builder.push_values(&batch.events, |mut b, event| {
-        b.push_bind(event.id)
-            .push_bind(event.time)
-            .push_bind(&event.project_id)
-            .push_bind(&event.event_type)
-            .push_bind(&event.properties)
-            .push_bind(&event.user_id)
-            .push_bind(event.session_id)
-            .push_bind(event.value);
-    });
+    // Hand events to the buffer, written to the database in the background
+    let accepted = events.len();
+    if let Err(e) = state.buffer.push(events) {
+        reject("buffer_full");
+        return Err(AppError::ServiceUnavailable(e.to_string()));
+    }
 
-    let query = query_builder.build();
+    for (project_id, count) in &per_project {
+        state.metrics.ingest_events(project_id, *count);
+    }
 
-    // Execute the query
-    query.execute(&state.db).await?;
+    tracing::info!("Accepted {} events", accepted);
 
-    Ok(())
-}
\ No newline at end of file
+    Ok((StatusCode::ACCEPTED, Json(IngestionResponse::new(accepted))))
+}
@@ -0,0 +1,27 @@
+use axum::{extract::State, http::header, response::IntoResponse, Extension};
+
+use crate::{
+    middleware::policy::{self, Action, Resource},
+    models::{AppResult, Principal},
+    AppState,
+};
+
+/// Prometheus scrape endpoint, for the config API key only
+pub async fn metrics(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> AppResult<impl IntoResponse> {
+    policy::authorize(&state, &principal, Action::ViewMetrics, Resource::Global).await?;
+
+    let body = state
+        .metrics
+        .render(state.db.as_ref(), state.buffer.depth());
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    ))
+}
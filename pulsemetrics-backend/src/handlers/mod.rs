@@ -1,5 +1,20 @@
+pub mod admin;
+pub mod audit;
 pub mod health;
 pub mod ingestion;
+pub mod metrics;
+pub mod organizations;
+pub mod query;
+pub mod users;
 
+pub use admin::{
+    chunk_sizes, create_key, create_project, reload_config, retention_report, revoke_key,
+    update_project,
+};
+pub use audit::list_audit_log;
 pub use health::{health_check, liveness, readiness};
-pub use ingestion::ingest_events;
\ No newline at end of file
+pub use ingestion::ingest_events;
+pub use metrics::metrics;
+pub use organizations::{create_organization, remove_member, set_member};
+pub use query::{aggregate_events, list_events};
+pub use users::{delete_user_data, download_export, export_user_data, get_job};
@@ -0,0 +1,304 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use serde::Deserialize;
+use validator::Validate;
+
+use super::admin::{conflict_on_duplicate, request_id};
+use crate::{
+    db::{audit, organizations},
+    middleware::policy::{self, Action, Resource},
+    models::{
+        AppError, AppResult, AuthMethod, Member, NewAuditEntry, Organization, Principal, Role,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateOrganizationRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub id: String,
+
+    #[validate(length(min = 1, max = 200))]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetMemberRequest {
+    pub role: Role,
+}
+
+/// Create an organization
+///
+/// When called with a JWT, the caller becomes the organization's owner.
+pub async fn create_organization(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Json(req): Json<CreateOrganizationRequest>,
+) -> AppResult<(StatusCode, Json<Organization>)> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::CreateOrganization,
+        Resource::Global,
+    )
+    .await?;
+    req.validate()?;
+
+    let mut tx = state.db()?.begin().await?;
+
+    let organization = organizations::create(&mut *tx, &req.id, &req.name)
+        .await
+        .map_err(|e| conflict_on_duplicate(e, format!("Organization {} already exists", req.id)))?;
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "organization.create",
+        "organization",
+        &organization.id,
+    )
+    .after(&organization);
+    audit::record(&mut *tx, &entry).await?;
+
+    if principal.method == AuthMethod::Jwt {
+        let owner = organizations::upsert_member(
+            &mut *tx,
+            &organization.id,
+            &principal.subject,
+            Role::Owner,
+        )
+        .await?;
+
+        let entry = NewAuditEntry::new(
+            &principal,
+            request_id(&headers),
+            "member.set",
+            "member",
+            &member_resource_id(&owner),
+        )
+        .after(&owner);
+        audit::record(&mut *tx, &entry).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(organization)))
+}
+
+/// Add a member to an organization or change their role
+///
+/// Granting or changing the owner role requires being an owner, and the
+/// last owner cannot be demoted.
+pub async fn set_member(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path((organization_id, subject)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(req): Json<SetMemberRequest>,
+) -> AppResult<Json<Member>> {
+    // Outsiders learn nothing about the organization, not even whether it exists
+    authorize_membership_change(&state, &principal, &organization_id, false).await?;
+
+    let mut tx = state.db()?.begin().await?;
+
+    if organizations::lock(&mut *tx, &organization_id)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::NotFound(format!(
+            "Organization {}",
+            organization_id
+        )));
+    }
+
+    let before = organizations::find_member(&mut *tx, &organization_id, &subject).await?;
+    let touches_owner =
+        req.role == Role::Owner || before.as_ref().and_then(Member::role) == Some(Role::Owner);
+
+    if touches_owner {
+        authorize_membership_change(&state, &principal, &organization_id, true).await?;
+    }
+    ensure_owner_remains(&mut tx, &organization_id, before.as_ref(), Some(req.role)).await?;
+
+    let member =
+        organizations::upsert_member(&mut *tx, &organization_id, &subject, req.role).await?;
+
+    let mut entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "member.set",
+        "member",
+        &member_resource_id(&member),
+    )
+    .after(&member);
+    if let Some(before) = &before {
+        entry = entry.before(before);
+    }
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(member))
+}
+
+/// Remove a member from an organization
+///
+/// The last owner cannot be removed.
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path((organization_id, subject)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> AppResult<StatusCode> {
+    authorize_membership_change(&state, &principal, &organization_id, false).await?;
+
+    let mut tx = state.db()?.begin().await?;
+
+    organizations::lock(&mut *tx, &organization_id).await?;
+    let before = organizations::find_member(&mut *tx, &organization_id, &subject)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Member {}", subject)))?;
+
+    if before.role() == Some(Role::Owner) {
+        authorize_membership_change(&state, &principal, &organization_id, true).await?;
+    }
+    ensure_owner_remains(&mut tx, &organization_id, Some(&before), None).await?;
+
+    organizations::remove_member(&mut *tx, &organization_id, &subject).await?;
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "member.remove",
+        "member",
+        &member_resource_id(&before),
+    )
+    .before(&before);
+    audit::record(&mut *tx, &entry).await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Refuse a change leaving the organization without an owner
+///
+/// `after` is the member's new role, `None` when they are removed.
+async fn ensure_owner_remains(
+    conn: &mut sqlx::PgConnection,
+    organization_id: &str,
+    before: Option<&Member>,
+    after: Option<Role>,
+) -> AppResult<()> {
+    if !loses_owner(before, after) {
+        return Ok(());
+    }
+
+    if organizations::count_owners(conn, organization_id).await? <= 1 {
+        return Err(AppError::Conflict(format!(
+            "Organization {} must keep at least one owner",
+            organization_id
+        )));
+    }
+
+    Ok(())
+}
+
+/// Whether a change takes the owner role away from an owner
+fn loses_owner(before: Option<&Member>, after: Option<Role>) -> bool {
+    before.and_then(Member::role) == Some(Role::Owner) && after != Some(Role::Owner)
+}
+
+async fn authorize_membership_change(
+    state: &AppState,
+    principal: &Principal,
+    organization_id: &str,
+    touches_owner: bool,
+) -> AppResult<()> {
+    let action = if touches_owner {
+        Action::ManageOrganization
+    } else {
+        Action::ManageMembers
+    };
+
+    policy::authorize(
+        state,
+        principal,
+        action,
+        Resource::Organization(organization_id),
+    )
+    .await
+}
+
+fn member_resource_id(member: &Member) -> String {
+    format!("{}/{}", member.organization_id, member.subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, models::SCOPE_ADMIN};
+
+    fn member(role: Role) -> Member {
+        Member {
+            organization_id: "acme".to_string(),
+            subject: "alice".to_string(),
+            role: role.as_str().to_string(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_loses_owner() {
+        let owner = member(Role::Owner);
+        assert!(loses_owner(Some(&owner), None));
+        assert!(loses_owner(Some(&owner), Some(Role::Admin)));
+        assert!(!loses_owner(Some(&owner), Some(Role::Owner)));
+        assert!(!loses_owner(Some(&member(Role::Admin)), None));
+        assert!(!loses_owner(None, Some(Role::Viewer)));
+    }
+
+    #[tokio::test]
+    async fn test_outsiders_are_refused_before_any_lookup() {
+        let config = Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "memory://".to_string())
+        })
+        .unwrap();
+        let state = AppState::new(None, config);
+        let outsider = Principal::jwt("mallory".to_string(), None, vec![SCOPE_ADMIN.to_string()]);
+        let path = || Path(("acme".to_string(), "alice".to_string()));
+
+        let result = set_member(
+            State(state.clone()),
+            Extension(outsider.clone()),
+            path(),
+            HeaderMap::new(),
+            Json(SetMemberRequest { role: Role::Viewer }),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+
+        let result = remove_member(
+            State(state.clone()),
+            Extension(outsider),
+            path(),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::Forbidden(_))));
+
+        // Only then is the organization looked up
+        let result = remove_member(
+            State(state),
+            Extension(Principal::config_key()),
+            path(),
+            HeaderMap::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::NotFound(_))));
+    }
+}
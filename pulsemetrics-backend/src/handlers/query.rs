@@ -0,0 +1,115 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+
+use crate::{
+    middleware::policy::{self, Action, Resource},
+    models::{
+        AggregateBucket, AggregateQuery, AppError, AppResult, Event, EventQuery, Interval,
+        Principal,
+    },
+    AppState,
+};
+
+/// Events returned when no `limit` is given
+const DEFAULT_LIMIT: u32 = 100;
+/// Largest accepted `limit`
+const MAX_LIMIT: u32 = 1000;
+/// Buckets an aggregation may span
+const MAX_BUCKETS: i64 = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct ListEventsParams {
+    pub event_type: Option<String>,
+    pub user_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateParams {
+    pub event_type: Option<String>,
+    /// Defaults to 24 hours before `to`
+    pub from: Option<DateTime<Utc>>,
+    /// Defaults to now
+    pub to: Option<DateTime<Utc>>,
+    pub interval: Option<Interval>,
+}
+
+/// List a project's events, newest first
+pub async fn list_events(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(project_id): Path<String>,
+    Query(params): Query<ListEventsParams>,
+) -> AppResult<Json<Vec<Event>>> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ReadEvents,
+        Resource::Project(&project_id),
+    )
+    .await?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+
+    let query = EventQuery {
+        project_id,
+        event_type: params.event_type,
+        user_id: params.user_id,
+        from: params.from,
+        to: params.to,
+        limit,
+    };
+
+    Ok(Json(state.events.query(&query).await?))
+}
+
+/// Event counts and value statistics per time bucket
+pub async fn aggregate_events(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path(project_id): Path<String>,
+    Query(params): Query<AggregateParams>,
+) -> AppResult<Json<Vec<AggregateBucket>>> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ReadEvents,
+        Resource::Project(&project_id),
+    )
+    .await?;
+
+    let to = params.to.unwrap_or_else(Utc::now);
+    let from = params.from.unwrap_or(to - Duration::hours(24));
+    let interval = params.interval.unwrap_or(Interval::Hour);
+    if from >= to {
+        return Err(AppError::BadRequest("from must be before to".to_string()));
+    }
+    if (to - from).num_seconds() / interval.duration().num_seconds() > MAX_BUCKETS {
+        return Err(AppError::BadRequest(format!(
+            "Range spans more than {} buckets, use a wider interval",
+            MAX_BUCKETS
+        )));
+    }
+
+    let query = AggregateQuery {
+        project_id,
+        event_type: params.event_type,
+        from,
+        to,
+        interval,
+    };
+
+    Ok(Json(state.events.aggregate(&query).await?))
+}
@@ -0,0 +1,487 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use super::admin::request_id;
+use crate::{
+    config::ExportConfig,
+    db::{audit, jobs, projects},
+    gdpr,
+    middleware::policy::{self, Action, Resource},
+    models::{
+        AppError, AppResult, DataJob, ExportFormat, JobKind, JobStatus, NewAuditEntry, Principal,
+        Project,
+    },
+    scrub::Scrubber,
+    AppState,
+};
+
+/// Longest user id stored with events
+const MAX_USER_ID_LENGTH: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// Erase every event of a user, in the background
+///
+/// Responds with the queued job, whose status is at the `Location` URL.
+pub async fn delete_user_data(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Path((project_id, user_id)): Path<(String, String)>,
+) -> AppResult<Response> {
+    queue(
+        state,
+        principal,
+        headers,
+        project_id,
+        user_id,
+        JobKind::UserDeletion,
+        None,
+    )
+    .await
+}
+
+/// Write every event of a user to a downloadable file, in the background
+///
+/// Responds with the queued job; once completed, the file is served from
+/// its `download` URL until it expires.
+pub async fn export_user_data(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Path((project_id, user_id)): Path<(String, String)>,
+    Query(params): Query<ExportParams>,
+) -> AppResult<Response> {
+    queue(
+        state,
+        principal,
+        headers,
+        project_id,
+        user_id,
+        JobKind::UserExport,
+        Some(params.format),
+    )
+    .await
+}
+
+/// Stream the file written by a completed export job
+pub async fn download_export(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Path((project_id, job_id)): Path<(String, Uuid)>,
+) -> AppResult<Response> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageUserData,
+        Resource::Project(&project_id),
+    )
+    .await?;
+
+    let job = jobs::find(state.db()?, &project_id, job_id)
+        .await?
+        .filter(|job| job.kind() == Some(JobKind::UserExport))
+        .ok_or_else(|| AppError::NotFound(format!("Export {}", job_id)))?;
+    let path = export_file(&job, &state.config().export, chrono::Utc::now())?;
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                AppError::NotFound(format!("Export {} has expired", job_id))
+            }
+            _ => AppError::Internal(anyhow::Error::new(e).context("Failed to open export")),
+        })?;
+
+    let entry = NewAuditEntry::new(
+        &principal,
+        request_id(&headers),
+        "user.export.download",
+        "user",
+        &job.user_id,
+    )
+    .project(&project_id)
+    .after(&job);
+    audit::record(state.db()?, &entry).await?;
+
+    let format = job.format().unwrap_or_default();
+    let disposition = format!(
+        "attachment; filename=\"export-{}.{}\"",
+        job.id,
+        format.as_str()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+/// File of an export job, while it can still be downloaded at `now`
+fn export_file<'a>(
+    job: &'a DataJob,
+    config: &ExportConfig,
+    now: DateTime<Utc>,
+) -> AppResult<&'a str> {
+    if job.status != JobStatus::Completed.as_str() {
+        return Err(AppError::Conflict(format!(
+            "Export {} is {}",
+            job.id, job.status
+        )));
+    }
+    let expired = gdpr::expires_at(job, config).is_none_or(|expires_at| expires_at <= now);
+    match job.output_path.as_deref() {
+        Some(path) if !expired => Ok(path),
+        _ => Err(AppError::NotFound(format!("Export {} has expired", job.id))),
+    }
+}
+
+/// Status of a data job
+pub async fn get_job(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    Path((project_id, job_id)): Path<(String, Uuid)>,
+) -> AppResult<Json<DataJob>> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageUserData,
+        Resource::Project(&project_id),
+    )
+    .await?;
+
+    let job = jobs::find(state.db()?, &project_id, job_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Job {}", job_id)))?;
+
+    Ok(Json(job))
+}
+
+/// The id `project` stores events of `user_id` under, after scrubbing
+///
+/// Refused when its rules mask or drop the id, since the user's events
+/// can then no longer be told apart from other users'.
+fn stored_user_id(project: &Project, user_id: &str) -> AppResult<String> {
+    if project.scrub_rules.is_empty() {
+        return Ok(user_id.to_string());
+    }
+
+    let scrubber = Scrubber::new(&project.scrub_key, &project.scrub_rules)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Invalid scrubbing rules: {}", e)))?;
+    scrubber.stored_user_id(user_id).ok_or_else(|| {
+        AppError::Conflict(format!(
+            "Project {} masks or drops this user id, so its events cannot be found",
+            project.id
+        ))
+    })
+}
+
+async fn queue(
+    state: AppState,
+    principal: Principal,
+    headers: HeaderMap,
+    project_id: String,
+    user_id: String,
+    kind: JobKind,
+    format: Option<ExportFormat>,
+) -> AppResult<Response> {
+    policy::authorize(
+        &state,
+        &principal,
+        Action::ManageUserData,
+        Resource::Project(&project_id),
+    )
+    .await?;
+
+    if user_id.is_empty() || user_id.len() > MAX_USER_ID_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "user_id must be 1 to {} characters",
+            MAX_USER_ID_LENGTH
+        )));
+    }
+    let project = projects::find_by_id(state.db()?, &project_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Project {}", project_id)))?;
+    let user_id = stored_user_id(&project, &user_id)?;
+
+    let job = jobs::create(
+        state.db()?,
+        kind,
+        &project_id,
+        &user_id,
+        format,
+        &principal,
+        request_id(&headers).as_deref(),
+    )
+    .await?;
+    tracing::info!(job_id = %job.id, kind = %job.kind, %project_id, "Queued data job");
+
+    gdpr::spawn(state, job.clone());
+
+    Ok(accepted(job))
+}
+
+/// 202 response pointing at the status of a queued job
+fn accepted(job: DataJob) -> Response {
+    let location = format!("/api/projects/{}/jobs/{}", job.project_id, job.id);
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(job),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        models::{Detector, ScrubAction, ScrubRule},
+        routes::create_router,
+    };
+    use axum::{
+        body::to_bytes,
+        http::Request,
+        routing::{delete, get, post},
+        Router,
+    };
+    use chrono::Duration;
+    use tower::ServiceExt;
+
+    fn project(rules: &[(Detector, ScrubAction)]) -> Project {
+        Project {
+            id: "web".to_string(),
+            name: "Web".to_string(),
+            organization_id: None,
+            allowed_origins: Vec::new(),
+            retention_days: None,
+            scrub_rules: rules
+                .iter()
+                .map(|&(detector, action)| ScrubRule {
+                    detector: Some(detector),
+                    pattern: None,
+                    key: None,
+                    action,
+                    name: None,
+                })
+                .collect(),
+            scrub_key: b"secret".to_vec(),
+            ip_mode: "drop".to_string(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_jobs_use_the_stored_user_id() {
+        let plain = project(&[]);
+        assert_eq!(
+            stored_user_id(&plain, "jane@example.com").unwrap(),
+            "jane@example.com"
+        );
+
+        // Events are stored under the hash, so jobs must look for it
+        let hashing = project(&[(Detector::Email, ScrubAction::Hash)]);
+        let stored = stored_user_id(&hashing, "jane@example.com").unwrap();
+        let mut event = crate::models::Event::fixture("web").user_id("jane@example.com");
+        Scrubber::new(&hashing.scrub_key, &hashing.scrub_rules)
+            .unwrap()
+            .scrub(&mut event, &mut [0]);
+        assert_eq!(event.user_id, Some(stored));
+        assert_eq!(stored_user_id(&hashing, "u-42").unwrap(), "u-42");
+
+        for action in [ScrubAction::Mask, ScrubAction::Drop] {
+            let masking = project(&[(Detector::Email, action)]);
+            assert!(matches!(
+                stored_user_id(&masking, "jane@example.com"),
+                Err(AppError::Conflict(_))
+            ));
+        }
+    }
+
+    fn job(status: JobStatus, completed_hours_ago: Option<i64>) -> DataJob {
+        DataJob {
+            id: Uuid::new_v4(),
+            kind: JobKind::UserExport.as_str().to_string(),
+            project_id: "web".to_string(),
+            user_id: "jane".to_string(),
+            status: status.as_str().to_string(),
+            requested_by: "config".to_string(),
+            requested_method: "config_key".to_string(),
+            request_id: None,
+            format: Some("ndjson".to_string()),
+            output_path: Some("/var/lib/pulsemetrics/exports/job.ndjson".to_string()),
+            events_affected: None,
+            error: None,
+            created_at: Utc::now(),
+            started_at: None,
+            completed_at: completed_hours_ago.map(|hours| Utc::now() - Duration::hours(hours)),
+        }
+    }
+
+    fn memory_state() -> AppState {
+        let config = Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "memory://".to_string())
+        })
+        .unwrap();
+        AppState::new(None, config)
+    }
+
+    /// The user data routes, called as `principal`
+    fn app(principal: Principal) -> Router {
+        Router::new()
+            .route("/projects/{id}/users/{user_id}", delete(delete_user_data))
+            .route(
+                "/projects/{id}/users/{user_id}/export",
+                post(export_user_data),
+            )
+            .route("/projects/{id}/jobs/{job_id}", get(get_job))
+            .route(
+                "/projects/{id}/jobs/{job_id}/download",
+                get(download_export),
+            )
+            .layer(Extension(principal))
+            .with_state(memory_state())
+    }
+
+    async fn status(app: &Router, method: &str, uri: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_user_data_requires_credentials() {
+        let app = create_router(memory_state());
+        let request = Request::delete("/api/projects/web/users/jane")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_user_data_authorization() {
+        let job_id = Uuid::new_v4();
+        let requests = [
+            ("DELETE", "/projects/web/users/jane".to_string()),
+            ("POST", "/projects/web/users/jane/export".to_string()),
+            ("GET", format!("/projects/web/jobs/{}", job_id)),
+            ("GET", format!("/projects/web/jobs/{}/download", job_id)),
+        ];
+
+        // Neither a member of the project's organization nor bound to it
+        let outsiders = [
+            Principal::jwt("alice".to_string(), None, Vec::new()),
+            Principal::jwt(
+                "ci".to_string(),
+                Some("shop".to_string()),
+                vec![crate::models::SCOPE_ADMIN.to_string()],
+            ),
+        ];
+        for principal in outsiders {
+            let app = app(principal);
+            for (method, uri) in &requests {
+                assert_eq!(
+                    status(&app, method, uri).await,
+                    StatusCode::FORBIDDEN,
+                    "{}",
+                    uri
+                );
+            }
+        }
+
+        // Authorized, but jobs are kept in Postgres
+        let app = app(Principal::config_key());
+        for (method, uri) in &requests {
+            assert_eq!(
+                status(&app, method, uri).await,
+                StatusCode::NOT_FOUND,
+                "{}",
+                uri
+            );
+        }
+        let too_long = format!("/projects/web/users/{}", "u".repeat(MAX_USER_ID_LENGTH + 1));
+        assert_eq!(
+            status(&app, "DELETE", &too_long).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queued_jobs_point_at_their_status() {
+        let job = job(JobStatus::Pending, None);
+        let response = accepted(job.clone());
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            format!("/api/projects/web/jobs/{}", job.id)
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], job.id.to_string());
+        assert_eq!(body["status"], "pending");
+        assert_eq!(body["user_id"], "jane");
+        // The server's file path is never shown
+        assert!(body.get("output_path").is_none());
+    }
+
+    #[test]
+    fn test_export_download_window() {
+        let config = ExportConfig {
+            directory: "/var/lib/pulsemetrics/exports".to_string(),
+            expire_after_hours: 24,
+        };
+        let now = Utc::now();
+
+        let ready = job(JobStatus::Completed, Some(1));
+        assert_eq!(
+            export_file(&ready, &config, now).unwrap(),
+            "/var/lib/pulsemetrics/exports/job.ndjson"
+        );
+
+        for status in [JobStatus::Pending, JobStatus::Running, JobStatus::Failed] {
+            assert!(matches!(
+                export_file(&job(status, None), &config, now),
+                Err(AppError::Conflict(_))
+            ));
+        }
+
+        // Past `expire_after_hours`, or already deleted by the cleanup
+        let expired = job(JobStatus::Completed, Some(25));
+        let mut cleaned = job(JobStatus::Completed, Some(1));
+        cleaned.output_path = None;
+        for job in [expired, cleaned] {
+            assert!(matches!(
+                export_file(&job, &config, now),
+                Err(AppError::NotFound(_))
+            ));
+        }
+        let expires_at = gdpr::expires_at(&ready, &config).unwrap();
+        assert!(export_file(&ready, &config, expires_at - Duration::seconds(1)).is_ok());
+        assert!(matches!(
+            export_file(&ready, &config, expires_at),
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
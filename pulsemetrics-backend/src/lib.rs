@@ -1,31 +1,222 @@
+use arc_swap::ArcSwap;
 use sqlx::PgPool;
-use std::sync::Arc;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing_subscriber::{reload, EnvFilter, Registry};
 
-use crate::config::Config;
+use crate::{
+    client_ip::{IpModes, IpSalts},
+    config::{reload::ReloadOutcome, Config},
+    db::{buffer::EventBuffer, replicas::ReadReplicas},
+    metrics::Metrics,
+    middleware::{
+        cors::ProjectOrigins, jwt::JwtVerifier, rate_limit::RateLimiter, signing::NonceCache,
+    },
+    scrub::ScrubberCache,
+    storage::{EventStore, MemoryStore, PostgresStore},
+};
+
+/// Handle for swapping the active log filter at runtime
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
 
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
-    pub db: PgPool,
-    pub config: Arc<Config>,
+    /// Projects, keys, organizations and the audit log, `None` without
+    /// Postgres, leaving only the config API key to authenticate
+    pub db: Option<PgPool>,
+    /// Event data, in `db` unless another backend is configured
+    pub events: Arc<dyn EventStore>,
+    /// TimescaleDB holding `events`, `None` for other backends
+    pub events_db: Option<PgPool>,
+    /// Standbys serving event queries, reported by the health check
+    pub replicas: ReadReplicas,
+    /// Running configuration, replaced as a whole on reload
+    pub config: Arc<ArcSwap<Config>>,
+    /// File the configuration was loaded from, read again on reload
+    pub config_path: Option<PathBuf>,
+    pub nonces: Arc<NonceCache>,
+    pub jwt: Option<Arc<JwtVerifier>>,
+    pub metrics: Arc<Metrics>,
+    pub buffer: EventBuffer,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub scrubbers: Arc<ScrubberCache>,
+    pub ip_modes: Arc<IpModes>,
+    pub ip_salts: Arc<IpSalts>,
+    pub origins: Arc<ProjectOrigins>,
+    pub log_filter: Option<LogFilterHandle>,
+    /// When the process started serving, for uptime reporting
+    pub started_at: Instant,
+    reload_lock: Arc<Mutex<()>>,
 }
 
 impl AppState {
-    pub fn new(db: PgPool, config: Config) -> Self {
+    /// Events are stored in `db`, or in memory without it, until
+    /// [`AppState::with_event_store`] says otherwise
+    pub fn new(db: Option<PgPool>, config: Config) -> Self {
+        let metrics = Arc::new(Metrics::new());
+        let events: Arc<dyn EventStore> = match &db {
+            Some(db) => Arc::new(PostgresStore::new(db.clone(), metrics.clone())),
+            None => Arc::new(MemoryStore::new()),
+        };
         Self {
+            events,
+            events_db: None,
             db,
-            config: Arc::new(config),
+            replicas: ReadReplicas::default(),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            config_path: None,
+            nonces: Arc::new(NonceCache::new()),
+            jwt: None,
+            metrics,
+            buffer: EventBuffer::new(),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            scrubbers: Arc::new(ScrubberCache::new()),
+            ip_modes: Arc::new(IpModes::new()),
+            ip_salts: Arc::new(IpSalts::new()),
+            origins: Arc::new(ProjectOrigins::new()),
+            log_filter: None,
+            started_at: Instant::now(),
+            reload_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Enable JWT authentication with the given verifier
+    pub fn with_jwt(mut self, verifier: Option<JwtVerifier>) -> Self {
+        self.jwt = verifier.map(Arc::new);
+        self
+    }
+
+    /// Store events somewhere other than the Postgres database
+    pub fn with_event_store(mut self, store: Arc<dyn EventStore>) -> Self {
+        self.events = store;
+        self
+    }
+
+    /// Report chunk sizes of the TimescaleDB storing events
+    pub fn with_events_db(mut self, pool: PgPool) -> Self {
+        self.events_db = Some(pool);
+        self
+    }
+
+    /// Report the health of the event database's read replicas
+    pub fn with_read_replicas(mut self, replicas: ReadReplicas) -> Self {
+        self.replicas = replicas;
+        self
+    }
+
+    /// Hash client IPs with salts agreed on through `salts`
+    pub fn with_ip_salts(mut self, salts: IpSalts) -> Self {
+        self.ip_salts = Arc::new(salts);
+        self
+    }
+
+    /// Reload configuration from `path` as well as the environment
+    pub fn with_config_path(mut self, path: Option<PathBuf>) -> Self {
+        self.config_path = path;
+        self
+    }
+
+    /// Let config reloads change the log filter
+    pub fn with_log_filter(mut self, handle: LogFilterHandle) -> Self {
+        self.log_filter = Some(handle);
+        self
+    }
+
+    /// Database of projects, keys, organizations and the audit log
+    ///
+    /// Without one, endpoints managing them respond 404.
+    pub fn db(&self) -> AppResult<&PgPool> {
+        self.db.as_ref().ok_or_else(|| {
+            AppError::NotFound(
+                "Projects, keys and organizations require a Postgres database".to_string(),
+            )
+        })
+    }
+
+    /// Snapshot of the running configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// Reload configuration from its sources and apply the reloadable settings
+    ///
+    /// An invalid configuration is rejected and the running one is kept.
+    pub fn reload_config(&self) -> anyhow::Result<ReloadOutcome> {
+        let _guard = self.reload_lock.lock().expect("reload lock poisoned");
+
+        let candidate = Config::load(self.config_path.as_deref())?;
+        let current = self.config();
+        let (next, outcome) = config::reload::apply(&current, &candidate);
+        next.validate()?;
+
+        if next.log.filter != current.log.filter {
+            if let Some(handle) = &self.log_filter {
+                handle.reload(EnvFilter::try_new(&next.log.filter)?)?;
+            }
+        }
+        self.config.store(Arc::new(next));
+
+        tracing::info!(changed = ?outcome.changed, "Configuration reloaded");
+        if !outcome.requires_restart.is_empty() {
+            tracing::warn!(
+                sections = ?outcome.requires_restart,
+                "Configuration changes require a restart to take effect"
+            );
         }
+
+        Ok(outcome)
     }
 }
 
 // Re-export commonly used items
+pub mod client_ip;
 pub mod config;
 pub mod db;
+pub mod gdpr;
 pub mod handlers;
+pub mod metrics;
 pub mod middleware;
 pub mod models;
+pub mod retention;
 pub mod routes;
+pub mod scrub;
+pub mod storage;
+pub mod telemetry;
 pub mod utils;
 
-pub use models::{AppError, AppResult};
\ No newline at end of file
+pub use models::{AppError, AppResult};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_reads_the_startup_file() {
+        let directory = std::env::temp_dir().join(format!("pulsemetrics-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.toml");
+        let write = |events_per_second: u32| {
+            let toml = format!(
+                "[database]\nurl = \"memory://\"\n\n[rate_limit]\nevents_per_second = {}\n",
+                events_per_second
+            );
+            std::fs::write(&path, toml).unwrap();
+        };
+
+        write(5);
+        let config = Config::load(Some(&path)).unwrap();
+        let state = AppState::new(None, config).with_config_path(Some(path.clone()));
+        assert_eq!(state.config().rate_limit.events_per_second, 5);
+
+        write(7);
+        let outcome = state.reload_config();
+        std::fs::remove_dir_all(&directory).ok();
+
+        assert_eq!(outcome.unwrap().changed, ["rate_limit.events_per_second"]);
+        assert_eq!(state.config().rate_limit.events_per_second, 7);
+    }
+}
@@ -1,39 +1,193 @@
 use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use clap::{Parser, ValueEnum};
 use pulsemetrics_backend::{
-    config::Config,
-    db::{create_pool, run_migrations},
+    client_ip::{self, IpSalts},
+    config::{Config, DatabaseConfig},
+    db::{compression, create_pool, replicas::ReadReplicas, run_migrations},
+    gdpr, metrics,
+    middleware::{cors, jwt::JwtVerifier},
+    retention,
     routes::create_router,
-    AppState,
+    scrub,
+    storage::{Backend, ClickHouseStore, MemoryStore, PostgresStore, SqliteStore},
+    telemetry, AppState,
 };
-use std::time::Duration;
+use sqlx::PgPool;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 use tokio::signal;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tokio_util::sync::CancellationToken;
+
+/// PulseMetrics ingestion and query server
+#[derive(Debug, Parser)]
+#[command(name = "pulsemetrics-backend", version)]
+struct Cli {
+    /// TOML or YAML config file, defaults to `CONFIG_FILE`
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Where events are stored, overriding `database.url`
+    #[arg(long, value_enum)]
+    storage: Option<Storage>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Storage {
+    Postgres,
+    /// Keep events in process memory, for local development
+    Memory,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    init_tracing();
+    let cli = Cli::parse();
 
-    tracing::info!("Starting PulseMetrics Backend");
+    // Set through the environment so config reloads keep the override
+    if let Some(Storage::Memory) = cli.storage {
+        std::env::set_var("DATABASE_URL", "memory://");
+    }
+
+    // Load configuration, before tracing since it holds the log filter
+    let config = Config::load(cli.config.as_deref()).context("Failed to load configuration")?;
+
+    // Initialize tracing, kept alive until shutdown to flush file output
+    let telemetry =
+        telemetry::init(&config.log, &config.otel).context("Failed to initialize logging")?;
 
-    // Load configuration
-    let config = Config::from_env().context("Failed to load configuration")?;
+    tracing::info!("Starting PulseMetrics Backend");
     tracing::info!("Configuration loaded successfully");
     tracing::debug!("Environment: {:?}", config.app.environment);
 
-    // Create database connection pool
-    let pool = create_pool(&config.database)
-        .await
-        .context("Failed to create database pool")?;
+    let backend = config
+        .database
+        .backend()
+        .map_err(anyhow::Error::msg)
+        .context("Invalid database URL")?;
 
-    // Run migrations
-    run_migrations(&pool)
-        .await
-        .context("Failed to run database migrations")?;
+    // Load JWT verification keys
+    let jwt = JwtVerifier::from_config(&config.jwt).context("Failed to load JWT configuration")?;
+
+    // Projects, keys, organizations and the audit log
+    let pool = if Backend::from_url(&config.database.url) == Ok(Backend::Postgres) {
+        Some(connect_postgres(&config.database).await?)
+    } else {
+        tracing::warn!("No Postgres database configured, only the config API key is accepted");
+        None
+    };
 
     // Create application state
     let state = AppState::new(pool, config.clone());
 
+    // Standbys of the event database, checked before receiving queries
+    let replicas =
+        ReadReplicas::from_config(&config.database).context("Invalid read replica URL")?;
+
+    // Event storage, the same database unless `database.events_url` is set
+    let state = match backend {
+        Backend::Postgres => {
+            let pool = match &state.db {
+                Some(pool) if config.database.events_url() == config.database.url => pool.clone(),
+                _ => {
+                    let database = DatabaseConfig {
+                        url: config.database.events_url().to_string(),
+                        ..config.database.clone()
+                    };
+                    connect_postgres(&database).await?
+                }
+            };
+            compression::apply_policy(&pool, &config.compression)
+                .await
+                .context("Failed to apply the compression policy")?;
+            let store = PostgresStore::new(pool.clone(), state.metrics.clone())
+                .with_replicas(replicas.clone());
+            state.with_event_store(Arc::new(store)).with_events_db(pool)
+        }
+        Backend::ClickHouse => {
+            let store = ClickHouseStore::from_url(config.database.events_url())?;
+            store.ensure_schema().await?;
+            tracing::info!("Storing events in ClickHouse");
+            state.with_event_store(Arc::new(store))
+        }
+        Backend::Sqlite => {
+            let store = SqliteStore::connect(config.database.events_url()).await?;
+            tracing::info!("Storing events in {}", config.database.events_url());
+            state.with_event_store(Arc::new(store))
+        }
+        Backend::Memory => {
+            tracing::warn!("Storing events in memory, they are lost on shutdown");
+            state.with_event_store(Arc::new(MemoryStore::new()))
+        }
+    };
+    let state = state
+        .with_read_replicas(replicas)
+        .with_jwt(jwt)
+        .with_config_path(cli.config.clone())
+        .with_log_filter(telemetry.log_filter.clone());
+
+    // Instances sharing Postgres hash client IPs with the same salt
+    let state = match state.db.clone() {
+        Some(pool) => state.with_ip_salts(IpSalts::shared(pool)),
+        None => state,
+    };
+
+    // Start writing buffered events in the background
+    let shutdown = CancellationToken::new();
+    let flusher = state.buffer.start(
+        state.events.clone(),
+        state.config.clone(),
+        state.metrics.clone(),
+        shutdown.clone(),
+    );
+
+    // Route queries to replicas only while they keep up
+    if !state.replicas.is_empty() {
+        let count = state.replicas.iter().count();
+        tracing::info!("Serving event queries from {} read replicas", count);
+        state
+            .replicas
+            .start_monitor(state.config.clone(), shutdown.clone());
+    }
+
+    // Settings and background jobs kept in Postgres
+    if state.db.is_some() {
+        // Scrub events as their project's rules say
+        scrub::refresh(&state)
+            .await
+            .context("Failed to load scrubbing rules")?;
+        scrub::start(state.clone(), shutdown.clone());
+
+        // Capture client IPs as their project allows
+        client_ip::refresh(&state)
+            .await
+            .context("Failed to load IP modes")?;
+        client_ip::start(state.clone(), shutdown.clone());
+
+        // Check browser origins against each project's own
+        cors::refresh(&state)
+            .await
+            .context("Failed to load project origins")?;
+        cors::start(state.clone(), shutdown.clone());
+
+        // Label metrics by registered projects only
+        metrics::refresh(&state)
+            .await
+            .context("Failed to load registered projects")?;
+        metrics::start(state.clone(), shutdown.clone());
+
+        // Delete events past their project's retention, and expired exports
+        retention::start(state.clone(), shutdown.clone());
+        gdpr::start_cleanup(state.clone(), shutdown.clone());
+
+        // Finish data jobs interrupted by the last shutdown
+        gdpr::resume(&state)
+            .await
+            .context("Failed to resume data jobs")?;
+    }
+
+    // Reload runtime settings on SIGHUP
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(state.clone()));
+
     // Build router
     let app = create_router(state);
 
@@ -43,33 +197,92 @@ async fn main() -> anyhow::Result<()> {
         .socket_addr()
         .context("Failed to parse socket address")?;
 
-    tracing::info!("Server listening on {}", addr);
+    if let Some((cert, key)) = config.server.tls() {
+        // Run server over TLS with graceful shutdown
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let tls = RustlsConfig::from_pem_file(cert, key)
+            .await
+            .context("Failed to load TLS certificate or key")?;
 
-    // Create server
-    let listener = tokio::net::TcpListener::bind(addr)
-        .await
-        .context("Failed to bind to address")?;
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                shutdown_signal().await;
+                handle.graceful_shutdown(Some(Duration::from_secs(10)));
+            }
+        });
+
+        tracing::info!("Server listening on {} (TLS)", addr);
+
+        axum_server::bind_rustls(addr, tls)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .context("Server error")?;
+    } else {
+        tracing::info!("Server listening on {}", addr);
 
-    // Run server with graceful shutdown
-    axum::serve(listener, app)
+        // Create server
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .context("Failed to bind to address")?;
+
+        // Run server with graceful shutdown
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
         .with_graceful_shutdown(shutdown_signal())
         .await
         .context("Server error")?;
+    }
+
+    // Flush events accepted before shutdown
+    shutdown.cancel();
+    flusher.await.context("Event buffer flusher failed")?;
 
     tracing::info!("Server shut down gracefully");
+    telemetry.shutdown();
 
     Ok(())
 }
 
-/// Initialize tracing/logging
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "info,pulsemetrics_backend=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer().with_target(true))
-        .init();
+/// Connect to a Postgres database and apply pending migrations
+async fn connect_postgres(database: &DatabaseConfig) -> anyhow::Result<PgPool> {
+    // Create database connection pool
+    let pool = create_pool(database)
+        .await
+        .context("Failed to create database pool")?;
+
+    // Run migrations
+    run_migrations(&pool)
+        .await
+        .context("Failed to run database migrations")?;
+
+    Ok(pool)
+}
+
+/// Reload the runtime configuration whenever the process receives SIGHUP
+#[cfg(unix)]
+async fn reload_on_hangup(state: AppState) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::error!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("Received SIGHUP, reloading configuration");
+        if let Err(e) = state.reload_config() {
+            tracing::error!(
+                "Configuration reload rejected, keeping current settings: {:#}",
+                e
+            );
+        }
+    }
 }
 
 /// Graceful shutdown signal handler
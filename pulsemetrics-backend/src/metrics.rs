@@ -0,0 +1,348 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::{collections::HashSet, sync::RwLock, time::Duration};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{db::projects, AppState};
+
+/// Prefix shared by every metric name
+const NAMESPACE: &str = "pulsemetrics";
+
+/// `project_id` label of events naming a project that is not registered
+pub const OTHER_PROJECT: &str = "other";
+
+/// How often the registered projects are reloaded
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Prometheus metrics for the service
+///
+/// Request, ingestion and database metrics are recorded as they happen;
+/// pool and buffer gauges are sampled when the registry is scraped.
+/// Project ids arrive in request bodies, so only registered projects get
+/// their own `project_id` label; the rest share [`OTHER_PROJECT`].
+pub struct Metrics {
+    registry: Registry,
+    projects: RwLock<HashSet<String>>,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub events_ingested: IntCounterVec,
+    pub events_rejected: IntCounterVec,
+    pub batch_size: Histogram,
+    pub cors_rejections: IntCounter,
+    pub db_pool_connections: IntGaugeVec,
+    pub db_pool_max_connections: IntGauge,
+    pub db_pool_acquire_duration: Histogram,
+    pub buffer_depth: IntGauge,
+    pub events_dropped: IntCounterVec,
+    pub retention_events_deleted: IntCounterVec,
+    pub retention_last_run: IntGauge,
+    pub chunks_decompressed: IntCounter,
+    pub pii_redactions: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            opts("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::from(opts(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests",
+            )),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let events_ingested = IntCounterVec::new(
+            opts("events_ingested_total", "Events accepted for ingestion"),
+            &["project_id"],
+        )
+        .unwrap();
+        let events_rejected = IntCounterVec::new(
+            opts("events_rejected_total", "Events refused at ingestion"),
+            &["project_id", "reason"],
+        )
+        .unwrap();
+        let batch_size = Histogram::with_opts(
+            HistogramOpts::from(opts("ingest_batch_size", "Events per ingestion request"))
+                .buckets(vec![1.0, 10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 5000.0]),
+        )
+        .unwrap();
+        let cors_rejections = IntCounter::with_opts(opts(
+            "cors_rejections_total",
+            "Requests refused for a disallowed origin",
+        ))
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            opts("db_pool_connections", "Open database connections"),
+            &["state"],
+        )
+        .unwrap();
+        let db_pool_max_connections = IntGauge::with_opts(opts(
+            "db_pool_max_connections",
+            "Configured maximum size of the database pool",
+        ))
+        .unwrap();
+        let db_pool_acquire_duration = Histogram::with_opts(
+            HistogramOpts::from(opts(
+                "db_pool_acquire_duration_seconds",
+                "Time spent waiting for a database connection",
+            ))
+            .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+        )
+        .unwrap();
+        let buffer_depth = IntGauge::with_opts(opts(
+            "ingest_buffer_depth",
+            "Events accepted but not yet written to the database",
+        ))
+        .unwrap();
+        let events_dropped = IntCounterVec::new(
+            opts(
+                "events_dropped_total",
+                "Events accepted for ingestion but never written to the database",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+
+        let retention_events_deleted = IntCounterVec::new(
+            opts(
+                "retention_events_deleted_total",
+                "Events deleted for exceeding their project's retention",
+            ),
+            &["project_id"],
+        )
+        .unwrap();
+        let retention_last_run = IntGauge::with_opts(opts(
+            "retention_last_run_timestamp_seconds",
+            "Unix time the last retention run completed",
+        ))
+        .unwrap();
+
+        let chunks_decompressed = IntCounter::with_opts(opts(
+            "compressed_chunks_decompressed_total",
+            "Compressed chunks decompressed to write late events or delete events",
+        ))
+        .unwrap();
+
+        let pii_redactions = IntCounterVec::new(
+            opts(
+                "pii_redactions_total",
+                "Values scrubbed from events at ingestion by a project's rules",
+            ),
+            &["project_id", "rule", "action"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(events_ingested.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(events_rejected.clone()))
+            .unwrap();
+        registry.register(Box::new(batch_size.clone())).unwrap();
+        registry
+            .register(Box::new(cors_rejections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_max_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_acquire_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(buffer_depth.clone())).unwrap();
+        registry.register(Box::new(events_dropped.clone())).unwrap();
+        registry
+            .register(Box::new(retention_events_deleted.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(retention_last_run.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(chunks_decompressed.clone()))
+            .unwrap();
+        registry.register(Box::new(pii_redactions.clone())).unwrap();
+
+        Self {
+            registry,
+            projects: RwLock::new(HashSet::new()),
+            http_requests,
+            http_request_duration,
+            events_ingested,
+            events_rejected,
+            batch_size,
+            cors_rejections,
+            db_pool_connections,
+            db_pool_max_connections,
+            db_pool_acquire_duration,
+            buffer_depth,
+            events_dropped,
+            retention_events_deleted,
+            retention_last_run,
+            chunks_decompressed,
+            pii_redactions,
+        }
+    }
+
+    /// Record a completed HTTP request
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration
+            .with_label_values(&labels)
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Label `project_id` under its own id once it is registered
+    pub fn add_project(&self, project_id: &str) {
+        self.projects
+            .write()
+            .expect("metric projects poisoned")
+            .insert(project_id.to_string());
+    }
+
+    /// Keep only the given projects
+    fn replace_projects(&self, project_ids: Vec<String>) {
+        *self.projects.write().expect("metric projects poisoned") =
+            project_ids.into_iter().collect();
+    }
+
+    /// `project_id` label for events naming `project_id`
+    pub fn project_label<'a>(&self, project_id: &'a str) -> &'a str {
+        let projects = self.projects.read().expect("metric projects poisoned");
+        if projects.contains(project_id) {
+            project_id
+        } else {
+            OTHER_PROJECT
+        }
+    }
+
+    /// Count `count` events from `project_id` accepted for ingestion
+    pub fn ingest_events(&self, project_id: &str, count: usize) {
+        self.events_ingested
+            .with_label_values(&[self.project_label(project_id)])
+            .inc_by(count as u64);
+    }
+
+    /// Count `count` events from `project_id` refused for `reason`
+    pub fn reject_events(&self, project_id: &str, reason: &str, count: usize) {
+        self.events_rejected
+            .with_label_values(&[self.project_label(project_id), reason])
+            .inc_by(count as u64);
+    }
+
+    /// Sample the pool and buffer gauges, then encode every metric in the text format
+    ///
+    /// Pool gauges stay at zero without a Postgres database.
+    pub fn render(&self, pool: Option<&PgPool>, buffer_depth: usize) -> String {
+        if let Some(pool) = pool {
+            let size = i64::from(pool.size());
+            let idle = pool.num_idle() as i64;
+            self.db_pool_connections
+                .with_label_values(&["idle"])
+                .set(idle);
+            self.db_pool_connections
+                .with_label_values(&["in_use"])
+                .set(size - idle);
+            self.db_pool_max_connections
+                .set(i64::from(pool.options().get_max_connections()));
+        }
+        self.buffer_depth.set(buffer_depth as i64);
+
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("text encoding cannot fail");
+        String::from_utf8(buf).expect("text encoding is UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn opts(name: &str, help: &str) -> Opts {
+    Opts::new(name, help).namespace(NAMESPACE)
+}
+
+/// Load the ids of every registered project, none without Postgres
+pub async fn refresh(state: &AppState) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    state.metrics.replace_projects(projects::ids(db).await?);
+    Ok(())
+}
+
+/// Spawn the task reloading registered projects every `REFRESH_INTERVAL`
+pub fn start(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = shutdown.cancelled() => break,
+            }
+
+            if let Err(e) = refresh(&state).await {
+                tracing::warn!("Failed to reload registered projects: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unregistered_projects_share_a_label() {
+        let metrics = Metrics::new();
+        metrics.add_project("web");
+
+        metrics.ingest_events("web", 2);
+        metrics.ingest_events("spam-1", 1);
+        metrics.ingest_events("spam-2", 4);
+        metrics.reject_events("spam-3", "rate_limited", 3);
+
+        assert_eq!(metrics.events_ingested.with_label_values(&["web"]).get(), 2);
+        assert_eq!(
+            metrics
+                .events_ingested
+                .with_label_values(&[OTHER_PROJECT])
+                .get(),
+            5
+        );
+        assert_eq!(
+            metrics
+                .events_rejected
+                .with_label_values(&[OTHER_PROJECT, "rate_limited"])
+                .get(),
+            3
+        );
+
+        let body = metrics.render(None, 0);
+        assert!(!body.contains("spam"));
+
+        metrics.replace_projects(vec!["mobile".to_string()]);
+        assert_eq!(metrics.project_label("web"), OTHER_PROJECT);
+        assert_eq!(metrics.project_label("mobile"), "mobile");
+    }
+}
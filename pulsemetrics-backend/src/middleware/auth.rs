@@ -1,34 +1,180 @@
 use axum::{
-    extract::{Request, State},
+    body::{self, Body},
+    extract::{OriginalUri, Request, State},
     http::header,
     middleware::Next,
     response::Response,
 };
+use uuid::Uuid;
 
-use crate::{models::AppError, AppState};
+use super::{
+    cors, jwt,
+    signing::{self, SignatureHeader},
+};
+use crate::{
+    db::api_keys,
+    models::{AppError, AuthMethod, AuthScheme, Principal},
+    AppState,
+};
 
-/// Simple API key authentication middleware
+/// Authentication middleware
+///
+/// Accepts a bearer token (the global config key, a project key or a JWT)
+/// or a request signed with a project key's secret. The resulting
+/// [`Principal`] is inserted into the request extensions.
 pub async fn auth(
     State(state): State<AppState>,
     req: Request,
     next: Next,
 ) -> Result<Response, AppError> {
-    // Extract API key from Authorization header
+    // Browsers send preflights without credentials, `cors` answers them
+    if cors::is_preflight(&req) {
+        return Ok(next.run(req).await);
+    }
+
+    let (principal, mut req) = authenticate(&state, req).await?;
+    req.extensions_mut().insert(principal);
+
+    Ok(next.run(req).await)
+}
+
+/// Check the credentials of a request, timed apart from the handler it guards
+#[tracing::instrument(name = "auth", skip_all)]
+async fn authenticate(state: &AppState, req: Request) -> Result<(Principal, Request), AppError> {
+    // Extract credentials from Authorization header
     let auth_header = req
         .headers()
         .get(header::AUTHORIZATION)
         .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AppError::Unauthorized("Missing authorization header".to_string()))?;
+        .ok_or_else(|| AppError::Unauthorized("Missing authorization header".to_string()))?
+        .to_string();
 
-    // Expected format: "Bearer <api_key>"
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or_else(|| AppError::Unauthorized("Invalid authorization format".to_string()))?;
+    if let Some(token) = auth_header.strip_prefix("Bearer ") {
+        Ok((authenticate_bearer(state, token).await?, req))
+    } else if let Some(params) = auth_header
+        .strip_prefix(signing::SCHEME)
+        .and_then(|rest| rest.strip_prefix(' '))
+    {
+        authenticate_signature(state, params, req).await
+    } else {
+        Err(AppError::Unauthorized(
+            "Invalid authorization format".to_string(),
+        ))
+    }
+}
 
-    // Validate API key
-    if token != state.config.app.api_key {
-        return Err(AppError::Unauthorized("Invalid API key".to_string()));
+/// Authenticate a `Bearer <token>` credential
+async fn authenticate_bearer(state: &AppState, token: &str) -> Result<Principal, AppError> {
+    if token == state.config().app.api_key {
+        return Ok(Principal::config_key());
     }
 
-    Ok(next.run(req).await)
-}
\ No newline at end of file
+    if let Some(verifier) = state.jwt.as_deref().filter(|_| jwt::looks_like_jwt(token)) {
+        let claims = verifier.verify(token)?;
+        let scopes = claims.scopes();
+
+        return Ok(Principal::jwt(claims.sub, claims.project, scopes));
+    }
+
+    let key = match &state.db {
+        Some(db) => api_keys::find_active_by_token_hash(db, &api_keys::hash_token(token)).await?,
+        None => None,
+    }
+    .ok_or_else(|| AppError::Unauthorized("Invalid API key".to_string()))?;
+
+    if key.scheme() != Some(AuthScheme::Bearer) {
+        return Err(AppError::Unauthorized(
+            "API key requires signed requests".to_string(),
+        ));
+    }
+
+    Ok(Principal::api_key(&key, AuthMethod::ApiKey))
+}
+
+/// Authenticate a `PM-HMAC-SHA256` signed request
+///
+/// The body has to be buffered to hash it, so the request is rebuilt
+/// and handed back for the rest of the stack.
+async fn authenticate_signature(
+    state: &AppState,
+    params: &str,
+    req: Request,
+) -> Result<(Principal, Request), AppError> {
+    let header = SignatureHeader::parse(params)
+        .ok_or_else(|| AppError::Unauthorized("Malformed signature header".to_string()))?;
+
+    let key_id: Uuid = header
+        .key_id
+        .parse()
+        .map_err(|_| AppError::Unauthorized("Invalid key id".to_string()))?;
+
+    let timestamp: i64 = required_header(&req, signing::TIMESTAMP_HEADER)?
+        .parse()
+        .map_err(|_| AppError::Unauthorized("Invalid signature timestamp".to_string()))?;
+    let nonce = required_header(&req, signing::NONCE_HEADER)?.to_string();
+
+    // Reject stale or future-dated requests
+    let now = chrono::Utc::now().timestamp();
+    let max_skew = state.config().app.hmac_max_skew_seconds as i64;
+    if (now - timestamp).abs() > max_skew {
+        return Err(AppError::Unauthorized(
+            "Signature timestamp outside accepted window".to_string(),
+        ));
+    }
+
+    let key = match &state.db {
+        Some(db) => api_keys::find_active_by_id(db, key_id).await?,
+        None => None,
+    }
+    .ok_or_else(|| AppError::Unauthorized("Invalid API key".to_string()))?;
+
+    let secret = match (key.scheme(), key.signing_secret.as_deref()) {
+        (Some(AuthScheme::Hmac), Some(secret)) => secret.to_string(),
+        _ => {
+            return Err(AppError::Unauthorized(
+                "API key does not support signed requests".to_string(),
+            ))
+        }
+    };
+
+    // Sign the path as the client sent it, not as seen inside nested routers
+    let path_and_query = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| req.uri().clone())
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let method = req.method().as_str().to_string();
+
+    let (parts, body) = req.into_parts();
+    let bytes = body::to_bytes(body, signing::MAX_SIGNED_BODY_BYTES)
+        .await
+        .map_err(|_| AppError::BadRequest("Request body too large to verify".to_string()))?;
+
+    let string_to_sign =
+        signing::string_to_sign(&method, &path_and_query, timestamp, &nonce, &bytes);
+    if !signing::verify(secret.as_bytes(), &string_to_sign, &header.signature) {
+        return Err(AppError::Unauthorized("Invalid signature".to_string()));
+    }
+
+    // Only remember nonces of correctly signed requests
+    if !state
+        .nonces
+        .check_and_insert(format!("{}:{}", key.id, nonce), timestamp + max_skew, now)
+    {
+        return Err(AppError::Unauthorized("Replayed request nonce".to_string()));
+    }
+
+    let req = Request::from_parts(parts, Body::from(bytes));
+
+    Ok((Principal::api_key(&key, AuthMethod::Signature), req))
+}
+
+fn required_header<'a>(req: &'a Request, name: &str) -> Result<&'a str, AppError> {
+    req.headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .ok_or_else(|| AppError::Unauthorized(format!("Missing {} header", name)))
+}
@@ -0,0 +1,329 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    db::projects,
+    models::{AppError, Principal, Project},
+    AppState,
+};
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str =
+    "authorization, content-type, x-pm-timestamp, x-pm-nonce, x-request-id";
+/// Response headers readable by browser scripts
+const EXPOSED_HEADERS: &str = "x-request-id";
+const PREFLIGHT_MAX_AGE_SECONDS: &str = "600";
+/// Time before origins changed through another instance apply here
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Browser origins allowed by each project defining its own
+///
+/// Kept in memory like the scrubbing rules, so no request waits on the
+/// database for its CORS check; [`start`] reloads them for changes made
+/// through other instances.
+#[derive(Default)]
+pub struct ProjectOrigins {
+    origins: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl ProjectOrigins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Origins of a project, `None` when it defines none
+    pub fn get(&self, project_id: &str) -> Option<Vec<String>> {
+        let origins = self.origins.lock().expect("project origins poisoned");
+        origins.get(project_id).cloned()
+    }
+
+    pub fn set(&self, project_id: &str, allowed_origins: &[String]) {
+        let mut origins = self.origins.lock().expect("project origins poisoned");
+        if allowed_origins.is_empty() {
+            origins.remove(project_id);
+        } else {
+            origins.insert(project_id.to_string(), allowed_origins.to_vec());
+        }
+    }
+
+    /// Whether any project allows `origin`
+    pub fn any_allows(&self, origin: &str) -> bool {
+        let origins = self.origins.lock().expect("project origins poisoned");
+        origins.values().any(|origins| allows(origins, origin))
+    }
+
+    /// Keep only the given projects, with their current origins
+    fn replace(&self, projects: &[Project]) {
+        *self.origins.lock().expect("project origins poisoned") = projects
+            .iter()
+            .map(|p| (p.id.clone(), p.allowed_origins.clone()))
+            .collect();
+    }
+}
+
+/// Load the origins of every project defining its own, none without Postgres
+pub async fn refresh(state: &AppState) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    let projects = projects::with_allowed_origins(db).await?;
+    state.origins.replace(&projects);
+    Ok(())
+}
+
+/// Spawn the task reloading project origins every `REFRESH_INTERVAL`
+pub fn start(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = shutdown.cancelled() => break,
+            }
+
+            if let Err(e) = refresh(&state).await {
+                tracing::warn!("Failed to reload project origins: {}", e);
+            }
+        }
+    })
+}
+
+/// Per-project CORS middleware
+///
+/// Runs inside `auth`, so actual requests are checked against the origins
+/// of the project their key belongs to, falling back to the global
+/// `CorsConfig::allowed_origins` when the project defines none. Preflight
+/// requests carry no credentials, so they are allowed when the origin is
+/// accepted globally or by any project; the request that follows is then
+/// held to its own project's list.
+pub async fn cors(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(origin) = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+    else {
+        return next.run(req).await;
+    };
+
+    if is_preflight(&req) {
+        return if preflight_allowed(&state, &origin) {
+            preflight_response(&origin)
+        } else {
+            reject(&state, &origin, None)
+        };
+    }
+
+    let project_id = req
+        .extensions()
+        .get::<Principal>()
+        .and_then(|p| p.project_id.clone());
+
+    if !origin_allowed(&state, project_id.as_deref(), &origin) {
+        return reject(&state, &origin, project_id.as_deref());
+    }
+
+    let mut response = next.run(req).await;
+    set_allow_origin(response.headers_mut(), &origin);
+    response
+}
+
+/// Whether a request is a CORS preflight
+pub fn is_preflight(req: &Request) -> bool {
+    req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+fn allows(origins: &[String], origin: &str) -> bool {
+    origins.iter().any(|o| o == "*" || o == origin)
+}
+
+fn preflight_allowed(state: &AppState, origin: &str) -> bool {
+    allows(&state.config().cors.allowed_origins, origin) || state.origins.any_allows(origin)
+}
+
+fn origin_allowed(state: &AppState, project_id: Option<&str>, origin: &str) -> bool {
+    match project_id.and_then(|project_id| state.origins.get(project_id)) {
+        Some(origins) => allows(&origins, origin),
+        None => allows(&state.config().cors.allowed_origins, origin),
+    }
+}
+
+fn reject(state: &AppState, origin: &str, project_id: Option<&str>) -> Response {
+    state.metrics.cors_rejections.inc();
+    tracing::warn!(
+        origin = %origin,
+        project_id = project_id.unwrap_or("-"),
+        "Rejected request from disallowed origin"
+    );
+
+    AppError::Forbidden(format!("Origin {} is not allowed", origin)).into_response()
+}
+
+fn preflight_response(origin: &str) -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    set_allow_origin(headers, origin);
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static(ALLOWED_METHODS),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static(ALLOWED_HEADERS),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_MAX_AGE,
+        HeaderValue::from_static(PREFLIGHT_MAX_AGE_SECONDS),
+    );
+    response
+}
+
+fn set_allow_origin(headers: &mut HeaderMap, origin: &str) {
+    if let Ok(value) = HeaderValue::from_str(origin) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSED_HEADERS),
+        );
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{body::Body, middleware, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    const GLOBAL: &str = "https://global.example.com";
+    const WEB: &str = "https://web.example.com";
+
+    fn state() -> AppState {
+        let config = Config::load_from(None, |var| match var {
+            "DATABASE_URL" => Some("memory://".to_string()),
+            "CORS_ALLOWED_ORIGINS" => Some(GLOBAL.to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let state = AppState::new(None, config);
+        state.origins.set("web", &[WEB.to_string()]);
+        state
+    }
+
+    /// `cors` in front of a handler, reached by `principal` as `auth` would set it
+    fn app(state: &AppState, principal: Principal) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(state.clone(), cors))
+            .layer(Extension(principal))
+    }
+
+    fn project_principal(project_id: &str) -> Principal {
+        Principal::jwt(
+            "alice".to_string(),
+            Some(project_id.to_string()),
+            Vec::new(),
+        )
+    }
+
+    async fn request(app: &Router, method: Method, origin: &str) -> Response {
+        let mut request = axum::http::Request::builder()
+            .method(method.clone())
+            .uri("/")
+            .header(header::ORIGIN, origin);
+        if method == Method::OPTIONS {
+            request = request.header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST");
+        }
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_allows() {
+        let origins = vec!["https://app.example.com".to_string()];
+        assert!(allows(&origins, "https://app.example.com"));
+        assert!(!allows(&origins, "https://evil.example.com"));
+        assert!(allows(&["*".to_string()], "https://evil.example.com"));
+        assert!(!allows(&[], "https://app.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_preflight() {
+        let state = state();
+        let app = app(&state, Principal::config_key());
+
+        // Allowed by a project's own list, before any credentials are known
+        let response = request(&app, Method::OPTIONS, WEB).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS],
+            ALLOWED_METHODS
+        );
+
+        let response = request(&app, Method::OPTIONS, GLOBAL).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = request(&app, Method::OPTIONS, "https://evil.example.com").await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.metrics.cors_rejections.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_project_origins() {
+        let state = state();
+        let app = app(&state, project_principal("web"));
+
+        let response = request(&app, Method::GET, WEB).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB);
+        assert_eq!(response.headers()[header::VARY], "Origin");
+
+        // A project's own list replaces the global one
+        let response = request(&app, Method::GET, GLOBAL).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert_eq!(state.metrics.cors_rejections.get(), 1);
+
+        // Cleared origins fall back to the global list
+        state.origins.set("web", &[]);
+        let response = request(&app, Method::GET, GLOBAL).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_global_fallback() {
+        let state = state();
+
+        for principal in [project_principal("mobile"), Principal::config_key()] {
+            let app = app(&state, principal);
+
+            let response = request(&app, Method::GET, GLOBAL).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+                GLOBAL
+            );
+
+            // Another project's origins are not enough
+            let response = request(&app, Method::GET, WEB).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(state.metrics.cors_rejections.get(), 2);
+    }
+}
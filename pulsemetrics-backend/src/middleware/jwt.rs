@@ -0,0 +1,314 @@
+use anyhow::Context;
+use jsonwebtoken::{
+    decode, decode_header,
+    errors::ErrorKind,
+    jwk::{AlgorithmParameters, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{config::JwtConfig, models::AppError};
+
+/// Claims accepted in identity provider tokens
+#[derive(Debug, Clone, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: i64,
+
+    /// Project the token is restricted to, if any
+    #[serde(default)]
+    pub project: Option<String>,
+
+    /// Space separated scopes, e.g. `events:read events:write`
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+impl Claims {
+    pub fn scopes(&self) -> Vec<String> {
+        self.scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Verifies HS256 tokens against a shared secret and RS256 tokens against a local JWKS file
+pub struct JwtVerifier {
+    hs256_key: Option<DecodingKey>,
+    rs256_keys: HashMap<String, DecodingKey>,
+    audience: String,
+    issuer: Option<String>,
+    leeway_seconds: u64,
+}
+
+impl JwtVerifier {
+    /// Build a verifier from configuration
+    ///
+    /// Returns `None` when neither an HS256 secret nor a JWKS file is configured.
+    pub fn from_config(config: &JwtConfig) -> anyhow::Result<Option<Self>> {
+        if config.hs256_secret.is_none() && config.jwks_path.is_none() {
+            return Ok(None);
+        }
+
+        let hs256_key = config
+            .hs256_secret
+            .as_deref()
+            .map(|secret| DecodingKey::from_secret(secret.as_bytes()));
+
+        let mut rs256_keys = HashMap::new();
+        if let Some(path) = &config.jwks_path {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read JWKS file {}", path))?;
+            let jwks: JwkSet = serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid JWKS file {}", path))?;
+
+            for (idx, jwk) in jwks.keys.iter().enumerate() {
+                // Only RS256 tokens are checked against the JWKS
+                if !matches!(jwk.algorithm, AlgorithmParameters::RSA(_)) {
+                    tracing::debug!(
+                        kid = jwk.common.key_id.as_deref().unwrap_or("-"),
+                        "Skipping non-RSA key in JWKS file {}",
+                        path
+                    );
+                    continue;
+                }
+                let kid = jwk.common.key_id.clone().unwrap_or_else(|| idx.to_string());
+                let key = DecodingKey::from_jwk(jwk)
+                    .with_context(|| format!("Invalid key {} in JWKS file {}", kid, path))?;
+                rs256_keys.insert(kid, key);
+            }
+
+            tracing::info!("Loaded {} JWT signing keys from {}", rs256_keys.len(), path);
+        }
+
+        Ok(Some(Self {
+            hs256_key,
+            rs256_keys,
+            audience: config.audience.clone(),
+            issuer: config.issuer.clone(),
+            leeway_seconds: config.leeway_seconds,
+        }))
+    }
+
+    /// Verify a token's signature, expiry, audience and issuer
+    pub fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        let header = decode_header(token)
+            .map_err(|_| AppError::Unauthorized("Malformed token".to_string()))?;
+
+        let key = match header.alg {
+            Algorithm::HS256 => self.hs256_key.as_ref().ok_or_else(|| {
+                AppError::Unauthorized("HS256 tokens are not accepted".to_string())
+            })?,
+            Algorithm::RS256 => self.rs256_key(header.kid.as_deref())?,
+            alg => {
+                return Err(AppError::Unauthorized(format!(
+                    "Unsupported token algorithm {:?}",
+                    alg
+                )))
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.audience]);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+        validation.leeway = self.leeway_seconds;
+
+        decode::<Claims>(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| AppError::Unauthorized(rejection_reason(e.kind())))
+    }
+
+    fn rs256_key(&self, kid: Option<&str>) -> Result<&DecodingKey, AppError> {
+        match kid {
+            Some(kid) => self
+                .rs256_keys
+                .get(kid)
+                .ok_or_else(|| AppError::Unauthorized(format!("Unknown token key id {}", kid))),
+            // Tokens without a key id are only unambiguous with a single key
+            None if self.rs256_keys.len() == 1 => Ok(self.rs256_keys.values().next().unwrap()),
+            None => Err(AppError::Unauthorized(
+                "Token is missing a key id".to_string(),
+            )),
+        }
+    }
+}
+
+/// Whether a bearer token has the shape of a JWT rather than an API key
+pub fn looks_like_jwt(token: &str) -> bool {
+    token.starts_with("eyJ") && token.split('.').count() == 3
+}
+
+fn rejection_reason(kind: &ErrorKind) -> String {
+    match kind {
+        ErrorKind::ExpiredSignature => "Token expired".to_string(),
+        ErrorKind::ImmatureSignature => "Token not yet valid".to_string(),
+        ErrorKind::InvalidAudience => "Token audience not accepted".to_string(),
+        ErrorKind::InvalidIssuer => "Token issuer not accepted".to_string(),
+        ErrorKind::InvalidSignature => "Invalid token signature".to_string(),
+        ErrorKind::MissingRequiredClaim(claim) => format!("Token is missing the {} claim", claim),
+        _ => "Malformed token".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    /// RSA keys `key-1` and `key-2`, plus an EC and a symmetric key
+    const JWKS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jwt/jwks.json");
+
+    fn verifier() -> JwtVerifier {
+        JwtVerifier::from_config(&JwtConfig {
+            hs256_secret: Some("test-secret".to_string()),
+            jwks_path: None,
+            audience: "pulsemetrics".to_string(),
+            issuer: None,
+            leeway_seconds: 0,
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    fn jwks_verifier() -> JwtVerifier {
+        JwtVerifier::from_config(&JwtConfig {
+            hs256_secret: None,
+            jwks_path: Some(JWKS.to_string()),
+            audience: "pulsemetrics".to_string(),
+            issuer: None,
+            leeway_seconds: 0,
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    /// RS256 token signed with the fixture's private key `signer`, naming `kid`
+    fn rs256_token(signer: &str, kid: Option<&str>) -> String {
+        let path = format!(
+            "{}/tests/fixtures/jwt/{}.pem",
+            env!("CARGO_MANIFEST_DIR"),
+            signer
+        );
+        let pem = std::fs::read(path).unwrap();
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = kid.map(str::to_string);
+        let claims = json!({
+            "sub": "alice",
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "aud": "pulsemetrics",
+        });
+        encode(&header, &claims, &EncodingKey::from_rsa_pem(&pem).unwrap()).unwrap()
+    }
+
+    fn token(claims: serde_json::Value) -> String {
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(b"test-secret"),
+        )
+        .unwrap()
+    }
+
+    fn reason(result: Result<Claims, AppError>) -> String {
+        match result {
+            Err(AppError::Unauthorized(reason)) => reason,
+            other => panic!("expected Unauthorized, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_valid_hs256_token() {
+        let exp = chrono::Utc::now().timestamp() + 60;
+        let claims = verifier()
+            .verify(&token(json!({
+                "sub": "alice",
+                "exp": exp,
+                "aud": "pulsemetrics",
+                "project": "web",
+                "scope": "events:read events:write",
+            })))
+            .unwrap();
+
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.project.as_deref(), Some("web"));
+        assert_eq!(claims.scopes(), vec!["events:read", "events:write"]);
+    }
+
+    #[test]
+    fn test_expired_token_rejected() {
+        let exp = chrono::Utc::now().timestamp() - 60;
+        let result = verifier().verify(&token(json!({
+            "sub": "alice",
+            "exp": exp,
+            "aud": "pulsemetrics",
+        })));
+
+        assert_eq!(reason(result), "Token expired");
+    }
+
+    #[test]
+    fn test_wrong_audience_rejected() {
+        let exp = chrono::Utc::now().timestamp() + 60;
+        let result = verifier().verify(&token(json!({
+            "sub": "alice",
+            "exp": exp,
+            "aud": "someone-else",
+        })));
+
+        assert_eq!(reason(result), "Token audience not accepted");
+    }
+
+    #[test]
+    fn test_jwks_keys_are_selected_by_kid() {
+        let verifier = jwks_verifier();
+
+        // Non-RSA keys are skipped
+        let mut kids: Vec<&str> = verifier.rs256_keys.keys().map(String::as_str).collect();
+        kids.sort_unstable();
+        assert_eq!(kids, ["key-1", "key-2"]);
+        assert!(verifier.hs256_key.is_none());
+
+        let claims = verifier
+            .verify(&rs256_token("key-1", Some("key-1")))
+            .unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert!(verifier
+            .verify(&rs256_token("key-2", Some("key-2")))
+            .is_ok());
+
+        assert_eq!(
+            reason(verifier.verify(&rs256_token("key-1", Some("key-2")))),
+            "Invalid token signature"
+        );
+        assert_eq!(
+            reason(verifier.verify(&rs256_token("key-1", Some("ec-1")))),
+            "Unknown token key id ec-1"
+        );
+        assert_eq!(
+            reason(verifier.verify(&rs256_token("key-1", Some("key-3")))),
+            "Unknown token key id key-3"
+        );
+        assert_eq!(
+            reason(verifier.verify(&rs256_token("key-1", None))),
+            "Token is missing a key id"
+        );
+        assert_eq!(
+            reason(verifier.verify(&token(json!({ "sub": "alice" })))),
+            "HS256 tokens are not accepted"
+        );
+    }
+
+    #[test]
+    fn test_looks_like_jwt() {
+        assert!(looks_like_jwt(&token(json!({ "sub": "a" }))));
+        assert!(!looks_like_jwt("pm_0123456789abcdef"));
+    }
+}
@@ -1,14 +1,27 @@
 use axum::{
-    extract::Request,
+    extract::{MatchedPath, Request, State},
     middleware::Next,
     response::Response,
 };
 use std::time::Instant;
 
+use crate::AppState;
+
+/// Route label for requests that matched no route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
 /// Request logging middleware
-pub async fn log_request(req: Request, next: Next) -> Response {
+///
+/// Also records request count and latency, labelled by route template
+/// rather than URI so ids in paths don't create new series.
+pub async fn log_request(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let method = req.method().clone();
     let uri = req.uri().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
     let start = Instant::now();
 
     let response = next.run(req).await;
@@ -24,5 +37,9 @@ pub async fn log_request(req: Request, next: Next) -> Response {
         "Request completed"
     );
 
+    state
+        .metrics
+        .observe_request(method.as_str(), &route, status.as_u16(), latency);
+
     response
 }
@@ -1,5 +1,13 @@
 pub mod auth;
+pub mod cors;
+pub mod jwt;
 pub mod logging;
+pub mod policy;
+pub mod rate_limit;
+pub mod request_id;
+pub mod signing;
 
 pub use auth::auth;
-pub use logging::log_request;
\ No newline at end of file
+pub use cors::cors;
+pub use logging::log_request;
+pub use request_id::request_id;
@@ -0,0 +1,408 @@
+use crate::{
+    db::organizations,
+    models::{
+        AppError, AppResult, AuthMethod, Principal, Role, SCOPE_ADMIN, SCOPE_EVENTS_READ,
+        SCOPE_EVENTS_WRITE,
+    },
+    AppState,
+};
+
+/// Operations subject to authorization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    IngestEvents,
+    ReadEvents,
+    ManageProject,
+    ManageKeys,
+    ViewAudit,
+    ManageMembers,
+    ManageOrganization,
+    CreateOrganization,
+    ReloadConfig,
+    ManageRetention,
+    ManageStorage,
+    ManageUserData,
+    ViewMetrics,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::IngestEvents => "ingest events",
+            Action::ReadEvents => "read events",
+            Action::ManageProject => "manage projects",
+            Action::ManageKeys => "manage API keys",
+            Action::ViewAudit => "view the audit log",
+            Action::ManageMembers => "manage members",
+            Action::ManageOrganization => "manage the organization",
+            Action::CreateOrganization => "create organizations",
+            Action::ReloadConfig => "reload the configuration",
+            Action::ManageRetention => "manage data retention",
+            Action::ManageStorage => "manage event storage",
+            Action::ManageUserData => "delete or export user data",
+            Action::ViewMetrics => "view service metrics",
+        }
+    }
+
+    /// Token scope a JWT must carry to attempt this action at all
+    pub fn required_scope(&self) -> &'static str {
+        match self {
+            Action::IngestEvents => SCOPE_EVENTS_WRITE,
+            Action::ReadEvents => SCOPE_EVENTS_READ,
+            _ => SCOPE_ADMIN,
+        }
+    }
+}
+
+/// What an action is performed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource<'a> {
+    Project(&'a str),
+    Organization(&'a str),
+    /// Not tied to any organization, e.g. creating one
+    Global,
+}
+
+/// Whether a role grants an action
+pub fn role_allows(role: Role, action: Action) -> bool {
+    match action {
+        Action::ReadEvents | Action::CreateOrganization => true,
+        Action::IngestEvents => matches!(role, Role::Owner | Role::Admin | Role::Analyst),
+        Action::ManageProject
+        | Action::ManageKeys
+        | Action::ViewAudit
+        | Action::ManageMembers
+        | Action::ManageUserData => matches!(role, Role::Owner | Role::Admin),
+        Action::ManageOrganization => role == Role::Owner,
+        // Service-wide, reserved for the config key
+        Action::ReloadConfig
+        | Action::ManageRetention
+        | Action::ManageStorage
+        | Action::ViewMetrics => false,
+    }
+}
+
+/// Decide whether a principal may perform an action
+///
+/// `role` is the principal's role in the organization owning `resource`,
+/// as resolved by [`authorize`].
+pub fn decide(
+    principal: &Principal,
+    role: Option<Role>,
+    action: Action,
+    resource: Resource<'_>,
+) -> AppResult<()> {
+    if matches!(principal.method, AuthMethod::ConfigKey | AuthMethod::Cli) {
+        return Ok(());
+    }
+
+    // Principals bound to a project never reach beyond it
+    if let Some(own) = &principal.project_id {
+        if resource != Resource::Project(own) {
+            return Err(forbidden(action));
+        }
+    }
+
+    match principal.method {
+        AuthMethod::ConfigKey | AuthMethod::Cli => Ok(()),
+        AuthMethod::ApiKey | AuthMethod::Signature => match action {
+            Action::IngestEvents => Ok(()),
+            _ => Err(forbidden(action)),
+        },
+        AuthMethod::Jwt => {
+            if !principal.has_scope(action.required_scope()) {
+                return Err(AppError::Forbidden(format!(
+                    "Missing required scope {}",
+                    action.required_scope()
+                )));
+            }
+
+            match role {
+                // Creating an organization makes the caller its owner
+                _ if action == Action::CreateOrganization => Ok(()),
+                // Roles only apply within an organization
+                _ if resource == Resource::Global => Err(forbidden(action)),
+                Some(role) if role_allows(role, action) => Ok(()),
+                Some(role) => Err(AppError::Forbidden(format!(
+                    "Role {} may not {}",
+                    role.as_str(),
+                    action.as_str()
+                ))),
+                None => Err(forbidden(action)),
+            }
+        }
+    }
+}
+
+/// Resolve the principal's role for `resource` and check the action against it
+pub async fn authorize(
+    state: &AppState,
+    principal: &Principal,
+    action: Action,
+    resource: Resource<'_>,
+) -> AppResult<()> {
+    // Without Postgres there are no organizations to hold a role in
+    let role = match (principal.method, resource, &state.db) {
+        (AuthMethod::Jwt, Resource::Project(project_id), Some(db)) => {
+            organizations::role_for_project(db, project_id, &principal.subject).await?
+        }
+        (AuthMethod::Jwt, Resource::Organization(organization_id), Some(db)) => {
+            organizations::find_member(db, organization_id, &principal.subject)
+                .await?
+                .and_then(|m| m.role())
+        }
+        _ => None,
+    };
+
+    let result = decide(principal, role, action, resource);
+    if result.is_err() {
+        tracing::debug!(
+            subject = %principal.subject,
+            action = action.as_str(),
+            resource = ?resource,
+            "Authorization denied"
+        );
+    }
+
+    result
+}
+
+fn forbidden(action: Action) -> AppError {
+    AppError::Forbidden(format!("Not authorized to {}", action.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_ACTIONS: [Action; 13] = [
+        Action::IngestEvents,
+        Action::ReadEvents,
+        Action::ManageProject,
+        Action::ManageKeys,
+        Action::ViewAudit,
+        Action::ManageMembers,
+        Action::ManageOrganization,
+        Action::CreateOrganization,
+        Action::ReloadConfig,
+        Action::ManageRetention,
+        Action::ManageStorage,
+        Action::ManageUserData,
+        Action::ViewMetrics,
+    ];
+
+    fn jwt(project_id: Option<&str>) -> Principal {
+        Principal::jwt(
+            "alice".to_string(),
+            project_id.map(str::to_string),
+            vec![
+                SCOPE_EVENTS_READ.to_string(),
+                SCOPE_EVENTS_WRITE.to_string(),
+                SCOPE_ADMIN.to_string(),
+            ],
+        )
+    }
+
+    fn api_key(project_id: &str) -> Principal {
+        Principal {
+            subject: "key".to_string(),
+            project_id: Some(project_id.to_string()),
+            scopes: vec![SCOPE_EVENTS_WRITE.to_string()],
+            method: AuthMethod::ApiKey,
+        }
+    }
+
+    #[test]
+    fn test_role_matrix() {
+        use Role::*;
+
+        // (role, [ingest, read, project, keys, audit, members, organization, create org,
+        //         reload, retention, storage, user data, metrics])
+        let table = [
+            (
+                Owner,
+                [
+                    true, true, true, true, true, true, true, true, false, false, false, true,
+                    false,
+                ],
+            ),
+            (
+                Admin,
+                [
+                    true, true, true, true, true, true, false, true, false, false, false, true,
+                    false,
+                ],
+            ),
+            (
+                Analyst,
+                [
+                    true, true, false, false, false, false, false, true, false, false, false,
+                    false, false,
+                ],
+            ),
+            (
+                Viewer,
+                [
+                    false, true, false, false, false, false, false, true, false, false, false,
+                    false, false,
+                ],
+            ),
+        ];
+
+        for (role, expected) in table {
+            for (action, allowed) in ALL_ACTIONS.iter().zip(expected) {
+                assert_eq!(
+                    role_allows(role, *action),
+                    allowed,
+                    "{:?} {:?}",
+                    role,
+                    action
+                );
+                assert_eq!(
+                    decide(&jwt(None), Some(role), *action, Resource::Project("web")).is_ok(),
+                    allowed,
+                    "{:?} {:?} via JWT",
+                    role,
+                    action
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_principal_kinds() {
+        let config = Principal::config_key();
+
+        // (principal, role, action, resource, allowed)
+        let cases = [
+            (
+                &config,
+                None,
+                Action::ManageOrganization,
+                Resource::Global,
+                true,
+            ),
+            (
+                &config,
+                None,
+                Action::ManageKeys,
+                Resource::Project("web"),
+                true,
+            ),
+            (
+                &api_key("web"),
+                None,
+                Action::IngestEvents,
+                Resource::Project("web"),
+                true,
+            ),
+            (
+                &api_key("web"),
+                None,
+                Action::IngestEvents,
+                Resource::Project("other"),
+                false,
+            ),
+            (
+                &api_key("web"),
+                None,
+                Action::ReadEvents,
+                Resource::Project("web"),
+                false,
+            ),
+            (
+                &api_key("web"),
+                None,
+                Action::ManageKeys,
+                Resource::Project("web"),
+                false,
+            ),
+            (
+                &jwt(None),
+                None,
+                Action::ReadEvents,
+                Resource::Project("web"),
+                false,
+            ),
+            (
+                &jwt(None),
+                Some(Role::Owner),
+                Action::ManageOrganization,
+                Resource::Global,
+                false,
+            ),
+            (
+                &jwt(None),
+                None,
+                Action::CreateOrganization,
+                Resource::Global,
+                true,
+            ),
+            (
+                &jwt(Some("web")),
+                None,
+                Action::CreateOrganization,
+                Resource::Global,
+                false,
+            ),
+            (
+                &jwt(Some("web")),
+                Some(Role::Owner),
+                Action::ReadEvents,
+                Resource::Project("web"),
+                true,
+            ),
+            (
+                &jwt(Some("web")),
+                Some(Role::Owner),
+                Action::ReadEvents,
+                Resource::Project("other"),
+                false,
+            ),
+            (
+                &jwt(Some("web")),
+                Some(Role::Owner),
+                Action::ManageMembers,
+                Resource::Organization("acme"),
+                false,
+            ),
+        ];
+
+        for (idx, (principal, role, action, resource, allowed)) in cases.into_iter().enumerate() {
+            assert_eq!(
+                decide(principal, role, action, resource).is_ok(),
+                allowed,
+                "case {}",
+                idx
+            );
+        }
+    }
+
+    #[test]
+    fn test_jwt_scope_is_a_ceiling() {
+        let read_only =
+            Principal::jwt("bob".to_string(), None, vec![SCOPE_EVENTS_READ.to_string()]);
+
+        assert!(decide(
+            &read_only,
+            Some(Role::Owner),
+            Action::ReadEvents,
+            Resource::Project("web")
+        )
+        .is_ok());
+        assert!(decide(
+            &read_only,
+            Some(Role::Owner),
+            Action::IngestEvents,
+            Resource::Project("web")
+        )
+        .is_err());
+        assert!(decide(
+            &read_only,
+            Some(Role::Owner),
+            Action::ManageKeys,
+            Resource::Project("web")
+        )
+        .is_err());
+    }
+}
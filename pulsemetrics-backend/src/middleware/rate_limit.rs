@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::RateLimitConfig;
+
+/// Time between sweeps of the buckets that have refilled
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Per-key token buckets for ingestion rate limiting
+///
+/// Limits are passed on every check rather than stored, so a config
+/// reload takes effect on the next request. Keys are whatever project ids
+/// clients send, so buckets that have refilled completely are evicted;
+/// a new bucket starts full, so this changes no outcome.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    buckets: HashMap<String, Bucket>,
+    swept_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take `cost` tokens from `key`'s bucket, returning whether they were available
+    pub fn check(&self, key: &str, cost: u32, limits: &RateLimitConfig, now: Instant) -> bool {
+        self.check_all(&[(key, cost)], limits, now).is_ok()
+    }
+
+    /// Take each key's cost from its bucket, provided every bucket can pay
+    ///
+    /// Returns the first key short of tokens, in which case no bucket is
+    /// charged, so one limited key does not cost the others.
+    pub fn check_all<'a>(
+        &self,
+        costs: &[(&'a str, u32)],
+        limits: &RateLimitConfig,
+        now: Instant,
+    ) -> Result<(), &'a str> {
+        if !limits.is_enabled() {
+            return Ok(());
+        }
+
+        let burst = f64::from(limits.burst);
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+        let swept_at = *state.swept_at.get_or_insert(now);
+        if now.saturating_duration_since(swept_at) >= SWEEP_INTERVAL {
+            state.buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.refilled_at);
+                bucket.tokens + refill(elapsed, limits) < burst
+            });
+            state.swept_at = Some(now);
+        }
+
+        for &(key, cost) in costs {
+            let bucket = state.buckets.entry(key.to_string()).or_insert(Bucket {
+                tokens: burst,
+                refilled_at: now,
+            });
+
+            let elapsed = now.saturating_duration_since(bucket.refilled_at);
+            bucket.tokens = (bucket.tokens + refill(elapsed, limits)).min(burst);
+            bucket.refilled_at = now;
+
+            if bucket.tokens < f64::from(cost) {
+                return Err(key);
+            }
+        }
+
+        for &(key, cost) in costs {
+            if let Some(bucket) = state.buckets.get_mut(key) {
+                bucket.tokens -= f64::from(cost);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn refill(elapsed: Duration, limits: &RateLimitConfig) -> f64 {
+    elapsed.as_secs_f64() * f64::from(limits.events_per_second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buckets(limiter: &RateLimiter) -> usize {
+        limiter.state.lock().unwrap().buckets.len()
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let limiter = RateLimiter::new();
+        let limits = RateLimitConfig {
+            events_per_second: 10,
+            burst: 20,
+        };
+        let start = Instant::now();
+
+        assert!(limiter.check("web", 20, &limits, start));
+        assert!(!limiter.check("web", 1, &limits, start));
+        assert!(limiter.check("other", 5, &limits, start));
+
+        // Half a second refills 5 events
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check("web", 5, &limits, later));
+        assert!(!limiter.check("web", 1, &limits, later));
+    }
+
+    #[test]
+    fn test_refilled_buckets_are_evicted() {
+        let limiter = RateLimiter::new();
+        let limits = RateLimitConfig {
+            events_per_second: 10,
+            burst: 20,
+        };
+        let start = Instant::now();
+
+        for project_id in 0..100 {
+            assert!(limiter.check(&project_id.to_string(), 1, &limits, start));
+        }
+        assert!(limiter.check("web", 20, &limits, start + Duration::from_secs(59)));
+        assert_eq!(buckets(&limiter), 101);
+
+        // Only "web" has not refilled by the next sweep
+        let later = start + SWEEP_INTERVAL;
+        assert!(limiter.check("app", 1, &limits, later));
+        assert_eq!(buckets(&limiter), 2);
+        assert!(!limiter.check("web", 20, &limits, later));
+    }
+
+    #[test]
+    fn test_limited_batches_charge_no_one() {
+        let limiter = RateLimiter::new();
+        let limits = RateLimitConfig {
+            events_per_second: 10,
+            burst: 20,
+        };
+        let now = Instant::now();
+
+        assert!(limiter.check("app", 15, &limits, now));
+        assert_eq!(
+            limiter.check_all(&[("web", 10), ("app", 10)], &limits, now),
+            Err("app")
+        );
+
+        // "web" kept its tokens for a batch that was not accepted
+        assert!(limiter.check("web", 20, &limits, now));
+        assert_eq!(
+            limiter.check_all(&[("app", 5), ("other", 20)], &limits, now),
+            Ok(())
+        );
+        assert!(!limiter.check("app", 1, &limits, now));
+    }
+
+    #[test]
+    fn test_disabled_limit_allows_everything() {
+        let limiter = RateLimiter::new();
+        let limits = RateLimitConfig {
+            events_per_second: 0,
+            burst: 0,
+        };
+
+        assert!(limiter.check("web", u32::MAX, &limits, Instant::now()));
+    }
+}
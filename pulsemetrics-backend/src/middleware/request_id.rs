@@ -0,0 +1,71 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+/// Header carrying the id that correlates a request with server logs
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied id accepted before a new one is generated
+const MAX_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+/// Id of the request being handled, if called within [`request_id`]
+pub fn current() -> Option<String> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Request id middleware
+///
+/// Keeps the client's `X-Request-Id` if it is sensible, otherwise generates
+/// one. The id is written back to the request headers, where handlers and
+/// the request span pick it up, and echoed in the response.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(accept)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let header = HeaderValue::from_str(&id).expect("request id is a valid header value");
+    req.headers_mut()
+        .insert(REQUEST_ID_HEADER.clone(), header.clone());
+
+    let mut response = CURRENT.scope(id, next.run(req)).await;
+
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER.clone(), header);
+    response
+}
+
+/// Client-supplied id, if short and printable
+fn accept(value: &HeaderValue) -> Option<String> {
+    let id = value.to_str().ok()?;
+    let valid = !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+
+    valid.then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_printable_ids() {
+        let id = HeaderValue::from_static("req-42_a.b");
+        assert_eq!(accept(&id).as_deref(), Some("req-42_a.b"));
+    }
+
+    #[test]
+    fn test_rejects_unusable_ids() {
+        assert!(accept(&HeaderValue::from_static("")).is_none());
+        assert!(accept(&HeaderValue::from_static("has space")).is_none());
+        assert!(accept(&HeaderValue::from_str(&"x".repeat(MAX_LEN + 1)).unwrap()).is_none());
+    }
+}
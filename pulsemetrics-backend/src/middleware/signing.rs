@@ -0,0 +1,153 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Mutex};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Authorization scheme for signed requests:
+/// `Authorization: PM-HMAC-SHA256 KeyId=<key id>, Signature=<hex signature>`
+pub const SCHEME: &str = "PM-HMAC-SHA256";
+
+/// Unix timestamp (seconds) at which the request was signed
+pub const TIMESTAMP_HEADER: &str = "x-pm-timestamp";
+
+/// Single-use random value chosen by the client
+pub const NONCE_HEADER: &str = "x-pm-nonce";
+
+/// Largest body that will be buffered for signature verification
+pub const MAX_SIGNED_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Parsed `PM-HMAC-SHA256` authorization header
+#[derive(Debug, PartialEq)]
+pub struct SignatureHeader {
+    pub key_id: String,
+    pub signature: String,
+}
+
+impl SignatureHeader {
+    /// Parse the parameters following the scheme name
+    pub fn parse(params: &str) -> Option<Self> {
+        let mut key_id = None;
+        let mut signature = None;
+
+        for part in params.split(',') {
+            let (name, value) = part.trim().split_once('=')?;
+            match name.trim() {
+                "KeyId" => key_id = Some(value.trim().to_string()),
+                "Signature" => signature = Some(value.trim().to_string()),
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            key_id: key_id?,
+            signature: signature?,
+        })
+    }
+}
+
+/// Build the canonical string covered by the signature
+///
+/// Lines are: method, path with query, timestamp, nonce, hex SHA-256 of the body.
+pub fn string_to_sign(
+    method: &str,
+    path_and_query: &str,
+    timestamp: i64,
+    nonce: &str,
+    body: &[u8],
+) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        path_and_query,
+        timestamp,
+        nonce,
+        hex::encode(Sha256::digest(body))
+    )
+}
+
+/// Compute the hex-encoded signature of a canonical string
+pub fn sign(secret: &[u8], string_to_sign: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(string_to_sign.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Verify a hex-encoded signature in constant time
+pub fn verify(secret: &[u8], string_to_sign: &str, signature: &str) -> bool {
+    let Ok(expected) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(string_to_sign.as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Remembers nonces until their timestamps fall outside the accepted window
+#[derive(Debug, Default)]
+pub struct NonceCache {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl NonceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a nonce, returning `false` if it was already used
+    ///
+    /// `expires_at` is the unix time after which the request's timestamp is
+    /// stale anyway, so the nonce no longer needs to be remembered.
+    pub fn check_and_insert(&self, nonce: String, expires_at: i64, now: i64) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.retain(|_, expiry| *expiry > now);
+
+        if seen.contains_key(&nonce) {
+            return false;
+        }
+
+        seen.insert(nonce, expires_at);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let sts = string_to_sign("post", "/api/ingest", 1_700_000_000, "abc", b"{}");
+        let signature = sign(b"secret", &sts);
+
+        assert!(verify(b"secret", &sts, &signature));
+        assert!(!verify(b"other-secret", &sts, &signature));
+        assert!(!verify(b"secret", &sts, "not-hex"));
+
+        let tampered = string_to_sign("post", "/api/ingest", 1_700_000_000, "abc", b"{ }");
+        assert!(!verify(b"secret", &tampered, &signature));
+    }
+
+    #[test]
+    fn test_parse_signature_header() {
+        let header = SignatureHeader::parse("KeyId=abc, Signature=def").unwrap();
+        assert_eq!(header.key_id, "abc");
+        assert_eq!(header.signature, "def");
+
+        assert!(SignatureHeader::parse("KeyId=abc").is_none());
+        assert!(SignatureHeader::parse("KeyId=abc, Foo=bar, Signature=def").is_none());
+    }
+
+    #[test]
+    fn test_nonce_replay_rejected() {
+        let cache = NonceCache::new();
+
+        assert!(cache.check_and_insert("n1".to_string(), 100, 0));
+        assert!(!cache.check_and_insert("n1".to_string(), 100, 50));
+        assert!(cache.check_and_insert("n2".to_string(), 100, 50));
+
+        // Expired nonces are forgotten
+        assert!(cache.check_and_insert("n1".to_string(), 300, 150));
+    }
+}
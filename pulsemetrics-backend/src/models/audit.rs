@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use super::Principal;
+
+/// Stored audit log entry
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor: String,
+    pub actor_method: String,
+    pub action: String,
+    pub resource_type: String,
+    pub resource_id: String,
+    pub project_id: Option<String>,
+    pub request_id: Option<String>,
+    pub before: Option<JsonValue>,
+    pub after: Option<JsonValue>,
+}
+
+/// Audit entry to be recorded for an administrative change
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub actor_method: String,
+    pub action: String,
+    pub resource_type: String,
+    pub resource_id: String,
+    pub project_id: Option<String>,
+    pub request_id: Option<String>,
+    pub before: Option<JsonValue>,
+    pub after: Option<JsonValue>,
+}
+
+impl NewAuditEntry {
+    pub fn new(
+        principal: &Principal,
+        request_id: Option<String>,
+        action: &str,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Self {
+        Self {
+            actor: principal.subject.clone(),
+            actor_method: principal.method.as_str().to_string(),
+            action: action.to_string(),
+            resource_type: resource_type.to_string(),
+            resource_id: resource_id.to_string(),
+            project_id: None,
+            request_id,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn project(mut self, project_id: &str) -> Self {
+        self.project_id = Some(project_id.to_string());
+        self
+    }
+
+    /// Snapshot of the resource before the change
+    pub fn before<T: Serialize>(mut self, value: &T) -> Self {
+        self.before = serde_json::to_value(value).ok();
+        self
+    }
+
+    /// Snapshot of the resource after the change
+    pub fn after<T: Serialize>(mut self, value: &T) -> Self {
+        self.after = serde_json::to_value(value).ok();
+        self
+    }
+}
+
+/// Filters and cursor for listing audit entries
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub resource_type: Option<String>,
+    pub resource_id: Option<String>,
+    pub project_id: Option<String>,
+
+    /// Only return entries older than this id (the previous page's `next_cursor`)
+    pub before: Option<i64>,
+
+    pub limit: Option<i64>,
+}
+
+/// Page of audit entries, newest first
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next_cursor: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Key {
+        name: String,
+        revoked: bool,
+        #[serde(skip)]
+        #[allow(dead_code)]
+        secret: String,
+    }
+
+    fn key(revoked: bool) -> Key {
+        Key {
+            name: "ci".to_string(),
+            revoked,
+            secret: "s3cret".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_entry_snapshots() {
+        let principal = Principal::config_key();
+        let entry = NewAuditEntry::new(
+            &principal,
+            Some("req-1".to_string()),
+            "api_key.revoke",
+            "api_key",
+            "k1",
+        )
+        .project("web")
+        .before(&key(false))
+        .after(&key(true));
+
+        assert_eq!(entry.actor_method, principal.method.as_str());
+        assert_eq!(entry.project_id.as_deref(), Some("web"));
+        assert_eq!(entry.request_id.as_deref(), Some("req-1"));
+        assert_eq!(
+            entry.before,
+            Some(serde_json::json!({"name": "ci", "revoked": false}))
+        );
+        assert_eq!(
+            entry.after,
+            Some(serde_json::json!({"name": "ci", "revoked": true}))
+        );
+
+        let created = NewAuditEntry::new(&principal, None, "project.create", "project", "web");
+        assert_eq!(created.before, None);
+        assert_eq!(created.after, None);
+        assert_eq!(created.project_id, None);
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How requests made with an API key must be authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthScheme {
+    /// Token sent as `Authorization: Bearer <token>`
+    Bearer,
+    /// Request signed with a shared secret, see `middleware::signing`
+    Hmac,
+}
+
+impl AuthScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthScheme::Bearer => "bearer",
+            AuthScheme::Hmac => "hmac",
+        }
+    }
+}
+
+impl std::str::FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bearer" => Ok(AuthScheme::Bearer),
+            "hmac" => Ok(AuthScheme::Hmac),
+            _ => Err(format!("Unknown auth scheme: {}", s)),
+        }
+    }
+}
+
+/// API key record
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub project_id: String,
+    pub name: String,
+    pub auth_scheme: String,
+    pub token_hash: Option<String>,
+    pub signing_secret: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// API key without its secret material, safe to return and audit
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeySummary {
+    pub id: Uuid,
+    pub project_id: String,
+    pub name: String,
+    pub auth_scheme: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<&ApiKey> for ApiKeySummary {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            id: key.id,
+            project_id: key.project_id.clone(),
+            name: key.name.clone(),
+            auth_scheme: key.auth_scheme.clone(),
+            created_at: key.created_at,
+            revoked_at: key.revoked_at,
+        }
+    }
+}
+
+impl ApiKey {
+    pub fn scheme(&self) -> Option<AuthScheme> {
+        self.auth_scheme.parse().ok()
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+}
+
+/// Scope required to ingest events
+pub const SCOPE_EVENTS_WRITE: &str = "events:write";
+
+/// Scope required to query events
+pub const SCOPE_EVENTS_READ: &str = "events:read";
+
+/// Scope required to manage projects and keys
+pub const SCOPE_ADMIN: &str = "admin";
+
+/// How a request was authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// The global key from `AppConfig::api_key`
+    ConfigKey,
+    /// A project API key sent as a bearer token
+    ApiKey,
+    /// A project API key used to sign the request
+    Signature,
+    /// A JWT issued by the identity provider
+    Jwt,
+    /// An operator using the `pulsemetrics-admin` binary
+    Cli,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthMethod::ConfigKey => "config_key",
+            AuthMethod::ApiKey => "api_key",
+            AuthMethod::Signature => "signature",
+            AuthMethod::Jwt => "jwt",
+            AuthMethod::Cli => "cli",
+        }
+    }
+}
+
+/// Authenticated identity attached to request extensions by `middleware::auth`
+#[derive(Debug, Clone, Serialize)]
+pub struct Principal {
+    /// Key id or other stable identifier of the caller
+    pub subject: String,
+
+    /// Project the caller is restricted to, `None` for unrestricted callers
+    pub project_id: Option<String>,
+
+    /// Scopes granted to the caller
+    pub scopes: Vec<String>,
+
+    pub method: AuthMethod,
+}
+
+impl Principal {
+    /// Principal for the global configuration key
+    pub fn config_key() -> Self {
+        Self {
+            subject: "config".to_string(),
+            project_id: None,
+            scopes: vec![
+                SCOPE_EVENTS_WRITE.to_string(),
+                SCOPE_EVENTS_READ.to_string(),
+                SCOPE_ADMIN.to_string(),
+            ],
+            method: AuthMethod::ConfigKey,
+        }
+    }
+
+    /// Principal for a project API key
+    pub fn api_key(key: &ApiKey, method: AuthMethod) -> Self {
+        Self {
+            subject: key.id.to_string(),
+            project_id: Some(key.project_id.clone()),
+            scopes: vec![SCOPE_EVENTS_WRITE.to_string()],
+            method,
+        }
+    }
+
+    /// Principal for a verified JWT
+    pub fn jwt(subject: String, project_id: Option<String>, scopes: Vec<String>) -> Self {
+        Self {
+            subject,
+            project_id,
+            scopes,
+            method: AuthMethod::Jwt,
+        }
+    }
+
+    /// Principal for an operator running the admin CLI
+    ///
+    /// The CLI talks to the database directly, so it is unrestricted.
+    pub fn cli(operator: String) -> Self {
+        Self {
+            subject: operator,
+            project_id: None,
+            scopes: vec![
+                SCOPE_EVENTS_WRITE.to_string(),
+                SCOPE_EVENTS_READ.to_string(),
+                SCOPE_ADMIN.to_string(),
+            ],
+            method: AuthMethod::Cli,
+        }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// Whether this principal may act on the given project
+    pub fn can_access_project(&self, project_id: &str) -> bool {
+        match &self.project_id {
+            Some(own) => own == project_id,
+            None => true,
+        }
+    }
+}
@@ -5,6 +5,8 @@ use axum::{
 };
 use serde_json::json;
 
+use crate::middleware::request_id;
+
 /// Application-wide error type
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
@@ -14,6 +16,9 @@ pub enum AppError {
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
 
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
@@ -26,6 +31,9 @@ pub enum AppError {
     #[error("Rate limit exceeded")]
     RateLimited,
 
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
@@ -42,10 +50,12 @@ impl AppError {
         match self {
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
             AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::Conflict(_) => StatusCode::CONFLICT,
             AppError::UnprocessableEntity(_) => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Validation(_) => StatusCode::BAD_REQUEST,
             AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -68,10 +78,12 @@ impl AppError {
         match self {
             AppError::BadRequest(_) => "BAD_REQUEST",
             AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden(_) => "FORBIDDEN",
             AppError::NotFound(_) => "NOT_FOUND",
             AppError::Conflict(_) => "CONFLICT",
             AppError::UnprocessableEntity(_) => "UNPROCESSABLE_ENTITY",
             AppError::RateLimited => "RATE_LIMITED",
+            AppError::ServiceUnavailable(_) => "SERVICE_UNAVAILABLE",
             AppError::Database(_) => "DATABASE_ERROR",
             AppError::Validation(_) => "VALIDATION_ERROR",
             AppError::Internal(_) => "INTERNAL_ERROR",
@@ -91,12 +103,14 @@ impl IntoResponse for AppError {
             tracing::error!("Internal error: {:?}", self);
         }
 
-        let body = Json(json!({
-            "error": {
-                "code": self.error_code(),
-                "message": self.message(),
-            }
-        }));
+        let mut error = json!({
+            "code": self.error_code(),
+            "message": self.message(),
+        });
+        if let Some(id) = request_id::current() {
+            error["request_id"] = id.into();
+        }
+        let body = Json(json!({ "error": error }));
 
         (status, body).into_response()
     }
@@ -5,7 +5,7 @@ use uuid::Uuid;
 use validator::Validate;
 
 /// Event data model for ingestion
-#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, sqlx::FromRow)]
 pub struct Event {
     #[serde(default = "Uuid::new_v4")]
     pub id: Uuid,
@@ -36,7 +36,7 @@ pub struct Event {
 /// Batch of events for ingestion
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct EventBatch {
-    #[validate(length(min = 1, max = 1000))]
+    #[validate(length(min = 1))]
     #[validate(nested)]
     pub events: Vec<Event>,
 }
@@ -69,4 +69,52 @@ impl IngestionResponse {
             timestamp: Utc::now(),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl Event {
+    /// A `page_view` of `project_id` at noon on 2024-05-01, for tests to adjust
+    pub fn fixture(project_id: &str) -> Self {
+        use chrono::TimeZone;
+
+        Self {
+            id: Uuid::new_v4(),
+            time: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            project_id: project_id.to_string(),
+            event_type: "page_view".to_string(),
+            properties: None,
+            user_id: None,
+            session_id: None,
+            value: None,
+        }
+    }
+
+    pub fn at(mut self, time: DateTime<Utc>) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn later(mut self, duration: chrono::Duration) -> Self {
+        self.time += duration;
+        self
+    }
+
+    pub fn event_type(mut self, event_type: &str) -> Self {
+        self.event_type = event_type.to_string();
+        self
+    }
+
+    pub fn properties(mut self, properties: JsonValue) -> Self {
+        self.properties = Some(properties);
+        self
+    }
+
+    pub fn user_id(mut self, user_id: &str) -> Self {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn value(mut self, value: f64) -> Self {
+        self.value = Some(value);
+        self
+    }
+}
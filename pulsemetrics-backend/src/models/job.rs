@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What a data job does with a user's events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    /// Erase every event of the user (right to be forgotten)
+    UserDeletion,
+    /// Write every event of the user to a downloadable file (right of access)
+    UserExport,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::UserDeletion => "user_deletion",
+            JobKind::UserExport => "user_export",
+        }
+    }
+}
+
+impl std::str::FromStr for JobKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user_deletion" => Ok(JobKind::UserDeletion),
+            "user_export" => Ok(JobKind::UserExport),
+            _ => Err(format!("Unknown job kind: {}", s)),
+        }
+    }
+}
+
+/// Layout of an export file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One JSON document with the events in an array
+    #[default]
+    Json,
+    /// One event per line
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+/// Progress of a data job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Background job acting on one user's events
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DataJob {
+    pub id: Uuid,
+    pub kind: String,
+    pub project_id: String,
+    pub user_id: String,
+    pub status: String,
+    pub requested_by: String,
+    pub requested_method: String,
+    pub request_id: Option<String>,
+    /// `json` or `ndjson` for exports
+    pub format: Option<String>,
+    /// Export file on the server, not shown to clients
+    #[serde(skip_serializing)]
+    pub output_path: Option<String>,
+    /// Events deleted or exported, once completed
+    pub events_affected: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl DataJob {
+    pub fn kind(&self) -> Option<JobKind> {
+        self.kind.parse().ok()
+    }
+
+    pub fn format(&self) -> Option<ExportFormat> {
+        self.format.as_deref().and_then(|f| f.parse().ok())
+    }
+}
@@ -1,5 +1,22 @@
+pub mod audit;
+pub mod auth;
 pub mod error;
 pub mod event;
+pub mod job;
+pub mod organization;
+pub mod project;
+pub mod query;
+pub mod scrub;
 
+pub use audit::{AuditEntry, AuditPage, AuditQuery, NewAuditEntry};
+pub use auth::{
+    ApiKey, ApiKeySummary, AuthMethod, AuthScheme, Principal, SCOPE_ADMIN, SCOPE_EVENTS_READ,
+    SCOPE_EVENTS_WRITE,
+};
 pub use error::{AppError, AppResult};
-pub use event::{Event, EventBatch, IngestionResponse};
\ No newline at end of file
+pub use event::{Event, EventBatch, IngestionResponse};
+pub use job::{DataJob, ExportFormat, JobKind, JobStatus};
+pub use organization::{Member, Organization, Role};
+pub use project::{IpMode, Project, ProjectUsage};
+pub use query::{AggregateBucket, AggregateQuery, EventQuery, EventRange, Interval};
+pub use scrub::{Detector, ScrubAction, ScrubRule};
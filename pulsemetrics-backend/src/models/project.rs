@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::ScrubRule;
+
+/// How a project stores the IP address events were sent from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpMode {
+    /// Store no address
+    #[default]
+    Drop,
+    /// Store the address as is
+    Full,
+    /// Zero the last octet of IPv4 and the last 80 bits of IPv6 addresses
+    Truncate,
+    /// Store a hash salted with a regularly replaced salt
+    Hash,
+}
+
+impl IpMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpMode::Drop => "drop",
+            IpMode::Full => "full",
+            IpMode::Truncate => "truncate",
+            IpMode::Hash => "hash",
+        }
+    }
+}
+
+impl std::str::FromStr for IpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(IpMode::Drop),
+            "full" => Ok(IpMode::Full),
+            "truncate" => Ok(IpMode::Truncate),
+            "hash" => Ok(IpMode::Hash),
+            _ => Err(format!("Unknown IP mode: {}", s)),
+        }
+    }
+}
+
+/// Project record
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Project {
+    pub id: String,
+    pub name: String,
+    pub organization_id: Option<String>,
+    pub allowed_origins: Vec<String>,
+    /// Days events are kept, `None` keeps them forever
+    pub retention_days: Option<i32>,
+    /// Applied to events at ingestion, in order
+    #[sqlx(json)]
+    pub scrub_rules: Vec<ScrubRule>,
+    /// Secret keying the hashes of scrubbed values
+    #[serde(skip)]
+    pub scrub_key: Vec<u8>,
+    /// `drop`, `full`, `truncate` or `hash`, see [`IpMode`]
+    pub ip_mode: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Project {
+    /// Unknown modes store no address
+    pub fn ip_mode(&self) -> IpMode {
+        self.ip_mode.parse().unwrap_or_default()
+    }
+}
+
+/// Event volume and key count for a project
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ProjectUsage {
+    pub project_id: String,
+    pub events: i64,
+    pub first_event: Option<DateTime<Utc>>,
+    pub last_event: Option<DateTime<Utc>>,
+    pub active_keys: i64,
+}
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Selects events to purge
+///
+/// Bounds are inclusive of `from` and exclusive of `to`.
+#[derive(Debug, Clone, Default)]
+pub struct EventRange {
+    pub project_id: Option<String>,
+    pub user_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl EventRange {
+    /// Whether no filter is set, i.e. the range covers every event
+    pub fn is_unbounded(&self) -> bool {
+        self.project_id.is_none()
+            && self.user_id.is_none()
+            && self.from.is_none()
+            && self.to.is_none()
+    }
+}
+
+/// Filter for listing a project's events
+///
+/// Bounds are inclusive of `from` and exclusive of `to`.
+#[derive(Debug, Clone)]
+pub struct EventQuery {
+    pub project_id: String,
+    pub event_type: Option<String>,
+    pub user_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Maximum number of events returned, newest first
+    pub limit: u32,
+}
+
+/// Width of the time buckets of an aggregation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Interval {
+    pub fn duration(self) -> Duration {
+        match self {
+            Interval::Minute => Duration::minutes(1),
+            Interval::Hour => Duration::hours(1),
+            Interval::Day => Duration::days(1),
+        }
+    }
+
+    /// Start of the bucket containing `time`, aligned to the Unix epoch
+    pub fn truncate(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        time.duration_trunc(self.duration())
+            .expect("bucket widths fit in a timestamp")
+    }
+}
+
+/// Bucketed statistics over a project's events
+///
+/// Bounds are inclusive of `from` and exclusive of `to`.
+#[derive(Debug, Clone)]
+pub struct AggregateQuery {
+    pub project_id: String,
+    pub event_type: Option<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub interval: Interval,
+}
+
+/// Statistics for one time bucket, buckets without events are omitted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AggregateBucket {
+    pub bucket: DateTime<Utc>,
+    pub count: i64,
+    /// Distinct non-null `user_id`s
+    pub unique_users: i64,
+    /// Sum of `value` over events that have one
+    pub sum: Option<f64>,
+    /// Mean of `value` over events that have one
+    pub avg: Option<f64>,
+}
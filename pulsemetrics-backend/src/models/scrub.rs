@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// Built-in detector for a common kind of personal data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    Email,
+    /// Numbers written with a country code, area code or separators
+    Phone,
+    /// 13 to 19 digits passing the Luhn check
+    CreditCard,
+}
+
+impl Detector {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Detector::Email => "email",
+            Detector::Phone => "phone",
+            Detector::CreditCard => "credit_card",
+        }
+    }
+}
+
+/// What happens to data matched by a scrubbing rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrubAction {
+    /// Replace the match with `[name]`
+    #[default]
+    Mask,
+    /// Replace the match with a hash, so equal values still correlate
+    Hash,
+    /// Remove the whole property, or the `user_id`
+    Drop,
+}
+
+impl ScrubAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScrubAction::Mask => "mask",
+            ScrubAction::Hash => "hash",
+            ScrubAction::Drop => "drop",
+        }
+    }
+}
+
+/// A project's rule for scrubbing personal data at ingestion
+///
+/// Exactly one of `detector`, `pattern` and `key` is set. Detectors and
+/// patterns are matched inside string values of the properties and in
+/// `user_id`; `key` matches property names at any depth, ignoring case.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScrubRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detector: Option<Detector>,
+
+    /// Regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    #[serde(default)]
+    pub action: ScrubAction,
+
+    /// Label of the rule in masks and metrics, the detector's name by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl ScrubRule {
+    pub fn name(&self) -> &str {
+        match (&self.name, self.detector) {
+            (Some(name), _) => name,
+            (None, Some(detector)) => detector.as_str(),
+            (None, None) => "redacted",
+        }
+    }
+}
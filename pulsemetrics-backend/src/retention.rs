@@ -0,0 +1,277 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    db::projects,
+    metrics::Metrics,
+    models::{EventRange, Project},
+    storage::EventStore,
+    AppState,
+};
+
+/// Time between configuration checks while the job is disabled
+const DISABLED_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Expired events of one project
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProjectRetention {
+    pub project_id: String,
+    pub retention_days: i32,
+    /// Events before this time are expired
+    pub cutoff: DateTime<Utc>,
+    /// Expired events found, or deleted unless `dry_run`
+    pub expired_events: u64,
+}
+
+/// Outcome of a retention run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub projects: Vec<ProjectRetention>,
+}
+
+impl RetentionReport {
+    pub fn total(&self) -> u64 {
+        self.projects.iter().map(|p| p.expired_events).sum()
+    }
+}
+
+/// Range of a project's events older than `retention_days` at `now`
+fn expired_range(project_id: &str, retention_days: i32, now: DateTime<Utc>) -> EventRange {
+    EventRange {
+        project_id: Some(project_id.to_string()),
+        user_id: None,
+        from: None,
+        to: Some(now - Duration::days(i64::from(retention_days))),
+    }
+}
+
+/// Count the expired events of `projects` without deleting anything
+pub async fn report(
+    store: &dyn EventStore,
+    projects: &[Project],
+    now: DateTime<Utc>,
+) -> anyhow::Result<RetentionReport> {
+    let mut report = RetentionReport {
+        dry_run: true,
+        projects: Vec::new(),
+    };
+    for project in projects {
+        let Some(retention_days) = project.retention_days else {
+            continue;
+        };
+        let range = expired_range(&project.id, retention_days, now);
+        report.projects.push(ProjectRetention {
+            expired_events: store.count(&range).await?,
+            project_id: project.id.clone(),
+            retention_days,
+            cutoff: range.to.expect("expired range has an end"),
+        });
+    }
+
+    Ok(report)
+}
+
+/// Delete the expired events of `projects`, `batch_size` rows per statement
+///
+/// Short statements keep ingestion from waiting on locks held by a large
+/// deletion. Stops between batches once `shutdown` is cancelled.
+pub async fn enforce(
+    store: &dyn EventStore,
+    projects: &[Project],
+    batch_size: u64,
+    now: DateTime<Utc>,
+    metrics: &Metrics,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<RetentionReport> {
+    let mut report = RetentionReport {
+        dry_run: false,
+        projects: Vec::new(),
+    };
+    for project in projects {
+        let Some(retention_days) = project.retention_days else {
+            continue;
+        };
+        let range = expired_range(&project.id, retention_days, now);
+
+        let mut deleted = 0;
+        while !shutdown.is_cancelled() {
+            let batch = store.delete_batch(&range, batch_size).await?;
+            deleted += batch;
+            metrics
+                .retention_events_deleted
+                .with_label_values(&[project.id.as_str()])
+                .inc_by(batch);
+            if batch < batch_size {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        if deleted > 0 {
+            tracing::info!(project_id = %project.id, deleted, "Deleted expired events");
+        }
+
+        report.projects.push(ProjectRetention {
+            project_id: project.id.clone(),
+            retention_days,
+            cutoff: range.to.expect("expired range has an end"),
+            expired_events: deleted,
+        });
+    }
+
+    Ok(report)
+}
+
+/// Spawn the task enforcing retention every `retention.interval_seconds`
+///
+/// Settings are re-read before each run, so a reload can enable, disable
+/// or reschedule the job.
+pub fn start(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let config = state.config().retention.clone();
+            let wait = if config.is_enabled() {
+                if let Err(e) = run(&state, config.batch_size, &shutdown).await {
+                    tracing::error!("Retention run failed: {:#}", e);
+                }
+                std::time::Duration::from_secs(config.interval_seconds)
+            } else {
+                DISABLED_POLL_INTERVAL
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.cancelled() => break,
+            }
+        }
+    })
+}
+
+async fn run(
+    state: &AppState,
+    batch_size: u64,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<()> {
+    let projects = projects::with_retention(state.db()?).await?;
+    let report = enforce(
+        state.events.as_ref(),
+        &projects,
+        batch_size,
+        Utc::now(),
+        &state.metrics,
+        shutdown,
+    )
+    .await?;
+    state.metrics.retention_last_run.set(Utc::now().timestamp());
+
+    tracing::debug!(
+        projects = report.projects.len(),
+        deleted = report.total(),
+        "Retention run complete"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+    use chrono::TimeZone;
+
+    fn project(id: &str, retention_days: Option<i32>) -> Project {
+        Project {
+            id: id.to_string(),
+            name: id.to_string(),
+            organization_id: None,
+            allowed_origins: Vec::new(),
+            retention_days,
+            scrub_rules: Vec::new(),
+            scrub_key: Vec::new(),
+            ip_mode: "drop".to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn event(project_id: &str, days_ago: i64, now: DateTime<Utc>) -> crate::models::Event {
+        crate::models::Event::fixture(project_id).at(now - Duration::days(days_ago))
+    }
+
+    #[tokio::test]
+    async fn test_report_and_enforce_per_project() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        let store = MemoryStore::new();
+        let mut events = Vec::new();
+        for days_ago in [1, 29, 31, 45, 400] {
+            events.push(event("free", days_ago, now));
+            events.push(event("paid", days_ago, now));
+            events.push(event("forever", days_ago, now));
+        }
+        store.insert(&events).await.unwrap();
+        let projects = [
+            project("free", Some(30)),
+            project("paid", Some(365)),
+            project("forever", None),
+        ];
+
+        let dry_run = report(&store, &projects, now).await.unwrap();
+        assert!(dry_run.dry_run);
+        assert_eq!(dry_run.projects.len(), 2);
+        assert_eq!(dry_run.projects[0].expired_events, 3);
+        assert_eq!(
+            dry_run.projects[0].cutoff,
+            Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap()
+        );
+        assert_eq!(dry_run.projects[1].expired_events, 1);
+        assert_eq!(store.count(&EventRange::default()).await.unwrap(), 15);
+
+        // A batch size of 2 takes several statements for the free project
+        let metrics = Metrics::new();
+        let enforced = enforce(
+            &store,
+            &projects,
+            2,
+            now,
+            &metrics,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert!(!enforced.dry_run);
+        assert_eq!(enforced.total(), 4);
+        assert_eq!(
+            metrics
+                .retention_events_deleted
+                .with_label_values(&["free"])
+                .get(),
+            3
+        );
+        assert_eq!(store.count(&EventRange::default()).await.unwrap(), 11);
+        assert_eq!(report(&store, &projects, now).await.unwrap().total(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_enforce_stops_on_shutdown() {
+        let now = Utc::now();
+        let store = MemoryStore::new();
+        store.insert(&[event("free", 60, now)]).await.unwrap();
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+
+        let enforced = enforce(
+            &store,
+            &[project("free", Some(30))],
+            100,
+            now,
+            &Metrics::new(),
+            &shutdown,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(enforced.total(), 0);
+        assert_eq!(store.count(&EventRange::default()).await.unwrap(), 1);
+    }
+}
@@ -1,15 +1,11 @@
 use axum::{
     middleware,
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
-use tower_http::{
-    compression::CompressionLayer,
-    cors::CorsLayer,
-    trace::TraceLayer,
-};
+use tower_http::{compression::CompressionLayer, trace::TraceLayer};
 
-use crate::{handlers, middleware as mw, AppState};
+use crate::{handlers, middleware as mw, telemetry, AppState};
 
 /// Build the application router
 pub fn create_router(state: AppState) -> Router {
@@ -19,18 +15,357 @@ pub fn create_router(state: AppState) -> Router {
         .route("/ready", get(handlers::readiness))
         .route("/live", get(handlers::liveness));
 
+    // Metrics name projects and routes, so scrapers authenticate too
+    let metrics_routes = Router::new()
+        .route("/metrics", get(handlers::metrics))
+        .layer(middleware::from_fn_with_state(state.clone(), mw::auth));
+
+    // Administrative routes
+    let admin_routes = Router::new()
+        .route("/projects", post(handlers::create_project))
+        .route("/projects/{id}", patch(handlers::update_project))
+        .route("/projects/{id}/keys", post(handlers::create_key))
+        .route("/keys/{id}", delete(handlers::revoke_key))
+        .route("/organizations", post(handlers::create_organization))
+        .route(
+            "/organizations/{id}/members/{subject}",
+            put(handlers::set_member).delete(handlers::remove_member),
+        )
+        .route("/audit", get(handlers::list_audit_log))
+        .route("/config/reload", post(handlers::reload_config))
+        .route("/retention", get(handlers::retention_report))
+        .route("/storage/chunks", get(handlers::chunk_sizes));
+
     // API routes (auth required)
     let api_routes = Router::new()
         .route("/ingest", post(handlers::ingest_events))
+        .route("/projects/{id}/events", get(handlers::list_events))
+        .route(
+            "/projects/{id}/events/aggregate",
+            get(handlers::aggregate_events),
+        )
+        .route(
+            "/projects/{id}/users/{user_id}",
+            delete(handlers::delete_user_data),
+        )
+        .route(
+            "/projects/{id}/users/{user_id}/export",
+            post(handlers::export_user_data),
+        )
+        .route("/projects/{id}/jobs/{job_id}", get(handlers::get_job))
+        .route(
+            "/projects/{id}/jobs/{job_id}/download",
+            get(handlers::download_export),
+        )
+        .nest("/admin", admin_routes)
+        .layer(middleware::from_fn_with_state(state.clone(), mw::cors))
         .layer(middleware::from_fn_with_state(state.clone(), mw::auth));
 
     // Combine routes
     Router::new()
         .nest("/api", api_routes)
         .merge(health_routes)
-        .layer(middleware::from_fn(mw::log_request))
+        .merge(metrics_routes)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            mw::log_request,
+        ))
         .layer(CompressionLayer::new())
-        .layer(CorsLayer::permissive()) // Will configure CORS properly for production
-        .layer(TraceLayer::new_for_http())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::make_request_span)
+                .on_response(telemetry::record_response),
+        )
+        .layer(middleware::from_fn(mw::request_id))
         .with_state(state)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::storage::MemoryStore;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::Arc;
+    use tokio_util::sync::CancellationToken;
+    use tower::ServiceExt;
+
+    fn state() -> AppState {
+        let config = Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "postgres://db".to_string())
+        })
+        .unwrap();
+        let pool = PgPoolOptions::new()
+            .connect_lazy(&config.database.url)
+            .unwrap();
+        AppState::new(Some(pool), config)
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_metrics_scrape() {
+        let state = state();
+        state.metrics.add_project("web");
+        state.metrics.reject_events("web", "rate_limited", 3);
+        state.metrics.reject_events("unknown", "rate_limited", 2);
+        state.metrics.batch_size.observe(10.0);
+        let app = create_router(state);
+
+        get(&app, "/live").await;
+        let (status, _) = get(&app, "/metrics").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(
+                authorized(Request::get("/metrics"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(
+            r#"pulsemetrics_http_requests_total{method="GET",route="/live",status="200"} 1"#
+        ));
+        assert!(body.contains(
+            r#"pulsemetrics_http_request_duration_seconds_bucket{method="GET",route="/live",status="200",le="+Inf"} 1"#
+        ));
+        assert!(body.contains(
+            r#"pulsemetrics_events_rejected_total{project_id="web",reason="rate_limited"} 3"#
+        ));
+        assert!(body.contains(
+            r#"pulsemetrics_events_rejected_total{project_id="other",reason="rate_limited"} 2"#
+        ));
+        assert!(body.contains("pulsemetrics_ingest_batch_size_count 1"));
+        assert!(body.contains(r#"pulsemetrics_db_pool_connections{state="idle"} 0"#));
+        assert!(body.contains("pulsemetrics_db_pool_max_connections 10"));
+        assert!(body.contains("pulsemetrics_ingest_buffer_depth 0"));
+        assert!(body.contains("# TYPE pulsemetrics_cors_rejections_total counter"));
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_echoed_in_errors() {
+        let app = create_router(state());
+        let request = Request::post("/api/ingest")
+            .header("x-request-id", "client-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["x-request-id"], "client-123");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["request_id"], "client-123");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_generated() {
+        let app = create_router(state());
+
+        let response = app
+            .oneshot(Request::get("/live").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(id).is_ok());
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn authorized(builder: axum::http::request::Builder) -> axum::http::request::Builder {
+        builder.header(
+            "authorization",
+            format!("Bearer {}", crate::config::DEFAULT_API_KEY),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_ingest_and_query_in_memory() {
+        let state = state().with_event_store(Arc::new(MemoryStore::new()));
+        let shutdown = CancellationToken::new();
+        let flusher = state.buffer.start(
+            state.events.clone(),
+            state.config.clone(),
+            state.metrics.clone(),
+            shutdown.clone(),
+        );
+        let app = create_router(state);
+
+        let batch = serde_json::json!({
+            "events": [
+                {"project_id": "web", "event_type": "click", "time": "2024-05-01T12:00:00Z", "value": 2.0},
+                {"project_id": "web", "event_type": "click", "time": "2024-05-01T12:30:00Z", "value": 4.0},
+                {"project_id": "web", "event_type": "view", "time": "2024-05-01T13:15:00Z"},
+            ]
+        });
+        let request = authorized(Request::post("/api/ingest"))
+            .header("content-type", "application/json")
+            .body(Body::from(batch.to_string()))
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["accepted"], 3);
+
+        // Write the buffered events
+        shutdown.cancel();
+        flusher.await.unwrap();
+
+        let request = authorized(Request::get("/api/projects/web/events?event_type=click"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(body[0]["time"], "2024-05-01T12:30:00Z");
+
+        let request = authorized(Request::get(
+            "/api/projects/web/events/aggregate?from=2024-05-01T00:00:00Z&to=2024-05-02T00:00:00Z",
+        ))
+        .body(Body::empty())
+        .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["count"], 2);
+        assert_eq!(body[0]["avg"], 3.0);
+        assert_eq!(body[1]["bucket"], "2024-05-01T13:00:00Z");
+        assert_eq!(body[1]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_memory_mode_runs_without_postgres() {
+        let config = Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "memory://".to_string())
+        })
+        .unwrap();
+        let app = create_router(AppState::new(None, config));
+
+        let (status, _) = get(&app, "/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = get(&app, "/health").await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["components"]["event_store"]["status"], "healthy");
+        for component in ["database", "timescaledb", "migrations"] {
+            assert!(body["components"].get(component).is_none(), "{}", component);
+        }
+
+        // Answered at once rather than after waiting for a connection
+        let request = authorized(Request::get("/api/projects/web/events"))
+            .header("origin", "https://app.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let request = authorized(Request::get("/api/admin/audit"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reload_without_postgres() {
+        let directory = std::env::temp_dir().join(format!("pulsemetrics-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.toml");
+        std::fs::write(&path, "[database]\nurl = \"memory://\"\n").unwrap();
+        let config = Config::load(Some(&path)).unwrap();
+        let app = create_router(AppState::new(None, config).with_config_path(Some(path)));
+
+        // Applied, with no audit log to record it in
+        let request = authorized(Request::post("/api/admin/config/reload"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        std::fs::remove_dir_all(&directory).ok();
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["changed"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_batch_size_follows_config() {
+        let config = Config::load_from(None, |var| match var {
+            "DATABASE_URL" => Some("memory://".to_string()),
+            "MAX_BATCH_SIZE" => Some("1500".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let app = create_router(AppState::new(None, config));
+
+        for (count, expected) in [
+            (1200, StatusCode::ACCEPTED),
+            (1501, StatusCode::BAD_REQUEST),
+        ] {
+            let events: Vec<_> = (0..count)
+                .map(|_| serde_json::json!({"project_id": "web", "event_type": "click"}))
+                .collect();
+            let batch = serde_json::json!({ "events": events });
+            let request = authorized(Request::post("/api/ingest"))
+                .header("content-type", "application/json")
+                .body(Body::from(batch.to_string()))
+                .unwrap();
+            let (status, body) = send(&app, request).await;
+            assert_eq!(status, expected, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunk_sizes_require_timescaledb() {
+        let app = create_router(state().with_event_store(Arc::new(MemoryStore::new())));
+
+        let request = authorized(Request::get("/api/admin/storage/chunks"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, request).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_query_rejects_oversized_limit() {
+        let app = create_router(state().with_event_store(Arc::new(MemoryStore::new())));
+
+        let request = authorized(Request::get("/api/projects/web/events?limit=5000"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(&app, request).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
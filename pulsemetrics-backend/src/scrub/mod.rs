@@ -0,0 +1,609 @@
+use hmac::{Hmac, Mac};
+use regex::{Regex, RegexBuilder};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    db::projects,
+    models::{Detector, Event, Project, ScrubAction, ScrubRule},
+    AppState,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Most scrubbing rules a project may define
+pub const MAX_RULES: u64 = 50;
+/// Longest pattern a rule may use
+const MAX_PATTERN_LENGTH: usize = 500;
+/// Compiled size allowed for a pattern
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+/// Time before rules changed through another instance apply here
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b").unwrap()
+});
+// Candidates only; `is_phone` rejects dates, versions and plain numbers
+static PHONE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\+?\(?\b\d[\d ().-]{5,}\d\b").unwrap());
+static CREDIT_CARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap());
+
+enum Matcher {
+    Text {
+        regex: Regex,
+        verify: Option<fn(&str) -> bool>,
+    },
+    /// Lowercase property name
+    Key(String),
+}
+
+/// Scrubbing rules of a project, compiled
+pub struct Scrubber {
+    key: Vec<u8>,
+    rules: Vec<ScrubRule>,
+    matchers: Vec<Matcher>,
+}
+
+impl Scrubber {
+    /// `key` is the project's secret keying hashes
+    pub fn new(key: &[u8], rules: &[ScrubRule]) -> Result<Self, String> {
+        let matchers = rules
+            .iter()
+            .enumerate()
+            .map(|(idx, rule)| compile(rule).map_err(|e| format!("Rule {}: {}", idx + 1, e)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            key: key.to_vec(),
+            rules: rules.to_vec(),
+            matchers,
+        })
+    }
+
+    pub fn rules(&self) -> &[ScrubRule] {
+        &self.rules
+    }
+
+    /// Scrub the properties and `user_id` of an event in place
+    ///
+    /// `redactions` is indexed like [`Scrubber::rules`] and counts the
+    /// values each rule scrubbed.
+    pub fn scrub(&self, event: &mut Event, redactions: &mut [u64]) {
+        if let Some(properties) = &mut event.properties {
+            if !self.scrub_value(properties, redactions) {
+                event.properties = None;
+            }
+        }
+
+        if let Some(user_id) = &event.user_id {
+            match self.scrub_text(user_id, redactions) {
+                None => event.user_id = None,
+                Some(Cow::Owned(scrubbed)) => event.user_id = Some(scrubbed),
+                Some(Cow::Borrowed(_)) => {}
+            }
+        }
+    }
+
+    /// `user_id` as [`Scrubber::scrub`] stores it, `None` when a rule masks or drops it
+    ///
+    /// A mask is shared by every id it matches, so only unchanged and
+    /// hashed ids still tell users apart.
+    pub fn stored_user_id(&self, user_id: &str) -> Option<String> {
+        let mut redactions = vec![0; self.rules.len()];
+        let stored = self.scrub_text(user_id, &mut redactions)?;
+        let masked = self
+            .rules
+            .iter()
+            .zip(&redactions)
+            .any(|(rule, &count)| count > 0 && rule.action != ScrubAction::Hash);
+
+        (!masked).then(|| stored.into_owned())
+    }
+
+    /// Apply the detectors and patterns in order, `None` when one of them drops the text
+    fn scrub_text<'a>(&self, text: &'a str, redactions: &mut [u64]) -> Option<Cow<'a, str>> {
+        let mut text = Cow::Borrowed(text);
+        for (idx, (rule, matcher)) in self.rules.iter().zip(&self.matchers).enumerate() {
+            let Matcher::Text { regex, verify } = matcher else {
+                continue;
+            };
+
+            let mut scrubbed = String::new();
+            let mut last = 0;
+            for found in regex.find_iter(&text) {
+                if verify.is_some_and(|verify| !verify(found.as_str())) {
+                    continue;
+                }
+                redactions[idx] += 1;
+                if rule.action == ScrubAction::Drop {
+                    return None;
+                }
+                scrubbed.push_str(&text[last..found.start()]);
+                scrubbed.push_str(&self.replacement(rule, found.as_str()));
+                last = found.end();
+            }
+
+            if last > 0 {
+                scrubbed.push_str(&text[last..]);
+                text = Cow::Owned(scrubbed);
+            }
+        }
+
+        Some(text)
+    }
+
+    /// Scrub a property value, returning whether it is kept
+    fn scrub_value(&self, value: &mut Value, redactions: &mut [u64]) -> bool {
+        match value {
+            Value::String(text) => match self.scrub_text(text, redactions) {
+                None => false,
+                Some(Cow::Owned(scrubbed)) => {
+                    *text = scrubbed;
+                    true
+                }
+                Some(Cow::Borrowed(_)) => true,
+            },
+            // Card and phone numbers are sometimes sent as numbers
+            Value::Number(number) => match self.scrub_text(&number.to_string(), redactions) {
+                None => false,
+                Some(Cow::Owned(scrubbed)) => {
+                    *value = Value::String(scrubbed);
+                    true
+                }
+                Some(Cow::Borrowed(_)) => true,
+            },
+            Value::Array(items) => {
+                items.retain_mut(|item| self.scrub_value(item, redactions));
+                true
+            }
+            Value::Object(map) => {
+                self.scrub_object(map, redactions);
+                true
+            }
+            Value::Null | Value::Bool(_) => true,
+        }
+    }
+
+    fn scrub_object(&self, map: &mut Map<String, Value>, redactions: &mut [u64]) {
+        map.retain(|key, value| {
+            let key = key.to_lowercase();
+            let denied = self
+                .matchers
+                .iter()
+                .position(|matcher| matches!(matcher, Matcher::Key(denied) if key == *denied));
+            let Some(idx) = denied else {
+                return self.scrub_value(value, redactions);
+            };
+
+            redactions[idx] += 1;
+            let rule = &self.rules[idx];
+            if rule.action == ScrubAction::Drop {
+                return false;
+            }
+            let text = match &*value {
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            *value = Value::String(self.replacement(rule, &text));
+            true
+        });
+    }
+
+    fn replacement(&self, rule: &ScrubRule, text: &str) -> String {
+        match rule.action {
+            ScrubAction::Hash => self.hash(text),
+            ScrubAction::Mask | ScrubAction::Drop => format!("[{}]", rule.name()),
+        }
+    }
+
+    /// Keyed by the project's secret, so hashes correlate within the project
+    /// but cannot be reversed by hashing guesses without the key
+    fn hash(&self, text: &str) -> String {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(text.as_bytes());
+        hex::encode(&mac.finalize().into_bytes()[..16])
+    }
+}
+
+fn compile(rule: &ScrubRule) -> Result<Matcher, String> {
+    match (rule.detector, &rule.pattern, &rule.key) {
+        (Some(detector), None, None) => Ok(match detector {
+            Detector::Email => Matcher::Text {
+                regex: EMAIL.clone(),
+                verify: None,
+            },
+            Detector::Phone => Matcher::Text {
+                regex: PHONE.clone(),
+                verify: Some(is_phone),
+            },
+            Detector::CreditCard => Matcher::Text {
+                regex: CREDIT_CARD.clone(),
+                verify: Some(|text| luhn(&digits(text))),
+            },
+        }),
+        (None, Some(pattern), None) => {
+            if pattern.len() > MAX_PATTERN_LENGTH {
+                return Err(format!(
+                    "pattern is longer than {} characters",
+                    MAX_PATTERN_LENGTH
+                ));
+            }
+            let regex = RegexBuilder::new(pattern)
+                .size_limit(PATTERN_SIZE_LIMIT)
+                .build()
+                .map_err(|e| format!("invalid pattern: {}", e))?;
+            if regex.is_match("") {
+                return Err("pattern matches the empty string".to_string());
+            }
+            Ok(Matcher::Text {
+                regex,
+                verify: None,
+            })
+        }
+        (None, None, Some(key)) if key.is_empty() => Err("key must not be empty".to_string()),
+        (None, None, Some(key)) => Ok(Matcher::Key(key.to_lowercase())),
+        _ => Err("set exactly one of detector, pattern and key".to_string()),
+    }
+}
+
+fn digits(text: &str) -> Vec<u32> {
+    text.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(idx, &d)| match (idx % 2, d * 2) {
+            (0, _) => d,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Whether a candidate is written like a phone number
+///
+/// Needs 7 to 15 digits, and a country code, an area code in parentheses
+/// or at least three groups ending in one of four digits or more, which
+/// rules out dates and IPv4 addresses. Card numbers are left to their own
+/// detector.
+fn is_phone(text: &str) -> bool {
+    let digits = digits(text);
+    if !(7..=15).contains(&digits.len()) || (digits.len() >= 13 && luhn(&digits)) {
+        return false;
+    }
+
+    let groups: Vec<&str> = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|group| !group.is_empty())
+        .collect();
+    text.starts_with('+')
+        || text.starts_with('(')
+        || (groups.len() >= 3 && groups.last().is_some_and(|group| group.len() >= 4))
+}
+
+/// Validate the `scrub_rules` of a project request
+pub fn validate_rules(rules: &[ScrubRule]) -> Result<(), validator::ValidationError> {
+    Scrubber::new(&[], rules)
+        .map(|_| ())
+        .map_err(|e| validator::ValidationError::new("scrub_rules").with_message(e.into()))
+}
+
+/// Compiled scrubbers of the projects that have rules
+///
+/// Kept in memory so ingestion never waits on the database; handlers
+/// update it as rules change, and [`start`] reloads it for changes made
+/// through other instances.
+#[derive(Default)]
+pub struct ScrubberCache {
+    scrubbers: Mutex<HashMap<String, Arc<Scrubber>>>,
+}
+
+impl ScrubberCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, project_id: &str) -> Option<Arc<Scrubber>> {
+        let scrubbers = self.scrubbers.lock().expect("scrubber cache poisoned");
+        scrubbers.get(project_id).cloned()
+    }
+
+    /// Use a project's current rules, compiling them only when they changed
+    pub fn set(&self, project_id: &str, key: &[u8], rules: &[ScrubRule]) -> Result<(), String> {
+        let mut scrubbers = self.scrubbers.lock().expect("scrubber cache poisoned");
+        if rules.is_empty() {
+            scrubbers.remove(project_id);
+            return Ok(());
+        }
+        if scrubbers
+            .get(project_id)
+            .is_some_and(|scrubber| scrubber.rules() == rules && scrubber.key == key)
+        {
+            return Ok(());
+        }
+
+        let scrubber = Scrubber::new(key, rules)?;
+        scrubbers.insert(project_id.to_string(), Arc::new(scrubber));
+        Ok(())
+    }
+
+    /// Keep only the given projects, with their current rules
+    fn replace(&self, projects: &[Project]) {
+        self.scrubbers
+            .lock()
+            .expect("scrubber cache poisoned")
+            .retain(|project_id, _| projects.iter().any(|p| p.id == *project_id));
+
+        for project in projects {
+            if let Err(e) = self.set(&project.id, &project.scrub_key, &project.scrub_rules) {
+                tracing::error!(project_id = %project.id, "Invalid scrubbing rules: {}", e);
+            }
+        }
+    }
+}
+
+/// Load the scrubbing rules of every project, none without Postgres
+pub async fn refresh(state: &AppState) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    let projects = projects::with_scrub_rules(db).await?;
+    state.scrubbers.replace(&projects);
+    Ok(())
+}
+
+/// Spawn the task reloading scrubbing rules every `REFRESH_INTERVAL`
+pub fn start(state: AppState, shutdown: CancellationToken) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(REFRESH_INTERVAL) => {}
+                _ = shutdown.cancelled() => break,
+            }
+
+            if let Err(e) = refresh(&state).await {
+                tracing::warn!("Failed to reload scrubbing rules: {}", e);
+            }
+        }
+    })
+}
+
+/// Scrub events with the rules of their projects before they are stored
+pub fn scrub_batch(state: &AppState, events: &mut [Event], project_ids: &[String]) {
+    for project_id in project_ids {
+        let Some(scrubber) = state.scrubbers.get(project_id) else {
+            continue;
+        };
+
+        let mut redactions = vec![0; scrubber.rules().len()];
+        for event in events.iter_mut().filter(|e| e.project_id == *project_id) {
+            scrubber.scrub(event, &mut redactions);
+        }
+
+        for (rule, count) in scrubber.rules().iter().zip(redactions) {
+            if count > 0 {
+                state
+                    .metrics
+                    .pii_redactions
+                    .with_label_values(&[project_id, rule.name(), rule.action.as_str()])
+                    .inc_by(count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    /// Text with personal data and how the built-in detectors mask it
+    #[derive(Deserialize)]
+    struct Case {
+        text: String,
+        expected: String,
+    }
+
+    fn rule(detector: Detector, action: ScrubAction) -> ScrubRule {
+        ScrubRule {
+            detector: Some(detector),
+            pattern: None,
+            key: None,
+            action,
+            name: None,
+        }
+    }
+
+    fn key(key: &str, action: ScrubAction) -> ScrubRule {
+        ScrubRule {
+            detector: None,
+            pattern: None,
+            key: Some(key.to_string()),
+            action,
+            name: None,
+        }
+    }
+
+    fn event(properties: Value, user_id: &str) -> Event {
+        Event::fixture("web")
+            .event_type("signup")
+            .properties(properties)
+            .user_id(user_id)
+    }
+
+    #[test]
+    fn test_detector_corpus() {
+        let cases: Vec<Case> = serde_json::from_str(include_str!("corpus.json")).unwrap();
+        let scrubber = Scrubber::new(
+            b"web-key",
+            &[
+                rule(Detector::Email, ScrubAction::Mask),
+                rule(Detector::CreditCard, ScrubAction::Mask),
+                rule(Detector::Phone, ScrubAction::Mask),
+            ],
+        )
+        .unwrap();
+
+        for case in cases {
+            let mut redactions = [0; 3];
+            let scrubbed = scrubber.scrub_text(&case.text, &mut redactions).unwrap();
+            assert_eq!(scrubbed, case.expected, "{}", case.text);
+        }
+    }
+
+    #[test]
+    fn test_actions_on_properties_and_user_id() {
+        let scrubber = Scrubber::new(
+            b"web-key",
+            &[
+                rule(Detector::Email, ScrubAction::Hash),
+                rule(Detector::CreditCard, ScrubAction::Drop),
+                key("Password", ScrubAction::Drop),
+                key("ssn", ScrubAction::Mask),
+            ],
+        )
+        .unwrap();
+
+        let mut e = event(
+            json!({
+                "plan": "pro",
+                "password": "hunter2",
+                "billing": {"SSN": "078-05-1120", "card": 4111111111111111_u64},
+                "contacts": ["a@example.com", "4111 1111 1111 1111", "none"],
+            }),
+            "jane@example.com",
+        );
+        let mut redactions = [0; 4];
+        scrubber.scrub(&mut e, &mut redactions);
+
+        let hashed = scrubber.hash("a@example.com");
+        assert_eq!(
+            e.properties.unwrap(),
+            json!({
+                "plan": "pro",
+                "billing": {"SSN": "[redacted]"},
+                "contacts": [hashed, "none"],
+            })
+        );
+        assert_eq!(e.user_id, Some(scrubber.hash("jane@example.com")));
+        assert_eq!(redactions, [2, 2, 1, 1]);
+
+        // Hashes are stable under the same key only
+        let other = Scrubber::new(b"app-key", scrubber.rules()).unwrap();
+        assert_eq!(scrubber.hash("a@example.com"), hashed);
+        assert_ne!(other.hash("a@example.com"), hashed);
+    }
+
+    #[test]
+    fn test_dropped_user_id_and_custom_pattern() {
+        let scrubber = Scrubber::new(
+            b"web-key",
+            &[
+                rule(Detector::Email, ScrubAction::Drop),
+                ScrubRule {
+                    detector: None,
+                    pattern: Some(r"\bACCT-\d+\b".to_string()),
+                    key: None,
+                    action: ScrubAction::Mask,
+                    name: Some("account".to_string()),
+                },
+            ],
+        )
+        .unwrap();
+
+        let mut e = event(json!("paid from ACCT-991 and ACCT-12"), "jane@example.com");
+        let mut redactions = [0; 2];
+        scrubber.scrub(&mut e, &mut redactions);
+
+        assert_eq!(
+            e.properties,
+            Some(json!("paid from [account] and [account]"))
+        );
+        assert_eq!(e.user_id, None);
+        assert_eq!(redactions, [1, 2]);
+    }
+
+    #[test]
+    fn test_stored_user_id() {
+        let hashing =
+            Scrubber::new(b"web-key", &[rule(Detector::Email, ScrubAction::Hash)]).unwrap();
+        assert_eq!(
+            hashing.stored_user_id("jane@example.com"),
+            Some(hashing.hash("jane@example.com"))
+        );
+        assert_eq!(hashing.stored_user_id("u-42").as_deref(), Some("u-42"));
+
+        for action in [ScrubAction::Mask, ScrubAction::Drop] {
+            let scrubber = Scrubber::new(b"web-key", &[rule(Detector::Email, action)]).unwrap();
+            assert_eq!(scrubber.stored_user_id("jane@example.com"), None);
+            assert_eq!(scrubber.stored_user_id("u-42").as_deref(), Some("u-42"));
+        }
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut both = rule(Detector::Email, ScrubAction::Mask);
+        both.key = Some("email".to_string());
+        let pattern = |pattern: &str| ScrubRule {
+            detector: None,
+            pattern: Some(pattern.to_string()),
+            key: None,
+            action: ScrubAction::Mask,
+            name: None,
+        };
+
+        for rules in [
+            vec![both],
+            vec![key("", ScrubAction::Drop)],
+            vec![pattern("(unclosed")],
+            vec![pattern("a*")],
+            vec![pattern(&"a".repeat(MAX_PATTERN_LENGTH + 1))],
+        ] {
+            assert!(validate_rules(&rules).is_err(), "{:?}", rules);
+        }
+        assert!(validate_rules(&[pattern(r"\d{3}")]).is_ok());
+    }
+
+    #[test]
+    fn test_cache_follows_rule_changes() {
+        let cache = ScrubberCache::new();
+        let rules = vec![rule(Detector::Email, ScrubAction::Mask)];
+
+        cache.set("web", b"key", &rules).unwrap();
+        let first = cache.get("web").unwrap();
+        cache.set("web", b"key", &rules).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.get("web").unwrap()));
+
+        cache
+            .set("web", b"key", &[rule(Detector::Email, ScrubAction::Hash)])
+            .unwrap();
+        let second = cache.get("web").unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        cache
+            .set(
+                "web",
+                b"new-key",
+                &[rule(Detector::Email, ScrubAction::Hash)],
+            )
+            .unwrap();
+        assert!(!Arc::ptr_eq(&second, &cache.get("web").unwrap()));
+        cache.set("web", b"key", &[]).unwrap();
+        assert!(cache.get("web").is_none());
+        assert!(cache.get("app").is_none());
+    }
+}
@@ -0,0 +1,554 @@
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::EventStore;
+use crate::models::{AggregateBucket, AggregateQuery, Event, EventQuery, EventRange};
+
+/// Schema of the `events` table, equivalent to `001_initial_schema.sql`
+const SCHEMA: &str = include_str!("clickhouse.sql");
+
+const DEFAULT_HTTP_PORT: u16 = 8123;
+const DEFAULT_HTTPS_PORT: u16 = 8443;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings sent with every statement
+///
+/// Timestamps go in as RFC 3339 and come back as ISO 8601, and 64-bit
+/// counts are returned as JSON numbers rather than strings.
+const SETTINGS: &[(&str, &str)] = &[
+    ("date_time_input_format", "best_effort"),
+    ("date_time_output_format", "iso"),
+    ("output_format_json_quote_64bit_integers", "0"),
+];
+
+const EVENT_COLUMNS: &str =
+    "id, time, project_id, event_type, properties, user_id, session_id, value";
+
+/// Events in a ClickHouse MergeTree table, over the HTTP interface
+///
+/// Batches are inserted as `JSONEachRow`. Query values are sent as
+/// ClickHouse query parameters, never interpolated into the SQL.
+pub struct ClickHouseStore {
+    client: Client,
+    endpoint: Url,
+    database: String,
+    user: String,
+    password: String,
+}
+
+impl ClickHouseStore {
+    /// Store for `clickhouse://[user[:password]@]host[:port][/database]`
+    ///
+    /// `clickhouses://` uses HTTPS. No connection is made until the first
+    /// statement.
+    pub fn from_url(url: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(url).context("Invalid ClickHouse URL")?;
+        let (scheme, default_port) = match url.scheme() {
+            "clickhouse" => ("http", DEFAULT_HTTP_PORT),
+            "clickhouses" => ("https", DEFAULT_HTTPS_PORT),
+            other => bail!("Unsupported ClickHouse URL scheme {}", other),
+        };
+        let host = url.host_str().context("ClickHouse URL has no host")?;
+        let endpoint = Url::parse(&format!(
+            "{}://{}:{}/",
+            scheme,
+            host,
+            url.port().unwrap_or(default_port)
+        ))?;
+
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let database = decode(url.path().trim_start_matches('/'));
+        let user = decode(url.username());
+
+        Ok(Self {
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            endpoint,
+            database: if database.is_empty() {
+                "default".to_string()
+            } else {
+                database
+            },
+            user: if user.is_empty() {
+                "default".to_string()
+            } else {
+                user
+            },
+            password: url.password().map(decode).unwrap_or_default(),
+        })
+    }
+
+    /// Create the `events` table if it does not exist
+    pub async fn ensure_schema(&self) -> anyhow::Result<()> {
+        self.send(None, SCHEMA.into(), &Params::default(), &[])
+            .await
+            .context("Failed to create ClickHouse schema")?;
+        Ok(())
+    }
+
+    /// Run one statement, with `query` in the URL when the body carries data
+    async fn send(
+        &self,
+        query: Option<&str>,
+        body: Vec<u8>,
+        params: &Params,
+        settings: &[(&str, &str)],
+    ) -> anyhow::Result<String> {
+        let mut request = self
+            .client
+            .post(self.endpoint.clone())
+            .header("X-ClickHouse-User", &self.user)
+            .header("X-ClickHouse-Key", &self.password)
+            .query(&[("database", self.database.as_str())])
+            .query(SETTINGS)
+            .query(settings)
+            .query(&params.0);
+        if let Some(query) = query {
+            request = request.query(&[("query", query)]);
+        }
+
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .context("ClickHouse request failed")?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            bail!("ClickHouse returned {}: {}", status, text.trim());
+        }
+
+        Ok(text)
+    }
+
+    /// Rows of a `SELECT ... FORMAT JSONEachRow`
+    async fn select<T: DeserializeOwned>(
+        &self,
+        sql: String,
+        params: &Params,
+    ) -> anyhow::Result<Vec<T>> {
+        let text = self.send(None, sql.into_bytes(), params, &[]).await?;
+        text.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).context("Unexpected ClickHouse row"))
+            .collect()
+    }
+}
+
+/// Values bound to `{name:Type}` placeholders
+#[derive(Debug, Default)]
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Placeholder for `value`, which ClickHouse parses as `ty`
+    fn bind(&mut self, ty: &str, value: impl ToString) -> String {
+        let name = format!("p{}", self.0.len());
+        self.0.push((format!("param_{}", name), value.to_string()));
+        format!("{{{}:{}}}", name, ty)
+    }
+
+    fn bind_time(&mut self, time: DateTime<Utc>) -> String {
+        format!(
+            "fromUnixTimestamp64Micro({})",
+            self.bind("Int64", time.timestamp_micros())
+        )
+    }
+}
+
+fn range_filter(range: &EventRange, params: &mut Params) -> String {
+    let mut conditions = vec!["1".to_string()];
+    if let Some(project_id) = &range.project_id {
+        conditions.push(format!(
+            "project_id = {}",
+            params.bind("String", project_id)
+        ));
+    }
+    if let Some(user_id) = &range.user_id {
+        conditions.push(format!("user_id = {}", params.bind("String", user_id)));
+    }
+    if let Some(from) = range.from {
+        conditions.push(format!("time >= {}", params.bind_time(from)));
+    }
+    if let Some(to) = range.to {
+        conditions.push(format!("time < {}", params.bind_time(to)));
+    }
+    conditions.join(" AND ")
+}
+
+/// An event as stored, with `properties` as JSON text
+#[derive(Debug, Serialize, Deserialize)]
+struct EventRow {
+    id: Uuid,
+    time: DateTime<Utc>,
+    project_id: String,
+    event_type: String,
+    properties: Option<String>,
+    user_id: Option<String>,
+    session_id: Option<Uuid>,
+    value: Option<f64>,
+}
+
+impl From<&Event> for EventRow {
+    fn from(event: &Event) -> Self {
+        Self {
+            id: event.id,
+            time: event.time,
+            project_id: event.project_id.clone(),
+            event_type: event.event_type.clone(),
+            properties: event.properties.as_ref().map(|p| p.to_string()),
+            user_id: event.user_id.clone(),
+            session_id: event.session_id,
+            value: event.value,
+        }
+    }
+}
+
+impl TryFrom<EventRow> for Event {
+    type Error = serde_json::Error;
+
+    fn try_from(row: EventRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            time: row.time,
+            project_id: row.project_id,
+            event_type: row.event_type,
+            properties: row
+                .properties
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+            user_id: row.user_id,
+            session_id: row.session_id,
+            value: row.value,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CountRow {
+    count: u64,
+}
+
+#[async_trait]
+impl EventStore for ClickHouseStore {
+    #[tracing::instrument(
+        name = "insert_events",
+        skip_all,
+        fields(db.system = "clickhouse", db.operation = "INSERT", rows = batch.len())
+    )]
+    async fn insert(&self, batch: &[Event]) -> anyhow::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut body = Vec::new();
+        for event in batch {
+            serde_json::to_writer(&mut body, &EventRow::from(event))?;
+            body.push(b'\n');
+        }
+
+        self.send(
+            Some("INSERT INTO events FORMAT JSONEachRow"),
+            body,
+            &Params::default(),
+            &[],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn query(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+        let mut params = Params::default();
+        let mut sql = format!(
+            "SELECT {} FROM events WHERE project_id = {}",
+            EVENT_COLUMNS,
+            params.bind("String", &query.project_id)
+        );
+        if let Some(event_type) = &query.event_type {
+            sql += &format!(" AND event_type = {}", params.bind("String", event_type));
+        }
+        if let Some(user_id) = &query.user_id {
+            sql += &format!(" AND user_id = {}", params.bind("String", user_id));
+        }
+        if let Some(from) = query.from {
+            sql += &format!(" AND time >= {}", params.bind_time(from));
+        }
+        if let Some(to) = query.to {
+            sql += &format!(" AND time < {}", params.bind_time(to));
+        }
+        sql += &format!(
+            " ORDER BY time DESC LIMIT {} FORMAT JSONEachRow",
+            params.bind("UInt32", query.limit)
+        );
+
+        let rows: Vec<EventRow> = self.select(sql, &params).await?;
+        Ok(rows
+            .into_iter()
+            .map(Event::try_from)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn aggregate(&self, query: &AggregateQuery) -> anyhow::Result<Vec<AggregateBucket>> {
+        let mut params = Params::default();
+        let mut sql = format!(
+            "SELECT toStartOfInterval(time, toIntervalSecond({})) AS bucket, \
+                    count() AS count, uniqExact(user_id) AS unique_users, \
+                    sum(value) AS sum, avg(value) AS avg \
+             FROM events \
+             WHERE project_id = {} AND time >= {} AND time < {}",
+            params.bind("UInt32", query.interval.duration().num_seconds()),
+            params.bind("String", &query.project_id),
+            params.bind_time(query.from),
+            params.bind_time(query.to),
+        );
+        if let Some(event_type) = &query.event_type {
+            sql += &format!(" AND event_type = {}", params.bind("String", event_type));
+        }
+        sql += " GROUP BY bucket ORDER BY bucket FORMAT JSONEachRow";
+
+        self.select(sql, &params).await
+    }
+
+    /// Counts the range, then deletes it with a synchronous mutation
+    ///
+    /// Events inserted into the range between the two statements are
+    /// deleted but not counted.
+    async fn delete(&self, range: &EventRange) -> anyhow::Result<u64> {
+        let count = self.count(range).await?;
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let mut params = Params::default();
+        let filter = range_filter(range, &mut params);
+        self.send(
+            None,
+            format!("ALTER TABLE events DELETE WHERE {}", filter).into_bytes(),
+            &params,
+            &[("mutations_sync", "1")],
+        )
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn count(&self, range: &EventRange) -> anyhow::Result<u64> {
+        let mut params = Params::default();
+        let sql = format!(
+            "SELECT count() AS count FROM events WHERE {} FORMAT JSONEachRow",
+            range_filter(range, &mut params)
+        );
+
+        let counts: Vec<CountRow> = self.select(sql, &params).await?;
+        Ok(counts.first().map_or(0, |row| row.count))
+    }
+
+    async fn scan(
+        &self,
+        range: &EventRange,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Event>> {
+        let mut params = Params::default();
+        let mut sql = format!(
+            "SELECT {} FROM events WHERE {}",
+            EVENT_COLUMNS,
+            range_filter(range, &mut params)
+        );
+        if let Some((time, id)) = after {
+            sql += &format!(
+                " AND (time, id) > ({}, {})",
+                params.bind_time(time),
+                params.bind("UUID", id)
+            );
+        }
+        sql += &format!(
+            " ORDER BY time, id LIMIT {} FORMAT JSONEachRow",
+            params.bind("UInt32", limit)
+        );
+
+        let rows: Vec<EventRow> = self.select(sql, &params).await?;
+        Ok(rows
+            .into_iter()
+            .map(Event::try_from)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        self.send(None, b"SELECT 1".to_vec(), &Params::default(), &[])
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::{Query, State},
+        http::StatusCode,
+        Router,
+    };
+    use chrono::TimeZone;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    type Requests = Arc<Mutex<Vec<(HashMap<String, String>, String)>>>;
+
+    /// ClickHouse stand-in answering every request with `status` and `body`
+    async fn stub(status: StatusCode, body: &'static str) -> (ClickHouseStore, Requests) {
+        let requests = Requests::default();
+        let app = Router::new()
+            .fallback(
+                move |State(requests): State<Requests>,
+                      Query(params): Query<HashMap<String, String>>,
+                      sent: String| async move {
+                    requests.lock().unwrap().push((params, sent));
+                    (status, body)
+                },
+            )
+            .with_state(requests.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let store =
+            ClickHouseStore::from_url(&format!("clickhouse://pm:s%40cret@{}/analytics", addr))
+                .unwrap();
+        (store, requests)
+    }
+
+    fn event() -> Event {
+        Event {
+            id: Uuid::nil(),
+            ..Event::fixture("web")
+                .event_type("click")
+                .properties(serde_json::json!({"button": "signup"}))
+                .user_id("alice")
+                .value(1.5)
+        }
+    }
+
+    #[test]
+    fn test_from_url() {
+        let store = ClickHouseStore::from_url("clickhouses://ch.example.com").unwrap();
+        assert_eq!(store.endpoint.as_str(), "https://ch.example.com:8443/");
+        assert_eq!(store.database, "default");
+        assert_eq!(store.user, "default");
+
+        let store =
+            ClickHouseStore::from_url("clickhouse://pm:s%40cret@ch:9000/analytics").unwrap();
+        assert_eq!(store.endpoint.as_str(), "http://ch:9000/");
+        assert_eq!(store.database, "analytics");
+        assert_eq!(store.password, "s@cret");
+    }
+
+    #[tokio::test]
+    async fn test_insert_sends_json_each_row() {
+        let (store, requests) = stub(StatusCode::OK, "").await;
+
+        store.insert(&[event(), event()]).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let (params, body) = &requests[0];
+        assert_eq!(params["query"], "INSERT INTO events FORMAT JSONEachRow");
+        assert_eq!(params["database"], "analytics");
+        let rows: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["properties"], r#"{"button":"signup"}"#);
+        assert_eq!(rows[0]["time"], "2024-05-01T12:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn test_query_binds_parameters_and_decodes_rows() {
+        let (store, requests) = stub(
+            StatusCode::OK,
+            concat!(
+                r#"{"id":"00000000-0000-0000-0000-000000000000","time":"2024-05-01T12:00:00.000000Z","#,
+                r#""project_id":"web","event_type":"click","properties":"{\"button\":\"signup\"}","#,
+                r#""user_id":"alice","session_id":null,"value":1.5}"#,
+                "\n"
+            ),
+        )
+        .await;
+
+        let events = store
+            .query(&EventQuery {
+                project_id: "web' OR 1".to_string(),
+                event_type: Some("click".to_string()),
+                user_id: None,
+                from: None,
+                to: None,
+                limit: 10,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].properties, event().properties);
+        assert_eq!(events[0].time, event().time);
+
+        let requests = requests.lock().unwrap();
+        let (params, sql) = &requests[0];
+        assert!(sql.contains("WHERE project_id = {p0:String} AND event_type = {p1:String}"));
+        assert!(sql.ends_with("LIMIT {p2:UInt32} FORMAT JSONEachRow"));
+        assert_eq!(params["param_p0"], "web' OR 1");
+        assert_eq!(params["param_p2"], "10");
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_decodes_buckets() {
+        let (store, _) = stub(
+            StatusCode::OK,
+            r#"{"bucket":"2024-05-01T12:00:00.000000Z","count":2,"unique_users":1,"sum":4,"avg":2}"#,
+        )
+        .await;
+
+        let buckets = store
+            .aggregate(&AggregateQuery {
+                project_id: "web".to_string(),
+                event_type: None,
+                from: Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+                to: Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap(),
+                interval: crate::models::Interval::Hour,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            buckets,
+            vec![AggregateBucket {
+                bucket: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+                count: 2,
+                unique_users: 1,
+                sum: Some(4.0),
+                avg: Some(2.0),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_reported() {
+        let (store, _) = stub(
+            StatusCode::NOT_FOUND,
+            "Code: 60. DB::Exception: Unknown table expression identifier 'events'",
+        )
+        .await;
+
+        let err = store.insert(&[event()]).await.unwrap_err();
+
+        assert!(err.to_string().contains("Code: 60"));
+    }
+}
@@ -0,0 +1,19 @@
+-- ClickHouse equivalent of the events hypertable in 001_initial_schema.sql
+-- Properties are stored as JSON text; the sorting key serves the
+-- (project_id, time) and (project_id, event_type, time) access paths
+CREATE TABLE IF NOT EXISTS events (
+    id UUID,
+    time DateTime64(6, 'UTC'),
+    project_id LowCardinality(String),
+    event_type LowCardinality(String),
+    properties Nullable(String),
+    user_id Nullable(String),
+    session_id Nullable(UUID),
+    value Nullable(Float64),
+    created_at DateTime64(6, 'UTC') DEFAULT now64(6),
+    INDEX idx_events_user_id user_id TYPE bloom_filter GRANULARITY 4,
+    INDEX idx_events_session_id session_id TYPE bloom_filter GRANULARITY 4
+)
+ENGINE = MergeTree
+PARTITION BY toYYYYMM(time)
+ORDER BY (project_id, event_type, time)
@@ -0,0 +1,268 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::RwLock,
+};
+use uuid::Uuid;
+
+use super::EventStore;
+use crate::models::{AggregateBucket, AggregateQuery, Event, EventQuery, EventRange};
+
+/// Events held in process memory
+///
+/// Follows the Postgres backend's semantics so handlers can be tested
+/// without a database.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    events: RwLock<Vec<Event>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored events
+    pub fn len(&self) -> usize {
+        self.events.read().expect("store lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn within(time: DateTime<Utc>, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+    from.is_none_or(|from| time >= from) && to.is_none_or(|to| time < to)
+}
+
+fn in_range(event: &Event, range: &EventRange) -> bool {
+    range
+        .project_id
+        .as_ref()
+        .is_none_or(|p| *p == event.project_id)
+        && range
+            .user_id
+            .as_ref()
+            .is_none_or(|u| event.user_id.as_ref() == Some(u))
+        && within(event.time, range.from, range.to)
+}
+
+#[derive(Default)]
+struct Accumulator<'a> {
+    count: i64,
+    users: HashSet<&'a str>,
+    values: Vec<f64>,
+}
+
+#[async_trait]
+impl EventStore for MemoryStore {
+    async fn insert(&self, batch: &[Event]) -> anyhow::Result<()> {
+        self.events
+            .write()
+            .expect("store lock poisoned")
+            .extend_from_slice(batch);
+        Ok(())
+    }
+
+    async fn query(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+        let events = self.events.read().expect("store lock poisoned");
+        let mut matching: Vec<Event> = events
+            .iter()
+            .filter(|e| e.project_id == query.project_id)
+            .filter(|e| query.event_type.as_ref().is_none_or(|t| *t == e.event_type))
+            .filter(|e| query.user_id.is_none() || e.user_id == query.user_id)
+            .filter(|e| within(e.time, query.from, query.to))
+            .cloned()
+            .collect();
+
+        matching.sort_by_key(|e| std::cmp::Reverse(e.time));
+        matching.truncate(query.limit as usize);
+
+        Ok(matching)
+    }
+
+    async fn aggregate(&self, query: &AggregateQuery) -> anyhow::Result<Vec<AggregateBucket>> {
+        let events = self.events.read().expect("store lock poisoned");
+        let mut buckets: BTreeMap<DateTime<Utc>, Accumulator> = BTreeMap::new();
+
+        let matching = events
+            .iter()
+            .filter(|e| e.project_id == query.project_id)
+            .filter(|e| query.event_type.as_ref().is_none_or(|t| *t == e.event_type))
+            .filter(|e| within(e.time, Some(query.from), Some(query.to)));
+        for event in matching {
+            let bucket = buckets
+                .entry(query.interval.truncate(event.time))
+                .or_default();
+            bucket.count += 1;
+            if let Some(user_id) = &event.user_id {
+                bucket.users.insert(user_id);
+            }
+            if let Some(value) = event.value {
+                bucket.values.push(value);
+            }
+        }
+
+        Ok(buckets
+            .into_iter()
+            .map(|(bucket, acc)| {
+                let sum = (!acc.values.is_empty()).then(|| acc.values.iter().sum::<f64>());
+                AggregateBucket {
+                    bucket,
+                    count: acc.count,
+                    unique_users: acc.users.len() as i64,
+                    sum,
+                    avg: sum.map(|sum| sum / acc.values.len() as f64),
+                }
+            })
+            .collect())
+    }
+
+    async fn delete(&self, range: &EventRange) -> anyhow::Result<u64> {
+        let mut events = self.events.write().expect("store lock poisoned");
+        let before = events.len();
+        events.retain(|e| !in_range(e, range));
+
+        Ok((before - events.len()) as u64)
+    }
+
+    async fn delete_batch(&self, range: &EventRange, limit: u64) -> anyhow::Result<u64> {
+        let mut events = self.events.write().expect("store lock poisoned");
+        let mut remaining = limit;
+        events.retain(|e| {
+            if remaining > 0 && in_range(e, range) {
+                remaining -= 1;
+                false
+            } else {
+                true
+            }
+        });
+
+        Ok(limit - remaining)
+    }
+
+    async fn count(&self, range: &EventRange) -> anyhow::Result<u64> {
+        let events = self.events.read().expect("store lock poisoned");
+        Ok(events.iter().filter(|e| in_range(e, range)).count() as u64)
+    }
+
+    async fn scan(
+        &self,
+        range: &EventRange,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Event>> {
+        let events = self.events.read().expect("store lock poisoned");
+        let mut matching: Vec<Event> = events
+            .iter()
+            .filter(|e| in_range(e, range))
+            .filter(|e| after.is_none_or(|cursor| (e.time, e.id) > cursor))
+            .cloned()
+            .collect();
+
+        matching.sort_by_key(|e| (e.time, e.id));
+        matching.truncate(limit as usize);
+
+        Ok(matching)
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Interval;
+    use chrono::{Duration, TimeZone};
+
+    async fn store() -> MemoryStore {
+        let store = MemoryStore::new();
+        store
+            .insert(&[
+                Event::fixture("web").user_id("alice").value(1.0),
+                Event::fixture("web")
+                    .later(Duration::minutes(10))
+                    .user_id("alice")
+                    .value(3.0),
+                Event::fixture("web")
+                    .later(Duration::minutes(70))
+                    .user_id("bob"),
+                Event::fixture("app").later(Duration::minutes(5)),
+            ])
+            .await
+            .unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn test_query_filters_and_orders_newest_first() {
+        let store = store().await;
+
+        let events = store
+            .query(&EventQuery {
+                project_id: "web".to_string(),
+                event_type: None,
+                user_id: Some("alice".to_string()),
+                from: None,
+                to: None,
+                limit: 1,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, Some(3.0));
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_buckets_by_interval() {
+        let store = store().await;
+
+        let buckets = store
+            .aggregate(&AggregateQuery {
+                project_id: "web".to_string(),
+                event_type: Some("page_view".to_string()),
+                from: Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+                to: Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap(),
+                interval: Interval::Hour,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(
+            buckets[0],
+            AggregateBucket {
+                bucket: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+                count: 2,
+                unique_users: 1,
+                sum: Some(4.0),
+                avg: Some(2.0),
+            }
+        );
+        assert_eq!(buckets[1].count, 1);
+        assert_eq!(buckets[1].sum, None);
+    }
+
+    #[tokio::test]
+    async fn test_delete_range() {
+        let store = store().await;
+
+        let deleted = store
+            .delete(&EventRange {
+                project_id: Some("web".to_string()),
+                user_id: None,
+                from: None,
+                to: Some(Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap()),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(deleted, 2);
+        assert_eq!(store.len(), 2);
+    }
+}
@@ -0,0 +1,140 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{sync::Arc, time::Instant};
+use uuid::Uuid;
+
+use super::EventStore;
+use crate::{
+    db::{
+        self, compression,
+        events::{self, MAX_ROWS_PER_INSERT},
+        replicas::ReadReplicas,
+    },
+    metrics::Metrics,
+    models::{AggregateBucket, AggregateQuery, Event, EventQuery, EventRange},
+};
+
+/// Events in the TimescaleDB `events` hypertable
+///
+/// Queries go to a usable read replica when there is one and are retried
+/// on the primary if the replica fails; writes always go to the primary.
+/// Late events landing in compressed chunks, and deletions reaching into
+/// them, succeed even where TimescaleDB refuses such writes, by
+/// decompressing the chunks first.
+pub struct PostgresStore {
+    pool: PgPool,
+    replicas: ReadReplicas,
+    metrics: Arc<Metrics>,
+}
+
+impl PostgresStore {
+    pub fn new(pool: PgPool, metrics: Arc<Metrics>) -> Self {
+        Self {
+            pool,
+            replicas: ReadReplicas::default(),
+            metrics,
+        }
+    }
+
+    /// Serve queries from `replicas` while they keep up
+    pub fn with_replicas(mut self, replicas: ReadReplicas) -> Self {
+        self.replicas = replicas;
+        self
+    }
+}
+
+#[async_trait]
+impl EventStore for PostgresStore {
+    async fn insert(&self, batch: &[Event]) -> anyhow::Result<()> {
+        for chunk in batch.chunks(MAX_ROWS_PER_INSERT) {
+            let start = Instant::now();
+            let mut conn = self.pool.acquire().await?;
+            self.metrics
+                .db_pool_acquire_duration
+                .observe(start.elapsed().as_secs_f64());
+
+            match events::insert(&mut *conn, chunk).await {
+                Err(e) if compression::is_compressed_chunk_error(&e) => {
+                    // Late events on a TimescaleDB that cannot write into
+                    // compressed chunks; the policy recompresses them later
+                    let mut times: Vec<DateTime<Utc>> = chunk.iter().map(|e| e.time).collect();
+                    times.sort_unstable();
+                    times.dedup();
+                    let decompressed = compression::decompress_at(&mut conn, &times).await?;
+                    self.metrics.chunks_decompressed.inc_by(decompressed as u64);
+                    tracing::warn!(decompressed, "Decompressed chunks for late events");
+
+                    events::insert(&mut *conn, chunk).await?;
+                }
+                result => result?,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn query(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+        if let Some(replica) = self.replicas.pick() {
+            match events::list(&replica.pool, query).await {
+                Ok(events) => return Ok(events),
+                Err(e) => {
+                    tracing::warn!(replica = %replica.name, "Replica query failed, using primary: {}", e);
+                    replica.mark_failed();
+                }
+            }
+        }
+
+        Ok(events::list(&self.pool, query).await?)
+    }
+
+    async fn aggregate(&self, query: &AggregateQuery) -> anyhow::Result<Vec<AggregateBucket>> {
+        if let Some(replica) = self.replicas.pick() {
+            match events::aggregate(&replica.pool, query).await {
+                Ok(buckets) => return Ok(buckets),
+                Err(e) => {
+                    tracing::warn!(replica = %replica.name, "Replica query failed, using primary: {}", e);
+                    replica.mark_failed();
+                }
+            }
+        }
+
+        Ok(events::aggregate(&self.pool, query).await?)
+    }
+
+    async fn delete(&self, range: &EventRange) -> anyhow::Result<u64> {
+        Ok(events::purge(&self.pool, range).await?)
+    }
+
+    async fn delete_batch(&self, range: &EventRange, limit: u64) -> anyhow::Result<u64> {
+        match events::purge_batch(&self.pool, range, limit).await {
+            Err(e) if compression::is_compressed_chunk_error(&e) => {
+                let mut conn = self.pool.acquire().await?;
+                let decompressed = compression::decompress_matching(&mut conn, range).await?;
+                self.metrics.chunks_decompressed.inc_by(decompressed as u64);
+                tracing::warn!(decompressed, "Decompressed chunks to delete events");
+
+                Ok(events::purge_batch(&mut *conn, range, limit).await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn count(&self, range: &EventRange) -> anyhow::Result<u64> {
+        Ok(events::count(&self.pool, range).await? as u64)
+    }
+
+    // Always on the primary, so a scan misses nothing a replica has yet to replay
+    async fn scan(
+        &self,
+        range: &EventRange,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Event>> {
+        Ok(events::scan(&self.pool, range, after, limit).await?)
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        db::health_check(&self.pool).await
+    }
+}
@@ -0,0 +1,402 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions},
+    QueryBuilder, Sqlite,
+};
+use std::{path::Path, str::FromStr};
+use uuid::Uuid;
+
+use super::EventStore;
+use crate::models::{AggregateBucket, AggregateQuery, Event, EventQuery, EventRange};
+
+/// Schema of the `events` table, equivalent to `001_initial_schema.sql`
+const SCHEMA: &str = include_str!("sqlite.sql");
+
+/// Rows per INSERT, keeping under SQLite's limit of 32766 bind parameters
+const MAX_ROWS_PER_INSERT: usize = 32766 / 8;
+
+/// Connections to the file; SQLite serializes writers regardless
+const MAX_CONNECTIONS: u32 = 4;
+
+/// Events in an embedded SQLite file, for single-node installs
+///
+/// Needs nothing but a writable data directory. Times are stored as
+/// microseconds since the Unix epoch so range filters and buckets are
+/// plain integer arithmetic.
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Open or create the database at `sqlite://<path>` and its schema
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)
+            .context("Invalid SQLite URL")?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+
+        if let Some(directory) = options.get_filename().parent() {
+            if directory != Path::new("") {
+                std::fs::create_dir_all(directory).with_context(|| {
+                    format!("Failed to create data directory {}", directory.display())
+                })?;
+            }
+        }
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect_with(options)
+            .await
+            .context("Failed to open SQLite database")?;
+        sqlx::raw_sql(SCHEMA)
+            .execute(&pool)
+            .await
+            .context("Failed to create SQLite schema")?;
+
+        Ok(Self { pool })
+    }
+}
+
+fn push_range(range: &EventRange, builder: &mut QueryBuilder<'_, Sqlite>) {
+    builder.push(" WHERE 1");
+    if let Some(project_id) = &range.project_id {
+        builder
+            .push(" AND project_id = ")
+            .push_bind(project_id.clone());
+    }
+    if let Some(user_id) = &range.user_id {
+        builder.push(" AND user_id = ").push_bind(user_id.clone());
+    }
+    if let Some(from) = range.from {
+        builder
+            .push(" AND time >= ")
+            .push_bind(from.timestamp_micros());
+    }
+    if let Some(to) = range.to {
+        builder
+            .push(" AND time < ")
+            .push_bind(to.timestamp_micros());
+    }
+}
+
+fn from_micros(micros: i64) -> anyhow::Result<DateTime<Utc>> {
+    DateTime::from_timestamp_micros(micros).context("Stored time is out of range")
+}
+
+/// An event as stored
+#[derive(Debug, sqlx::FromRow)]
+struct EventRow {
+    id: String,
+    time: i64,
+    project_id: String,
+    event_type: String,
+    properties: Option<String>,
+    user_id: Option<String>,
+    session_id: Option<String>,
+    value: Option<f64>,
+}
+
+impl TryFrom<EventRow> for Event {
+    type Error = anyhow::Error;
+
+    fn try_from(row: EventRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id.parse()?,
+            time: from_micros(row.time)?,
+            project_id: row.project_id,
+            event_type: row.event_type,
+            properties: row
+                .properties
+                .as_deref()
+                .map(serde_json::from_str)
+                .transpose()?,
+            user_id: row.user_id,
+            session_id: row.session_id.as_deref().map(Uuid::from_str).transpose()?,
+            value: row.value,
+        })
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct BucketRow {
+    bucket: i64,
+    count: i64,
+    unique_users: i64,
+    sum: Option<f64>,
+    avg: Option<f64>,
+}
+
+#[async_trait]
+impl EventStore for SqliteStore {
+    #[tracing::instrument(
+        name = "insert_events",
+        skip_all,
+        fields(db.system = "sqlite", db.operation = "INSERT", rows = batch.len())
+    )]
+    async fn insert(&self, batch: &[Event]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for chunk in batch.chunks(MAX_ROWS_PER_INSERT) {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO events (id, time, project_id, event_type, properties, user_id, session_id, value) ",
+            );
+            builder.push_values(chunk, |mut b, event| {
+                b.push_bind(event.id.to_string())
+                    .push_bind(event.time.timestamp_micros())
+                    .push_bind(&event.project_id)
+                    .push_bind(&event.event_type)
+                    .push_bind(event.properties.as_ref().map(|p| p.to_string()))
+                    .push_bind(&event.user_id)
+                    .push_bind(event.session_id.map(|s| s.to_string()))
+                    .push_bind(event.value);
+            });
+            builder.push(" ON CONFLICT (id, time) DO NOTHING");
+            builder.build().execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn query(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            "SELECT id, time, project_id, event_type, properties, user_id, session_id, value \
+             FROM events WHERE project_id = ",
+        );
+        builder.push_bind(&query.project_id);
+        if let Some(event_type) = &query.event_type {
+            builder.push(" AND event_type = ").push_bind(event_type);
+        }
+        if let Some(user_id) = &query.user_id {
+            builder.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(from) = query.from {
+            builder
+                .push(" AND time >= ")
+                .push_bind(from.timestamp_micros());
+        }
+        if let Some(to) = query.to {
+            builder
+                .push(" AND time < ")
+                .push_bind(to.timestamp_micros());
+        }
+        builder
+            .push(" ORDER BY time DESC LIMIT ")
+            .push_bind(i64::from(query.limit));
+
+        let rows: Vec<EventRow> = builder.build_query_as().fetch_all(&self.pool).await?;
+        rows.into_iter().map(Event::try_from).collect()
+    }
+
+    async fn aggregate(&self, query: &AggregateQuery) -> anyhow::Result<Vec<AggregateBucket>> {
+        let bucket_micros = query
+            .interval
+            .duration()
+            .num_microseconds()
+            .unwrap_or(i64::MAX);
+
+        let rows = sqlx::query_as::<_, BucketRow>(
+            "SELECT (time / ?1) * ?1 AS bucket, \
+                    COUNT(*) AS count, COUNT(DISTINCT user_id) AS unique_users, \
+                    SUM(value) AS sum, AVG(value) AS avg \
+             FROM events \
+             WHERE project_id = ?2 AND (?3 IS NULL OR event_type = ?3) \
+               AND time >= ?4 AND time < ?5 \
+             GROUP BY bucket \
+             ORDER BY bucket",
+        )
+        .bind(bucket_micros)
+        .bind(&query.project_id)
+        .bind(&query.event_type)
+        .bind(query.from.timestamp_micros())
+        .bind(query.to.timestamp_micros())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(AggregateBucket {
+                    bucket: from_micros(row.bucket)?,
+                    count: row.count,
+                    unique_users: row.unique_users,
+                    sum: row.sum,
+                    avg: row.avg,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete(&self, range: &EventRange) -> anyhow::Result<u64> {
+        let mut builder = QueryBuilder::<Sqlite>::new("DELETE FROM events");
+        push_range(range, &mut builder);
+
+        let result = builder.build().execute(&self.pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_batch(&self, range: &EventRange, limit: u64) -> anyhow::Result<u64> {
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            "DELETE FROM events WHERE rowid IN (SELECT rowid FROM events",
+        );
+        push_range(range, &mut builder);
+        builder
+            .push(" LIMIT ")
+            .push_bind(i64::try_from(limit).unwrap_or(i64::MAX))
+            .push(")");
+
+        let result = builder.build().execute(&self.pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn scan(
+        &self,
+        range: &EventRange,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Event>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            "SELECT id, time, project_id, event_type, properties, user_id, session_id, value \
+             FROM events",
+        );
+        push_range(range, &mut builder);
+        // Hyphenated lowercase ids sort as text in the same order as Uuid
+        if let Some((time, id)) = after {
+            builder
+                .push(" AND (time, id) > (")
+                .push_bind(time.timestamp_micros())
+                .push(", ")
+                .push_bind(id.to_string())
+                .push(")");
+        }
+        builder
+            .push(" ORDER BY time, id LIMIT ")
+            .push_bind(i64::from(limit));
+
+        let rows: Vec<EventRow> = builder.build_query_as().fetch_all(&self.pool).await?;
+        rows.into_iter().map(Event::try_from).collect()
+    }
+
+    async fn count(&self, range: &EventRange) -> anyhow::Result<u64> {
+        let mut builder = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM events");
+        push_range(range, &mut builder);
+
+        let count: i64 = builder.build_query_scalar().fetch_one(&self.pool).await?;
+
+        Ok(count as u64)
+    }
+
+    async fn health(&self) -> anyhow::Result<()> {
+        sqlx::query("SELECT 1").fetch_one(&self.pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Interval;
+    use chrono::{Duration, TimeZone};
+
+    fn database_url() -> (std::path::PathBuf, String) {
+        let directory = std::env::temp_dir().join(format!("pulsemetrics-{}", Uuid::new_v4()));
+        let url = format!("sqlite://{}/data/events.db", directory.display());
+        (directory, url)
+    }
+
+    #[tokio::test]
+    async fn test_events_persist_across_restarts() {
+        let (directory, url) = database_url();
+        let stored = Event {
+            session_id: Some(Uuid::new_v4()),
+            ..Event::fixture("web")
+                .properties(serde_json::json!({"path": "/pricing"}))
+                .user_id("alice")
+                .value(1.0)
+        };
+
+        let store = SqliteStore::connect(&url).await.unwrap();
+        store.insert(std::slice::from_ref(&stored)).await.unwrap();
+        store.pool.close().await;
+
+        let store = SqliteStore::connect(&url).await.unwrap();
+        let events = store
+            .query(&EventQuery {
+                project_id: "web".to_string(),
+                event_type: None,
+                user_id: None,
+                from: None,
+                to: None,
+                limit: 10,
+            })
+            .await
+            .unwrap();
+        store.pool.close().await;
+        std::fs::remove_dir_all(&directory).ok();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, stored.id);
+        assert_eq!(events[0].time, stored.time);
+        assert_eq!(events[0].properties, stored.properties);
+        assert_eq!(events[0].session_id, stored.session_id);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_and_delete() {
+        let (directory, url) = database_url();
+        let store = SqliteStore::connect(&url).await.unwrap();
+        store
+            .insert(&[
+                Event::fixture("web").user_id("alice").value(1.0),
+                Event::fixture("web")
+                    .later(Duration::minutes(10))
+                    .user_id("alice")
+                    .value(3.0),
+                Event::fixture("web")
+                    .later(Duration::minutes(70))
+                    .user_id("bob"),
+                Event::fixture("app").later(Duration::minutes(5)),
+            ])
+            .await
+            .unwrap();
+
+        let buckets = store
+            .aggregate(&AggregateQuery {
+                project_id: "web".to_string(),
+                event_type: Some("page_view".to_string()),
+                from: Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap(),
+                to: Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap(),
+                interval: Interval::Hour,
+            })
+            .await
+            .unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(
+            buckets[0],
+            AggregateBucket {
+                bucket: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+                count: 2,
+                unique_users: 1,
+                sum: Some(4.0),
+                avg: Some(2.0),
+            }
+        );
+        assert_eq!(buckets[1].sum, None);
+
+        let deleted = store
+            .delete(&EventRange {
+                project_id: Some("web".to_string()),
+                user_id: None,
+                from: None,
+                to: Some(Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap()),
+            })
+            .await
+            .unwrap();
+        store.pool.close().await;
+        std::fs::remove_dir_all(&directory).ok();
+
+        assert_eq!(deleted, 2);
+    }
+}
@@ -0,0 +1,24 @@
+-- SQLite equivalent of the events hypertable in 001_initial_schema.sql
+-- Times are microseconds since the Unix epoch, properties are JSON text
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    project_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    properties TEXT,
+    user_id TEXT,
+    session_id TEXT,
+    value REAL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now') * 1000000),
+    PRIMARY KEY (id, time)
+);
+
+CREATE INDEX IF NOT EXISTS idx_events_project_time
+    ON events (project_id, time DESC);
+
+CREATE INDEX IF NOT EXISTS idx_events_project_type_time
+    ON events (project_id, event_type, time DESC);
+
+CREATE INDEX IF NOT EXISTS idx_events_user_time
+    ON events (user_id, time DESC)
+    WHERE user_id IS NOT NULL;
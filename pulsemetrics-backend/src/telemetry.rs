@@ -0,0 +1,332 @@
+use anyhow::Context;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::Response,
+};
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use std::time::Duration;
+use tracing::{Span, Subscriber};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::{
+    config::{LogConfig, LogFormat, LogRotation, OtelConfig},
+    middleware::request_id::REQUEST_ID_HEADER,
+    LogFilterHandle,
+};
+
+/// Path of the trace endpoint below the collector's base URL
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+/// Installed tracing pipeline, which must be kept alive for the process lifetime
+pub struct Telemetry {
+    pub log_filter: LogFilterHandle,
+    tracer_provider: Option<SdkTracerProvider>,
+    /// Flushes buffered file output on drop
+    _file_guard: Option<WorkerGuard>,
+}
+
+impl Telemetry {
+    /// Export spans still queued for the collector
+    pub fn shutdown(&self) {
+        if let Some(provider) = &self.tracer_provider {
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!("Failed to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+/// Install the global tracing subscriber described by `log` and `otel`
+///
+/// JSON output flattens event fields into the top-level object next to
+/// `timestamp`, `level`, `target` and `message`; the fields of enclosing
+/// spans (such as `request_id`) are listed under `spans`.
+pub fn init(log: &LogConfig, otel: &OtelConfig) -> anyhow::Result<Telemetry> {
+    let (filter, log_filter) = reload::Layer::new(EnvFilter::try_new(&log.filter)?);
+
+    let (writer, file_guard, ansi) = match &log.directory {
+        Some(directory) => {
+            let appender = rolling::RollingFileAppender::builder()
+                .rotation(rotation(log.rotation))
+                .filename_prefix(&log.file_prefix)
+                .build(directory)
+                .with_context(|| format!("Failed to open log directory {}", directory))?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (BoxMakeWriter::new(writer), Some(guard), false)
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), None, true),
+    };
+
+    let output = output_layer(log.format, writer, ansi);
+
+    let tracer_provider = otel.endpoint.as_deref().map(|endpoint| {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        otlp_provider(endpoint, &otel.service_name)
+    });
+    let tracer_provider = tracer_provider.transpose()?;
+    let otel_layer = tracer_provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("pulsemetrics-backend"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(otel_layer)
+        .try_init()
+        .context("Failed to install tracing subscriber")?;
+
+    if let Some(endpoint) = &otel.endpoint {
+        tracing::info!("Exporting traces to {}", endpoint);
+    }
+
+    Ok(Telemetry {
+        log_filter,
+        tracer_provider,
+        _file_guard: file_guard,
+    })
+}
+
+/// Layer writing events to `writer` in `format`
+///
+/// JSON lists every enclosing span: `request_id` lives on the outermost.
+fn output_layer<S>(
+    format: LogFormat,
+    writer: BoxMakeWriter,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let output = fmt::layer()
+        .with_target(true)
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Full => output.boxed(),
+        LogFormat::Compact => output.compact().boxed(),
+        LogFormat::Pretty => output.pretty().boxed(),
+        LogFormat::Json => output
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+/// Tracer provider batching spans to the OTLP/HTTP collector at `endpoint`
+pub fn otlp_provider(endpoint: &str, service_name: &str) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!(
+            "{}{}",
+            endpoint.trim_end_matches('/'),
+            OTLP_TRACES_PATH
+        ))
+        .with_timeout(Duration::from_secs(10))
+        .build()
+        .context("Failed to create OTLP exporter")?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build())
+}
+
+/// Span for an incoming HTTP request
+///
+/// Continues the trace of a W3C `traceparent` header when the client sent
+/// one, and carries the request id so every log inside it can be correlated.
+pub fn make_request_span(req: &Request) -> Span {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_else(|| req.uri().path());
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("-");
+
+    let span = tracing::info_span!(
+        "request",
+        otel.name = %format!("{} {}", req.method(), route),
+        otel.kind = "server",
+        http.request.method = %req.method(),
+        http.route = %route,
+        url.path = %req.uri().path(),
+        request_id = %request_id,
+        http.response.status_code = tracing::field::Empty,
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let _ = span.set_parent(parent);
+
+    span
+}
+
+/// Record the response status on the request span
+pub fn record_response<B>(response: &Response<B>, _latency: Duration, span: &Span) {
+    span.record("http.response.status_code", response.status().as_u16());
+}
+
+fn rotation(rotation: LogRotation) -> rolling::Rotation {
+    match rotation {
+        LogRotation::Minutely => rolling::Rotation::MINUTELY,
+        LogRotation::Hourly => rolling::Rotation::HOURLY,
+        LogRotation::Daily => rolling::Rotation::DAILY,
+        LogRotation::Never => rolling::Rotation::NEVER,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceContextExt, Tracer};
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Minimal collector stand-in, returning the request line of the first export
+    async fn collector() -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let n = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            request.lines().next().unwrap_or_default().to_string()
+        });
+
+        (endpoint, handle)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_spans_are_exported_to_collector() {
+        let (endpoint, received) = collector().await;
+        let provider = otlp_provider(&endpoint, "pulsemetrics-test").unwrap();
+
+        provider.tracer("test").in_span("ingest", |cx| {
+            assert!(cx.span().span_context().is_valid());
+        });
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(received.await.unwrap(), "POST /v1/traces HTTP/1.1");
+    }
+
+    /// Log output captured in memory
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_logs_carry_the_request_id() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry().with(output_layer(
+            LogFormat::Json,
+            BoxMakeWriter::new(move || writer.clone()),
+            false,
+        ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request", request_id = "client-123");
+            let _request = request.enter();
+            let validate = tracing::info_span!("validate_batch", events = 3);
+            let _validate = validate.enter();
+            tracing::info!(project_id = "web", "Rejected batch");
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["message"], "Rejected batch");
+        assert_eq!(line["project_id"], "web");
+        assert_eq!(line["spans"][0]["name"], "request");
+        assert_eq!(line["spans"][0]["request_id"], "client-123");
+        assert_eq!(line["spans"][1]["name"], "validate_batch");
+    }
+
+    #[tokio::test]
+    async fn test_auth_span_ends_before_the_handler() {
+        use axum::{body::Body, http::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::registry().with(output_layer(
+            LogFormat::Json,
+            BoxMakeWriter::new(move || writer.clone()),
+            false,
+        ));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let config = crate::config::Config::load_from(None, |var| {
+            (var == "DATABASE_URL").then(|| "memory://".to_string())
+        })
+        .unwrap();
+        let state = crate::AppState::new(None, config);
+        let app = Router::new()
+            .route("/", get(|| async { tracing::info!("Handled") }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::middleware::auth,
+            ))
+            .with_state(state);
+        let request = Request::get("/")
+            .header(
+                "authorization",
+                format!("Bearer {}", crate::config::DEFAULT_API_KEY),
+            )
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["message"], "Handled");
+        assert!(line.get("spans").is_none(), "{}", line);
+    }
+}